@@ -1,4 +1,9 @@
-use core::str::FromStr;
+use crate::{CmdLine, CMDLINE_MAX};
+use arrayvec::ArrayVec;
+use core::fmt;
+
+/// Maximum number of boot entries
+pub const MAX_ENTRIES: usize = 8;
 
 /// Config for the bootloader
 #[derive(Debug)]
@@ -19,6 +24,34 @@ pub struct Config<'a> {
     pub cmdline: &'a str,
     /// Load apps into memory, when no fs(file system) implemented in kernel
     pub load_apps: bool,
+    /// The path of the initramfs (cpio newc or tar) unpacked by the kernel, empty means none
+    pub initramfs: &'a str,
+    /// Randomize the kernel base, kernel stack and physical memory offset
+    pub kaslr: bool,
+    /// The screen resolution to set, None means keep the current mode
+    pub resolution: Option<(usize, usize)>,
+    /// Boot entries defined by `[name]` sections
+    pub entries: ArrayVec<BootEntry<'a>, MAX_ENTRIES>,
+    /// The name of the entry to boot by default, empty means the first one
+    pub default_entry: &'a str,
+    /// Seconds to wait in the boot menu before booting the default entry, 0 means no menu
+    pub timeout: u64,
+}
+
+/// A named boot entry, keys not set in the section are inherited
+/// from the global ones defined before it
+#[derive(Debug, Clone, Copy)]
+pub struct BootEntry<'a> {
+    /// The name of the entry, shown in the boot menu
+    pub name: &'a str,
+    /// The path of kernel ELF
+    pub kernel_path: &'a str,
+    /// Kernel command line
+    pub cmdline: &'a str,
+    /// Load apps into memory
+    pub load_apps: bool,
+    /// The path of the initramfs
+    pub initramfs: &'a str,
 }
 
 const DEFAULT_CONFIG: Config = Config {
@@ -29,49 +62,495 @@ const DEFAULT_CONFIG: Config = Config {
     kernel_path: "\\KERNEL.ELF", // 根目录
     cmdline: "",
     load_apps: false,
+    initramfs: "",
+    kaslr: false,
+    resolution: None,
+    entries: ArrayVec::new_const(),
+    default_entry: "",
+    timeout: 0,
 };
 
 impl<'a> Config<'a> {
     // 'content: &'a [u8]'引用类型的切片，元素类型为u8
     // 数组是固定大小的，切片是动态分配的
     // 'content'储存文件的二进制内容
-    pub fn parse(content: &'a [u8]) -> Self {
+    pub fn parse(content: &'a [u8]) -> Result<Self, ConfigError<'a>> {
         // 将文件转换成utf8编码的字符串
-        let content = core::str::from_utf8(content).expect("failed to parse config as utf8");
+        let content = core::str::from_utf8(content)
+            .map_err(|_| ConfigError::new(0, "", "", ConfigErrorKind::InvalidUtf8))?;
         let mut config = DEFAULT_CONFIG;
-        for line in content.lines() {
+        let mut default_line = 0;
+        for (idx, line) in content.lines().enumerate() {
             let line = line.trim();
             // skip empty and comment
             if line.is_empty() || line.starts_with('#') {
                 continue;
             }
+            // parse '[name]' as the start of a boot entry
+            if let Some(section) = line.strip_prefix('[') {
+                let name = section
+                    .strip_suffix(']')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .ok_or(ConfigError::new(idx + 1, line, "", ConfigErrorKind::InvalidEntry))?;
+                config
+                    .add_entry(name)
+                    .map_err(|kind| ConfigError::new(idx + 1, line, "", kind))?;
+                continue;
+            }
             // parse 'key=value'
-            if let Some((key, value)) = line.split_once('=') {
-                config.process(key, value);
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(ConfigError::new(idx + 1, line, "", ConfigErrorKind::MissingValue))?;
+            let (key, value) = (key.trim(), value.trim());
+            if key == "default" {
+                default_line = idx + 1;
+            }
+            config
+                .process(key, value)
+                .map_err(|kind| ConfigError::new(idx + 1, key, value, kind))?;
+        }
+
+        // without sections, the global keys make up the only entry
+        if config.entries.is_empty() {
+            config.add_entry("default").unwrap();
+        }
+
+        if !config.default_entry.is_empty() && config.find_entry(config.default_entry).is_none() {
+            return Err(ConfigError::new(
+                default_line,
+                "default",
+                config.default_entry,
+                ConfigErrorKind::UnknownEntry,
+            ));
+        }
+
+        Ok(config)
+    }
+
+    /// Index of the entry to boot by default
+    pub fn default_index(&self) -> usize {
+        self.find_entry(self.default_entry).unwrap_or(0)
+    }
+
+    /// Use the entry at `idx` as the kernel to boot
+    pub fn select(&mut self, idx: usize) {
+        let entry = self.entries[idx];
+        self.kernel_path = entry.kernel_path;
+        self.cmdline = entry.cmdline;
+        self.load_apps = entry.load_apps;
+        self.initramfs = entry.initramfs;
+    }
+
+    /// The command line passed to the kernel in `BootInfo`
+    pub fn kernel_cmdline(&self) -> CmdLine {
+        // the length is checked when parsing
+        CmdLine::from(self.cmdline).unwrap()
+    }
+
+    /// Move the physical memory mapping up by `slide` bytes,
+    /// unless its start would not be canonical, returns if it moved
+    pub fn slide_physical_offset(&mut self, slide: u64) -> bool {
+        match self.physical_memory_offset.checked_add(slide) {
+            Some(offset) if is_canonical(offset) => {
+                self.physical_memory_offset = offset;
+                true
             }
+            _ => false,
         }
-        config
     }
 
-    fn process(&mut self, key: &str, value: &'a str) {
-        info!("parse {} = {}", key, value);// 日志宏
-        let r10 = u64::from_str(value).unwrap_or(0); // 转换成十进制字符串
-        let r16 = if value.len() > 2 { // 将value转换成十六进制字符串
-            u64::from_str_radix(&value[2..], 16).unwrap_or(0) // 从二开始是因为不需要0x
-        } else {
-            0
+    fn find_entry(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|e| e.name == name)
+    }
+
+    fn add_entry(&mut self, name: &'a str) -> Result<(), ConfigErrorKind> {
+        if self.find_entry(name).is_some() {
+            return Err(ConfigErrorKind::InvalidEntry);
+        }
+        let entry = BootEntry {
+            name,
+            kernel_path: self.kernel_path,
+            cmdline: self.cmdline,
+            load_apps: self.load_apps,
+            initramfs: self.initramfs,
         };
+        self.entries
+            .try_push(entry)
+            .map_err(|_| ConfigErrorKind::TooManyEntries)
+    }
+
+    fn process(&mut self, key: &str, value: &'a str) -> Result<(), ConfigErrorKind> {
+        info!("parse {} = {}", key, value);// 日志宏
+        // keys inside a section belong to that entry
+        if let Some(entry) = self.entries.last_mut() {
+            match key {
+                "kernel_path" => entry.kernel_path = value,
+                "cmdline" => entry.cmdline = parse_cmdline(value)?,
+                "load_apps" => entry.load_apps = parse_bool(value)?,
+                "initramfs" => entry.initramfs = value,
+                _ => return Err(ConfigErrorKind::UnknownKey),
+            }
+            return Ok(());
+        }
         match key {
-            "kernel_stack_address" => self.kernel_stack_address = r16,
-            "kernel_stack_size" => self.kernel_stack_size = r10,
+            "kernel_stack_address" => {
+                self.kernel_stack_address = parse_address(value, PAGE_SIZE)?
+            }
+            "kernel_stack_size" => self.kernel_stack_size = parse_pages(value)?,
             "physical_memory_offset" => {
-                self.physical_memory_offset = r16;
+                // physical memory is mapped with 2MiB pages
+                self.physical_memory_offset = parse_address(value, HUGE_PAGE_SIZE)?
             }
             "kernel_path" => self.kernel_path = value,
-            "kernel_stack_auto_grow" => self.kernel_stack_auto_grow = r10,
-            "cmdline" => self.cmdline = value,
-            "load_apps" => self.load_apps = r10 != 0,
-            _ => warn!("undefined config key: {}", key),
+            "kernel_stack_auto_grow" => self.kernel_stack_auto_grow = parse_pages(value)?,
+            "cmdline" => self.cmdline = parse_cmdline(value)?,
+            "load_apps" => self.load_apps = parse_bool(value)?,
+            "initramfs" => self.initramfs = value,
+            "kaslr" => self.kaslr = parse_bool(value)?,
+            "resolution" => self.resolution = Some(parse_resolution(value)?),
+            "default" => self.default_entry = value,
+            "timeout" => self.timeout = parse_number(value)?.0,
+            _ => return Err(ConfigErrorKind::UnknownKey),
+        }
+        Ok(())
+    }
+}
+
+const PAGE_SIZE: u64 = 0x1000;
+const HUGE_PAGE_SIZE: u64 = 0x20_0000;
+
+/// Kind of errors in config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigErrorKind {
+    /// The config file is not valid UTF-8
+    InvalidUtf8,
+    /// The line is not in `key=value` form
+    MissingValue,
+    /// The key is not defined
+    UnknownKey,
+    /// The value is not a valid number
+    InvalidNumber,
+    /// The value does not fit in u64
+    Overflow,
+    /// The value is not `true` or `false`
+    InvalidBool,
+    /// The address or size is not aligned as required
+    Misaligned,
+    /// The address is not canonical
+    NonCanonical,
+    /// The section header is malformed or the entry is defined twice
+    InvalidEntry,
+    /// Too many boot entries are defined
+    TooManyEntries,
+    /// The default entry is not defined
+    UnknownEntry,
+    /// The resolution is not in `WIDTHxHEIGHT` form
+    InvalidResolution,
+    /// The value is longer than the kernel accepts
+    TooLong,
+}
+
+/// Error reported when parsing config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfigError<'a> {
+    /// The line number, starts from 1, 0 for the whole file
+    pub line: usize,
+    pub key: &'a str,
+    pub value: &'a str,
+    pub kind: ConfigErrorKind,
+}
+
+impl<'a> ConfigError<'a> {
+    fn new(line: usize, key: &'a str, value: &'a str, kind: ConfigErrorKind) -> Self {
+        Self {
+            line,
+            key,
+            value,
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for ConfigErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            Self::InvalidUtf8 => "config is not valid utf8",
+            Self::MissingValue => "expected `key=value`",
+            Self::UnknownKey => "undefined config key",
+            Self::InvalidNumber => "invalid number",
+            Self::Overflow => "number too large",
+            Self::InvalidBool => "expected `true` or `false`",
+            Self::Misaligned => "value is not aligned",
+            Self::NonCanonical => "address is not canonical",
+            Self::InvalidEntry => "invalid or duplicated boot entry",
+            Self::TooManyEntries => "too many boot entries",
+            Self::UnknownEntry => "undefined boot entry",
+            Self::InvalidResolution => "expected `WIDTHxHEIGHT`",
+            Self::TooLong => "value too long",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl fmt::Display for ConfigError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)?;
+        match self.kind {
+            ConfigErrorKind::InvalidUtf8 => {}
+            ConfigErrorKind::MissingValue
+            | ConfigErrorKind::InvalidEntry
+            | ConfigErrorKind::TooManyEntries => write!(f, " in `{}`", self.key)?,
+            _ => write!(f, " in `{}={}`", self.key, self.value)?,
+        }
+        Ok(())
+    }
+}
+
+/// Parse a number in decimal or hex (`0x`), with optional size suffix (K/M/G)
+///
+/// returns the number and whether a size suffix is used
+fn parse_number(value: &str) -> Result<(u64, bool), ConfigErrorKind> {
+    let value = value.trim();
+    let (digits, shift) = match value.as_bytes().last() {
+        Some(b'K' | b'k') => (&value[..value.len() - 1], Some(10)),
+        Some(b'M' | b'm') => (&value[..value.len() - 1], Some(20)),
+        Some(b'G' | b'g') => (&value[..value.len() - 1], Some(30)),
+        _ => (value, None),
+    };
+
+    let number = match digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        Some(hex) => parse_digits(hex, 16)?,
+        None => parse_digits(digits, 10)?,
+    };
+
+    match shift {
+        Some(shift) => number
+            .checked_mul(1 << shift)
+            .map(|n| (n, true))
+            .ok_or(ConfigErrorKind::Overflow),
+        None => Ok((number, false)),
+    }
+}
+
+/// Parse digits in the given radix, `_` can be used as separator
+fn parse_digits(digits: &str, radix: u32) -> Result<u64, ConfigErrorKind> {
+    if digits.is_empty() || digits.starts_with('_') {
+        return Err(ConfigErrorKind::InvalidNumber);
+    }
+
+    let mut number: u64 = 0;
+    for ch in digits.chars().filter(|&c| c != '_') {
+        let digit = ch.to_digit(radix).ok_or(ConfigErrorKind::InvalidNumber)?;
+        number = number
+            .checked_mul(radix as u64)
+            .and_then(|n| n.checked_add(digit as u64))
+            .ok_or(ConfigErrorKind::Overflow)?;
+    }
+    Ok(number)
+}
+
+/// Parse a number of 4KiB pages, sizes with suffix are converted to pages
+fn parse_pages(value: &str) -> Result<u64, ConfigErrorKind> {
+    match parse_number(value)? {
+        (size, true) if size % PAGE_SIZE != 0 => Err(ConfigErrorKind::Misaligned),
+        (size, true) => Ok(size / PAGE_SIZE),
+        (pages, false) => Ok(pages),
+    }
+}
+
+/// If bits 48..64 of `addr` are copies of bit 47
+pub fn is_canonical(addr: u64) -> bool {
+    ((addr << 16) as i64 >> 16) as u64 == addr
+}
+
+/// Parse a canonical virtual address aligned to `align`
+fn parse_address(value: &str, align: u64) -> Result<u64, ConfigErrorKind> {
+    let (addr, _) = parse_number(value)?;
+
+    if addr % align != 0 {
+        return Err(ConfigErrorKind::Misaligned);
+    }
+
+    if !is_canonical(addr) {
+        return Err(ConfigErrorKind::NonCanonical);
+    }
+
+    Ok(addr)
+}
+
+/// Parse a resolution in `WIDTHxHEIGHT` form
+fn parse_resolution(value: &str) -> Result<(usize, usize), ConfigErrorKind> {
+    let (width, height) = value
+        .split_once(['x', 'X'])
+        .ok_or(ConfigErrorKind::InvalidResolution)?;
+    let width = parse_digits(width.trim(), 10)?;
+    let height = parse_digits(height.trim(), 10)?;
+    if width == 0 || height == 0 {
+        return Err(ConfigErrorKind::InvalidResolution);
+    }
+    Ok((width as usize, height as usize))
+}
+
+/// Check that a command line fits in `BootInfo`
+fn parse_cmdline(value: &str) -> Result<&str, ConfigErrorKind> {
+    if value.len() > CMDLINE_MAX {
+        return Err(ConfigErrorKind::TooLong);
+    }
+    Ok(value)
+}
+
+/// Parse a boolean, `1`/`0` are accepted for compatibility
+fn parse_bool(value: &str) -> Result<bool, ConfigErrorKind> {
+    match value {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(ConfigErrorKind::InvalidBool),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_err(content: &str) -> ConfigError<'_> {
+        Config::parse(content.as_bytes()).unwrap_err()
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number("4096"), Ok((4096, false)));
+        assert_eq!(parse_number("0x1000"), Ok((0x1000, false)));
+        assert_eq!(parse_number("0XfFfF_0000"), Ok((0xffff_0000, false)));
+        assert_eq!(parse_number("1_000"), Ok((1000, false)));
+        assert_eq!(parse_number("4K"), Ok((4096, true)));
+        assert_eq!(parse_number("0x2m"), Ok((0x20_0000, true)));
+        assert_eq!(parse_number("1G"), Ok((1 << 30, true)));
+
+        assert_eq!(parse_number("18446744073709551615"), Ok((u64::MAX, false)));
+        assert_eq!(parse_number("0xffff_ffff_ffff_ffff"), Ok((u64::MAX, false)));
+        assert_eq!(parse_number("18446744073709551616"), Err(ConfigErrorKind::Overflow));
+        assert_eq!(parse_number("0x1_0000_0000_0000_0000"), Err(ConfigErrorKind::Overflow));
+        assert_eq!(parse_number("0x4000_0000_0000_0000K"), Err(ConfigErrorKind::Overflow));
+
+        for invalid in ["", "0x", "K", "_1", "0x_1", "12a", "0xg", "-1", "1 2"] {
+            assert_eq!(parse_number(invalid), Err(ConfigErrorKind::InvalidNumber), "{}", invalid);
         }
     }
+
+    #[test]
+    fn pages() {
+        assert_eq!(parse_pages("512"), Ok(512));
+        assert_eq!(parse_pages("2M"), Ok(512));
+        assert_eq!(parse_pages("6K"), Err(ConfigErrorKind::Misaligned));
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(parse_address("0xFFFFFF0100000000", PAGE_SIZE), Ok(0xFFFF_FF01_0000_0000));
+        assert_eq!(parse_address("0x7fff_ffff_f000", PAGE_SIZE), Ok(0x7fff_ffff_f000));
+        assert_eq!(parse_address("4096", PAGE_SIZE), Ok(0x1000));
+        assert_eq!(parse_address("0x1000", HUGE_PAGE_SIZE), Err(ConfigErrorKind::Misaligned));
+        assert_eq!(parse_address("0x8000_0000_0000", PAGE_SIZE), Err(ConfigErrorKind::NonCanonical));
+        assert_eq!(parse_address("0xFFFF_7FFF_FFFF_F000", PAGE_SIZE), Err(ConfigErrorKind::NonCanonical));
+        assert_eq!(parse_address("0x1_0000_0000_0000_0000", PAGE_SIZE), Err(ConfigErrorKind::Overflow));
+    }
+
+    #[test]
+    fn physical_offset_slide() {
+        let mut config = Config::parse(b"physical_memory_offset=0xFFFF_8000_0000_0000\n").unwrap();
+        assert!(config.slide_physical_offset(0x1000_0000_0000));
+        assert_eq!(config.physical_memory_offset, 0xFFFF_9000_0000_0000);
+        assert!(!config.slide_physical_offset(0x7000_0000_0000_0000));
+        assert_eq!(config.physical_memory_offset, 0xFFFF_9000_0000_0000);
+
+        // from the top of the lower half, or past the end of the address space
+        let mut config = Config::parse(b"physical_memory_offset=0x7FC0_0000_0000\n").unwrap();
+        assert!(!config.slide_physical_offset(0x40_0000_0000));
+        assert!(config.slide_physical_offset(0x3F_C000_0000));
+        assert_eq!(config.physical_memory_offset, 0x7FFF_C000_0000);
+        config.physical_memory_offset = 0xFFFF_FFFF_C000_0000;
+        assert!(!config.slide_physical_offset(0x4000_0000));
+    }
+
+    #[test]
+    fn defaults_and_values() {
+        let config = Config::parse(include_bytes!("../../kernel/config/boot.conf")).unwrap();
+        assert_eq!(config.kernel_stack_address, 0xFFFF_FF01_0000_0000);
+        assert_eq!(config.physical_memory_offset, 0xFFFF_8000_0000_0000);
+        assert_eq!(config.kernel_path, "\\KERNEL.ELF");
+        assert_eq!(config.entries.len(), 1);
+
+        let config = Config::parse(b"kernel_stack_size = 1M\nkaslr=true\nresolution=800x600\n").unwrap();
+        assert_eq!(config.kernel_stack_size, 256);
+        assert!(config.kaslr);
+        assert_eq!(config.resolution, Some((800, 600)));
+    }
+
+    #[test]
+    fn entries() {
+        let content = "cmdline=quiet\n[debug]\ncmdline=debug\n[release]\n\ndefault=release\n";
+        assert_eq!(parse_err(content).kind, ConfigErrorKind::UnknownKey);
+
+        let content = "cmdline=quiet\ndefault=release\n[debug]\ncmdline=debug\n[release]\n";
+        let mut config = Config::parse(content.as_bytes()).unwrap();
+        assert_eq!(config.default_index(), 1);
+        config.select(0);
+        assert_eq!(config.cmdline, "debug");
+        config.select(1);
+        assert_eq!(config.cmdline, "quiet");
+    }
+
+    #[test]
+    fn selected_entry() {
+        let content = "kernel_path=\\KERNEL.ELF\ncmdline=quiet\n\
+            [debug]\ncmdline=log=trace smp=1\nload_apps=true\n\
+            [release]\n";
+        let mut config = Config::parse(content.as_bytes()).unwrap();
+
+        // what the bootloader passes to the kernel follows the selected entry
+        config.select(0);
+        assert_eq!(config.kernel_cmdline().as_str(), "log=trace smp=1");
+        assert!(config.load_apps);
+        config.select(1);
+        assert_eq!(config.kernel_cmdline().as_str(), "quiet");
+        assert!(!config.load_apps);
+        assert_eq!(config.kernel_path, "\\KERNEL.ELF");
+
+        let long = "cmdline=".to_string() + &"x".repeat(CMDLINE_MAX + 1);
+        assert_eq!(parse_err(&long).kind, ConfigErrorKind::TooLong);
+        let long = "[a]\n".to_string() + &long;
+        assert_eq!(parse_err(&long).line, 2);
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let err = parse_err("# comment\n\nkernel_path=\\KERNEL.ELF\nkernel_stak_size=512\n");
+        assert_eq!(err, ConfigError::new(4, "kernel_stak_size", "512", ConfigErrorKind::UnknownKey));
+
+        // global only keys are not accepted in a section
+        let err = parse_err("[debug]\nkaslr=1\n");
+        assert_eq!(err, ConfigError::new(2, "kaslr", "1", ConfigErrorKind::UnknownKey));
+    }
+
+    #[test]
+    fn error_lines() {
+        let err = parse_err("kaslr=0\n# comment\n   \nkernel_stack_size=0x\n");
+        assert_eq!(err, ConfigError::new(4, "kernel_stack_size", "0x", ConfigErrorKind::InvalidNumber));
+        assert_eq!(err.to_string(), "line 4: invalid number in `kernel_stack_size=0x`");
+
+        let err = parse_err("kaslr=0\r\nkernel_path\r\n");
+        assert_eq!(err, ConfigError::new(2, "kernel_path", "", ConfigErrorKind::MissingValue));
+        assert_eq!(err.to_string(), "line 2: expected `key=value` in `kernel_path`");
+
+        let err = parse_err("[a]\n[a]\n");
+        assert_eq!((err.line, err.kind), (2, ConfigErrorKind::InvalidEntry));
+
+        let err = parse_err("default=missing\n\n[a]\n");
+        assert_eq!((err.line, err.kind), (1, ConfigErrorKind::UnknownEntry));
+
+        let err = Config::parse(b"kaslr=\xff\n").unwrap_err();
+        assert_eq!((err.line, err.kind), (0, ConfigErrorKind::InvalidUtf8));
+        assert_eq!(err.to_string(), "line 0: config is not valid utf8");
+    }
 }
@@ -1,3 +1,6 @@
+use crate::{App, AppList};
+use arrayvec::ArrayString;
+use core::fmt::Write;
 use uefi::proto::media::file::*;
 use uefi::proto::media::fs::SimpleFileSystem;
 use uefi::table::boot::*;
@@ -20,17 +23,22 @@ pub fn open_root(bs: &BootServices) -> Directory {
 
 /// Open file at `path`
 pub fn open_file(bs: &BootServices, path: &str) -> RegularFile {
+    try_open_file(bs, path).unwrap_or_else(|| panic!("Failed to open file {}", path))
+}
+
+/// Open file at `path`, None if it does not exist or is not a regular file
+pub fn try_open_file(bs: &BootServices, path: &str) -> Option<RegularFile> {
     let mut buf = [0; 64];//初始话为0，大小为64
     //'from_str_with_buf' : Convert a &str to a &CStr16, backed by a buffer.
-    let cstr_path = uefi::CStr16::from_str_with_buf(path, &mut buf).unwrap();
+    let cstr_path = uefi::CStr16::from_str_with_buf(path, &mut buf).ok()?;
 
     let handle = open_root(bs)
         .open(cstr_path, FileMode::Read, FileAttribute::empty())//‘Attribute’属性
-        .expect("Failed to open file");
+        .ok()?;
 
-    match handle.into_type().expect("Failed to into_type") { //若打开地是一个目录，或无效路径，则panic!
-        FileType::Regular(regular) => regular,
-        _ => panic!("Invalid file type"),
+    match handle.into_type().ok()? { //若打开地是一个目录，则返回None
+        FileType::Regular(regular) => Some(regular),
+        _ => None,
     }
 }
 
@@ -59,6 +67,61 @@ pub fn load_file(bs: &BootServices, file: &mut RegularFile) -> &'static mut [u8]
     &mut buf[..len]//文件内容的引用
 }
 
+/// Directory of the apps loaded when `load_apps` is set
+const APP_DIR: &str = "\\APP";
+
+/// Load every file in the `\APP` directory, skipping those that do not fit in `AppList`
+///
+/// the pages stay allocated as loader data, so the kernel does not reuse them
+pub fn load_apps(bs: &BootServices) -> AppList {
+    let mut apps = AppList::new();
+
+    let mut buf = [0; 16];
+    let cstr_path = uefi::CStr16::from_str_with_buf(APP_DIR, &mut buf).unwrap();
+    let dir = open_root(bs)
+        .open(cstr_path, FileMode::Read, FileAttribute::empty())
+        .ok()
+        .and_then(|handle| handle.into_directory());
+    let mut dir = match dir {
+        Some(dir) => dir,
+        None => {
+            warn!("App directory {} not found", APP_DIR);
+            return apps;
+        }
+    };
+
+    let mut info_buf = [0u8; 0x200];
+    while let Ok(Some(info)) = dir.read_entry(&mut info_buf) {
+        if !info.is_regular_file() {
+            continue;
+        }
+
+        let mut name = ArrayString::<16>::new();
+        let mut path = ArrayString::<64>::new();
+        if write!(name, "{}", info.file_name()).is_err()
+            || write!(path, "{}\\{}", APP_DIR, name).is_err()
+        {
+            warn!("App name {} is too long, skipped", info.file_name());
+            continue;
+        }
+        if apps.is_full() {
+            warn!("Too many apps, {} and the rest are skipped", name);
+            break;
+        }
+
+        if let Some(mut file) = try_open_file(bs, &path) {
+            let data = load_file(bs, &mut file);
+            apps.push(App {
+                name,
+                base: data.as_ptr() as u64,
+                size: data.len() as u64,
+            });
+        }
+    }
+
+    apps
+}
+
 /// Free ELF files for which the buffer was created using 'load_file'
 pub fn free_elf(bs: &BootServices, elf: ElfFile) {
     let buffer = elf.input;//文件内容的引用
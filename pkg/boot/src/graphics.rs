@@ -0,0 +1,71 @@
+use uefi::proto::console::gop::{GraphicsOutput, PixelFormat};
+use uefi::table::boot::*;
+
+/// Framebuffer handed over to the kernel
+#[derive(Debug, Clone, Copy)]
+pub struct FrameBufferInfo {
+    /// Physical address of the framebuffer
+    pub base: u64,
+    /// Size of the framebuffer in bytes
+    pub size: usize,
+    /// Horizontal resolution in pixels
+    pub width: usize,
+    /// Vertical resolution in pixels
+    pub height: usize,
+    /// Number of pixels per scan line
+    pub stride: usize,
+    /// Layout of each 32-bit pixel, only `Rgb` and `Bgr` are used
+    pub format: PixelFormat,
+}
+
+/// Set the video mode and get the framebuffer from GOP
+///
+/// use the mode matching `resolution` if there is one, otherwise keep the current mode
+pub fn init_graphics(bs: &BootServices, resolution: Option<(usize, usize)>) -> Option<FrameBufferInfo> {
+    let handle = bs.get_handle_for_protocol::<GraphicsOutput>().ok()?;
+    // an exclusive open would disconnect the UEFI console from the screen, so
+    // share GOP with it, the kernel only draws once boot services have exited
+    let mut gop = unsafe {
+        bs.open_protocol::<GraphicsOutput>(
+            OpenProtocolParams {
+                handle,
+                agent: bs.image_handle(),
+                controller: None,
+            },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    }
+    .ok()?;
+
+    if let Some(resolution) = resolution {
+        let mode = gop.modes(bs).find(|m| {
+            let info = m.info();
+            info.resolution() == resolution
+                && matches!(info.pixel_format(), PixelFormat::Rgb | PixelFormat::Bgr)
+        });
+        match mode {
+            Some(mode) => gop.set_mode(&mode).expect("Failed to set video mode"),
+            None => warn!("No video mode for {}x{}, keep current", resolution.0, resolution.1),
+        }
+    }
+
+    let info = gop.current_mode_info();
+    if !matches!(info.pixel_format(), PixelFormat::Rgb | PixelFormat::Bgr) {
+        warn!("Unsupported pixel format: {:?}", info.pixel_format());
+        return None;
+    }
+
+    let (width, height) = info.resolution();
+    let mut fb = gop.frame_buffer();
+
+    info!("Video mode: {}x{}, {:?}", width, height, info.pixel_format());
+
+    Some(FrameBufferInfo {
+        base: fb.as_mut_ptr() as u64,
+        size: fb.size(),
+        width,
+        height,
+        stride: info.stride(),
+        format: info.pixel_format(),
+    })
+}
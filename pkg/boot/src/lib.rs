@@ -1,4 +1,4 @@
-#![no_std] // don't depend on std lib
+#![cfg_attr(not(test), no_std)] // don't depend on std lib
 // 导入外部依赖
 // 定义操作系统内核启动需要的结构体 BootInfo
 // 实现一些函数：current_page_table() 函数用于获取当前的页表。
@@ -10,19 +10,25 @@ use core::arch::asm;
 pub mod allocator;
 pub mod config;
 pub mod fs;
+pub mod graphics;
+pub mod menu;
+pub mod random;
 pub use allocator::*;
 pub use fs::*;
+pub use graphics::*;
+pub use random::*;
 
 pub use uefi::data_types::chars::*;
 pub use uefi::data_types::*;
 pub use uefi::prelude::SystemTable;
-pub use uefi::proto::console::gop::{GraphicsOutput, ModeInfo};
+pub use uefi::proto::console::gop::{GraphicsOutput, ModeInfo, PixelFormat};
 pub use uefi::table::boot::{MemoryAttribute, MemoryDescriptor, MemoryType};
+pub use uefi::table::cfg::{ACPI2_GUID, ACPI_GUID};
 pub use uefi::table::runtime::*;
 pub use uefi::table::Runtime;
 pub use uefi::Status as UefiStatus;
 
-use arrayvec::ArrayVec;
+use arrayvec::{ArrayString, ArrayVec};
 use x86_64::VirtAddr;
 use x86_64::registers::control::Cr3;
 use x86_64::structures::paging::{OffsetPageTable, PageTable};
@@ -41,6 +47,25 @@ pub struct MemoryDescriptor {
 */
 pub type MemoryMap = ArrayVec<MemoryDescriptor, 256>;
 
+/// Longest kernel command line, in bytes
+pub const CMDLINE_MAX: usize = 256;
+pub type CmdLine = ArrayString<CMDLINE_MAX>;
+
+/// Maximum number of apps loaded by the bootloader
+pub const MAX_APPS: usize = 16;
+
+/// An app loaded from the `\APP` directory of the boot disk
+pub struct App {
+    /// The file name of the app
+    pub name: ArrayString<16>,
+    /// Physical address of the loaded file
+    pub base: u64,
+    /// Size of the file in bytes
+    pub size: u64,
+}
+
+pub type AppList = ArrayVec<App, MAX_APPS>;
+
 /// This structure represents the information that the bootloader passes to the kernel.
 pub struct BootInfo {
     /// The memory map
@@ -49,6 +74,33 @@ pub struct BootInfo {
     /// The offset into the virtual address space where the physical memory is mapped.
     pub physical_memory_offset: u64,
 
+    /// The offset from the kernel's link address to where it is loaded, 0 if not randomized.
+    pub kernel_offset: u64,
+
+    /// The address at which the kernel stack is placed.
+    pub kernel_stack_address: u64,
+
+    /// The size of the kernel stack, given in number of 4KiB pages.
+    pub kernel_stack_size: u64,
+
+    /// The number of 4KiB pages mapped at the top of the kernel stack, 0 means all.
+    pub kernel_stack_auto_grow: u64,
+
+    /// The framebuffer set by the bootloader, None if there is no usable graphics output.
+    pub graphic_info: Option<FrameBufferInfo>,
+
+    /// Physical address of a page below 1MiB reserved for starting other CPUs, 0 if unavailable.
+    pub ap_trampoline: u64,
+
+    /// Physical address and size in bytes of the initramfs loaded by the bootloader, None if not configured.
+    pub initramfs: Option<(u64, u64)>,
+
+    /// The command line of the selected boot entry
+    pub cmdline: CmdLine,
+
+    /// The apps loaded by the bootloader, empty unless `load_apps` is set for the boot entry
+    pub loaded_apps: AppList,
+
     /// UEFI SystemTable
     pub system_table: SystemTable<Runtime>,
 }
@@ -13,6 +13,7 @@ use elf::*;
 use alloc::boxed::Box;
 use alloc::vec;
 use uefi::prelude::*;
+use uefi::table::boot::AllocateType;
 use x86_64::registers::control::*;
 use x86_64::VirtAddr;
 use ysos_boot::*;
@@ -20,6 +21,19 @@ use xmas_elf::ElfFile;
 
 // path of config file
 const CONFIG_PATH: &str = "\\EFI\\BOOT\\boot.conf";
+
+// KASLR: the kernel is slid by a 2MiB aligned offset within 1GiB
+const KASLR_KERNEL_RANGE: u64 = 0x4000_0000;
+const KASLR_KERNEL_ALIGN: u64 = 0x20_0000;
+// KASLR: the physical memory map is slid by a 1GiB aligned offset within 16TiB
+const KASLR_PHYS_RANGE: u64 = 0x1000_0000_0000;
+const KASLR_PHYS_ALIGN: u64 = 0x4000_0000;
+// KASLR: the kernel stack is slid by pages within its 4GiB aligned window
+const KASLR_STACK_WINDOW: u64 = 0x1_0000_0000;
+
+// APs start in real mode, so the trampoline must be below 1MiB
+const AP_TRAMPOLINE_MAX: u64 = 0xF_FFFF;
+
 // uefi程序入口点
 #[entry]
 fn efi_main(image: uefi::Handle, mut system_table: SystemTable<Boot>) -> Status {
@@ -37,8 +51,14 @@ fn efi_main(image: uefi::Handle, mut system_table: SystemTable<Boot>) -> Status
     let mut config_file = open_file(&bs, CONFIG_PATH);
     //load file
     let config_data = load_file(&bs, &mut config_file);
-    let config = ysos_boot::config::Config::parse(config_data);
-    
+    let mut config = ysos_boot::config::Config::parse(config_data)
+        .unwrap_or_else(|e| panic!("Failed to parse {}: {}", CONFIG_PATH, e));
+
+    // 1.1 Choose boot entry
+    let entry = ysos_boot::menu::select_entry(&mut uefi_services::system_table(), &config);
+    config.select(entry);
+    info!("Boot entry: {}", config.entries[entry].name);
+
     info!("Config: {:#x?}", config);//日志消息格式，用于输出结构体的信息
 
     // 2. Load ELF files
@@ -46,10 +66,31 @@ fn efi_main(image: uefi::Handle, mut system_table: SystemTable<Boot>) -> Status
     let mut elf_file = open_file(&bs, &config.kernel_path);
     let elf_data = load_file(&bs,&mut elf_file);
     let elf = ElfFile::new(elf_data).expect("fialed to new an ElfFile");
+
+    // 2.1 Randomize kernel base, kernel stack and physical memory offset
+    let kernel_offset = if config.kaslr {
+        randomize_layout(bs, &mut config, &elf)
+    } else {
+        0
+    };
+
     unsafe {
-        set_entry(elf.header.pt2.entry_point() as usize);
+        set_entry(entry_point(&elf, kernel_offset) as usize);
     }
 
+    // 2.2 Load initramfs, the kernel unpacks it before any storage driver is up
+    let initramfs = load_initramfs(bs, config.initramfs);
+
+    // 2.3 Load apps, if the boot entry asks for them
+    let loaded_apps = if config.load_apps {
+        load_apps(bs)
+    } else {
+        AppList::new()
+    };
+
+    // 2.4 Set video mode and get the framebuffer
+    let graphic_info = init_graphics(bs, config.resolution);
+
     // 3. Load MemoryMap
     let max_mmap_size = system_table.boot_services().memory_map_size();
     let mmap_storage = Box::leak(//Box::leak 是一个将 Box 转换为裸指针并泄漏其内存的方法，防止 Rust 的自动内存回收
@@ -65,7 +106,19 @@ fn efi_main(image: uefi::Handle, mut system_table: SystemTable<Boot>) -> Status
         .map(|m| m.phys_start + m.page_count * 0x1000)
         .max()
         .unwrap()
-        .max(0x1_0000_0000); // include IOAPIC MMIO area
+        .max(0x1_0000_0000) // include IOAPIC MMIO area
+        .max(graphic_info.map_or(0, |fb| fb.base + fb.size as u64)); // include framebuffer
+
+    // the end of the mapping must be canonical too, and in the same half as its start
+    let phys_end = config.physical_memory_offset.checked_add(max_phys_addr - 1);
+    if !phys_end.is_some_and(|end| {
+        ysos_boot::config::is_canonical(end) && end >> 63 == config.physical_memory_offset >> 63
+    }) {
+        panic!(
+            "Physical memory up to {:#x} does not fit at offset {:#x}",
+            max_phys_addr, config.physical_memory_offset
+        );
+    }
 
     // 4. Map ELF segments, kernel stack and physical memory to virtual memory
     let mut page_table = current_page_table();//获取页表
@@ -90,8 +143,9 @@ fn efi_main(image: uefi::Handle, mut system_table: SystemTable<Boot>) -> Status
     // FIXME: load and map the kernel elf file
     // 调用load_elf
     // 加载并映射内核 ELF 文件
-    load_elf(
+    load_elf_at(
         &elf, // 已加载的 ELF 文件
+        kernel_offset, // 内核加载偏移量
         config.physical_memory_offset, // 物理地址偏移量
         &mut page_table, // 页表映射器
         &mut frame_allocator, // 物理帧分配器
@@ -99,8 +153,14 @@ fn efi_main(image: uefi::Handle, mut system_table: SystemTable<Boot>) -> Status
 
     // FIXME: map kernel stack
     // 映射内核栈
+    // if auto grow is enabled, only map the top pages, the rest is handled by kernel
+    let stack_pages = if config.kernel_stack_auto_grow > 0 {
+        config.kernel_stack_auto_grow.min(config.kernel_stack_size)
+    } else {
+        config.kernel_stack_size
+    };
     let stack_end = VirtAddr::new(config.kernel_stack_address + config.kernel_stack_size * 0x1000);
-    let stack_start = VirtAddr::new(config.kernel_stack_address);
+    let stack_start = stack_end - stack_pages * 0x1000;
     map_range(//将虚拟内存映射到物理内存中，page_table是页表，如映射一个栈。
         stack_start.as_u64(),
         (stack_end - stack_start) / 0x1000, // 计算栈的大小，单位是页
@@ -116,6 +176,18 @@ fn efi_main(image: uefi::Handle, mut system_table: SystemTable<Boot>) -> Status
     }
     free_elf(bs, elf);
 
+    // 4.1 Reserve a page in real mode memory for the AP startup trampoline
+    let ap_trampoline = bs
+        .allocate_pages(
+            AllocateType::MaxAddress(AP_TRAMPOLINE_MAX),
+            MemoryType::LOADER_CODE,
+            1,
+        )
+        .unwrap_or_else(|e| {
+            warn!("Failed to reserve AP trampoline: {:?}", e);
+            0
+        });
+
     // 5. Exit boot and jump to ELF entry
     info!("Exiting boot services...");
 
@@ -126,6 +198,15 @@ fn efi_main(image: uefi::Handle, mut system_table: SystemTable<Boot>) -> Status
     let bootinfo = BootInfo {
         memory_map: mmap.entries().copied().collect(),
         physical_memory_offset: config.physical_memory_offset,
+        kernel_offset,
+        kernel_stack_address: config.kernel_stack_address,
+        kernel_stack_size: config.kernel_stack_size,
+        kernel_stack_auto_grow: config.kernel_stack_auto_grow,
+        graphic_info,
+        ap_trampoline,
+        initramfs,
+        cmdline: config.kernel_cmdline(),
+        loaded_apps,
         system_table: runtime,
     };
 
@@ -136,3 +217,61 @@ fn efi_main(image: uefi::Handle, mut system_table: SystemTable<Boot>) -> Status
         jump_to_entry(&bootinfo, stacktop);
     }
 }
+
+/// Randomize the layout described by `config` in place
+///
+/// returns the offset at which the kernel should be loaded
+fn randomize_layout(
+    bs: &BootServices,
+    config: &mut ysos_boot::config::Config,
+    elf: &ElfFile,
+) -> u64 {
+    let kernel_offset = if is_pie(elf) {
+        random_aligned(bs, KASLR_KERNEL_RANGE, KASLR_KERNEL_ALIGN)
+    } else {
+        warn!("Kernel is not relocatable, keep it at its link address");
+        0
+    };
+
+    let slide = random_aligned(bs, KASLR_PHYS_RANGE, KASLR_PHYS_ALIGN);
+    if !config.slide_physical_offset(slide) {
+        warn!(
+            "KASLR: physical offset {:#x} can not be moved by {:#x}",
+            config.physical_memory_offset, slide
+        );
+    }
+
+    // keep the stack inside the window it was configured in
+    let stack_end = config.kernel_stack_address + config.kernel_stack_size * 0x1000;
+    let window_end = (config.kernel_stack_address & !(KASLR_STACK_WINDOW - 1)) + KASLR_STACK_WINDOW;
+    if stack_end <= window_end {
+        config.kernel_stack_address += random_aligned(bs, window_end - stack_end + 0x1000, 0x1000);
+    }
+
+    info!(
+        "KASLR: kernel offset = {:#x}, stack = {:#x}, physical offset = {:#x}",
+        kernel_offset, config.kernel_stack_address, config.physical_memory_offset
+    );
+
+    kernel_offset
+}
+
+/// Load the initramfs at `path`, returns its physical address and size
+///
+/// the pages stay allocated as loader data, so the kernel does not reuse them
+fn load_initramfs(bs: &BootServices, path: &str) -> Option<(u64, u64)> {
+    if path.is_empty() {
+        return None;
+    }
+
+    let mut file = match try_open_file(bs, path) {
+        Some(file) => file,
+        None => {
+            warn!("Initramfs {} not found, booting without it", path);
+            return None;
+        }
+    };
+
+    let data = load_file(bs, &mut file);
+    Some((data.as_ptr() as u64, data.len() as u64))
+}
@@ -0,0 +1,90 @@
+use crate::config::Config;
+use core::fmt::Write;
+use uefi::proto::console::text::{Key, ScanCode};
+use uefi::table::{Boot, SystemTable};
+
+/// Interval of polling the keyboard, in microseconds
+const POLL_INTERVAL: usize = 100_000;
+const POLLS_PER_SECOND: u64 = 10;
+
+/// Show the boot menu on the UEFI console and return the selected entry
+///
+/// the default entry is booted when the timeout expires,
+/// any key press stops the countdown.
+pub fn select_entry(st: &mut SystemTable<Boot>, config: &Config) -> usize {
+    let count = config.entries.len();
+    let mut selected = config.default_index();
+
+    if count <= 1 || config.timeout == 0 {
+        return selected;
+    }
+
+    let mut remaining = Some(config.timeout * POLLS_PER_SECOND);
+    let mut redraw = true;
+
+    loop {
+        if redraw {
+            draw_menu(st, config, selected, remaining.map(|r| r / POLLS_PER_SECOND));
+            redraw = false;
+        }
+
+        if let Ok(Some(key)) = st.stdin().read_key() {
+            remaining = None;
+            redraw = true;
+            match key {
+                Key::Special(ScanCode::UP) => selected = (selected + count - 1) % count,
+                Key::Special(ScanCode::DOWN) => selected = (selected + 1) % count,
+                Key::Printable(ch) => match char::from(ch) {
+                    '\r' | '\n' => break,
+                    ch @ '1'..='9' if (ch as usize - '1' as usize) < count => {
+                        selected = ch as usize - '1' as usize;
+                        break;
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+
+        if let Some(r) = remaining.as_mut() {
+            if *r == 0 {
+                break;
+            }
+            *r -= 1;
+            redraw = *r % POLLS_PER_SECOND == 0;
+        }
+
+        st.boot_services().stall(POLL_INTERVAL);
+    }
+
+    let _ = st.stdout().clear();
+    selected
+}
+
+fn draw_menu(st: &mut SystemTable<Boot>, config: &Config, selected: usize, seconds: Option<u64>) {
+    let out = st.stdout();
+    let _ = out.clear();
+
+    let _ = writeln!(out, "YatSenOS Boot Menu\r\n\r");
+    for (idx, entry) in config.entries.iter().enumerate() {
+        let marker = if idx == selected { '>' } else { ' ' };
+        let _ = writeln!(
+            out,
+            " {} {}. {:16} {}\r",
+            marker,
+            idx + 1,
+            entry.name,
+            entry.kernel_path
+        );
+    }
+
+    let _ = writeln!(out, "\r\nUse Up/Down to choose, Enter to boot.\r");
+    if let Some(seconds) = seconds {
+        let _ = writeln!(
+            out,
+            "Booting '{}' in {}s...\r",
+            config.entries[selected].name,
+            seconds
+        );
+    }
+}
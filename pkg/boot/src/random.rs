@@ -0,0 +1,38 @@
+use uefi::proto::rng::Rng;
+use uefi::table::boot::*;
+use x86_64::instructions::random::RdRand;
+
+/// Get a random u64 for layout randomization
+///
+/// prefer the UEFI RNG protocol, fall back to RDRAND, and finally to TSC
+pub fn random_u64(bs: &BootServices) -> u64 {
+    if let Some(value) = uefi_random(bs) {
+        return value;
+    }
+
+    if let Some(value) = RdRand::new().and_then(|r| r.get_u64()) {
+        return value;
+    }
+
+    warn!("No hardware RNG available, falling back to TSC");
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Get a random multiple of `align` in `[0, range)`
+pub fn random_aligned(bs: &BootServices, range: u64, align: u64) -> u64 {
+    let slots = range / align;
+    if slots == 0 {
+        return 0;
+    }
+    (random_u64(bs) % slots) * align
+}
+
+fn uefi_random(bs: &BootServices) -> Option<u64> {
+    let handle = bs.get_handle_for_protocol::<Rng>().ok()?;
+    let mut rng = bs.open_protocol_exclusive::<Rng>(handle).ok()?;
+
+    let mut buf = [0u8; 8];
+    rng.get_rng(None, &mut buf).ok()?;
+
+    Some(u64::from_le_bytes(buf))
+}
@@ -1,14 +1,42 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 #[macro_use]
 extern crate log;
 
+use core::mem::size_of;
 use core::ptr::{copy_nonoverlapping, write_bytes};
 
 use x86_64::structures::paging::page::PageRange;
 use x86_64::structures::paging::{mapper::*, *};
-use x86_64::{align_up, PhysAddr, VirtAddr};
-use xmas_elf::{program, ElfFile};
+use x86_64::{PhysAddr, VirtAddr};
+use xmas_elf::sections::{Rela, SectionData};
+use xmas_elf::symbol_table::{DynEntry64, Entry};
+use xmas_elf::{header, program, ElfFile, P64};
+
+// relocation types used by x86_64 PIE binaries
+const R_X86_64_NONE: u32 = 0;
+const R_X86_64_64: u32 = 1;
+const R_X86_64_GLOB_DAT: u32 = 6;
+const R_X86_64_RELATIVE: u32 = 8;
+
+/// Errors while loading an ELF file
+#[derive(Debug)]
+pub enum LoadError {
+    Map(MapToError<Size4KiB>),
+    /// the section is out of the file, or not an array of its entries
+    InvalidSection(&'static str),
+    UnsupportedRelocation(u32),
+    /// a relocation refers to a symbol not in `.dynsym`
+    MissingSymbol(u32),
+    /// a relocation writes to an address no segment is loaded at
+    UnmappedTarget(u64),
+}
+
+impl From<MapToError<Size4KiB>> for LoadError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        Self::Map(err)
+    }
+}
 
 /// Map physical memory
 ///
@@ -80,6 +108,16 @@ pub fn map_range(
     Ok(Page::range(range_start, range_end))
 }
 
+/// Check if the ELF file is position-independent (ET_DYN)
+pub fn is_pie(elf: &ElfFile) -> bool {
+    elf.header.pt2.type_().as_type() == header::Type::SharedObject
+}
+
+/// Get the entry point of ELF file loaded at `base`
+pub fn entry_point(elf: &ElfFile, base: u64) -> u64 {
+    base + elf.header.pt2.entry_point()
+}
+
 /// Load & Map ELF file
 ///
 /// load segments in ELF file to new frames and set page table
@@ -88,10 +126,28 @@ pub fn load_elf(
     physical_offset: u64,
     page_table: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> Result<(), MapToError<Size4KiB>> {
+) -> Result<(), LoadError> {
+    load_elf_at(elf, 0, physical_offset, page_table, frame_allocator)
+}
+
+/// Load & Map ELF file at `base`
+///
+/// ET_EXEC images are loaded at their link addresses and require `base == 0`,
+/// ET_DYN (PIE) images are shifted by `base` and relocated after mapping.
+pub fn load_elf_at(
+    elf: &ElfFile,
+    base: u64,
+    physical_offset: u64,
+    page_table: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), LoadError> {
     let file_buf = elf.input.as_ptr();
+    let pie = is_pie(elf);
+
+    assert!(pie || base == 0, "ET_EXEC image cannot be loaded at {:#x}", base);
+    assert!(base & 0xfff == 0, "Load base {:#x} is not page aligned", base);
 
-    info!("Loading ELF file... @ {:#x}", file_buf as u64);
+    info!("Loading ELF file... @ {:#x}, base = {:#x}", file_buf as u64, base);
 
     for segment in elf.program_iter() {
         if segment.get_type().unwrap() != program::Type::Load {
@@ -100,6 +156,7 @@ pub fn load_elf(
 
         load_segment(
             file_buf,
+            base,
             physical_offset,
             &segment,
             page_table,
@@ -107,14 +164,142 @@ pub fn load_elf(
         )?
     }
 
+    if pie {
+        relocate(elf, base, physical_offset, page_table)?;
+    }
+
+    Ok(())
+}
+
+/// Apply dynamic relocations of ELF file loaded at `base`
+///
+/// handle R_X86_64_RELATIVE/64/GLOB_DAT entries in `.rela.dyn`,
+/// values are written through the physical memory mapping,
+/// so read-only segments can be patched as well.
+pub fn relocate(
+    elf: &ElfFile,
+    base: u64,
+    physical_offset: u64,
+    page_table: &impl Mapper<Size4KiB>,
+) -> Result<(), LoadError> {
+    let entries = match array_section(elf, ".rela.dyn", size_of::<Rela<P64>>())? {
+        Some(SectionData::Rela64(entries)) => entries,
+        Some(_) => return Err(LoadError::InvalidSection(".rela.dyn")),
+        None => {
+            trace!("No .rela.dyn section, skip relocation");
+            return Ok(());
+        }
+    };
+
+    let symbols = match array_section(elf, ".dynsym", size_of::<DynEntry64>())? {
+        Some(SectionData::DynSymbolTable64(symbols)) => symbols,
+        Some(_) => return Err(LoadError::InvalidSection(".dynsym")),
+        None => &[],
+    };
+
+    trace!("Applying {} relocations at base {:#x}", entries.len(), base);
+
+    for entry in entries {
+        let addend = entry.get_addend();
+        let value = match entry.get_type() {
+            R_X86_64_NONE => continue,
+            R_X86_64_RELATIVE => base.wrapping_add(addend),
+            R_X86_64_64 => {
+                symbol_value(symbols, entry.get_symbol_table_index(), base)?.wrapping_add(addend)
+            }
+            R_X86_64_GLOB_DAT => symbol_value(symbols, entry.get_symbol_table_index(), base)?,
+            ty => return Err(LoadError::UnsupportedRelocation(ty)),
+        };
+
+        let target = base.wrapping_add(entry.get_offset());
+        write_relocation(target, value, physical_offset, page_table)?;
+    }
+
+    Ok(())
+}
+
+/// Find a section holding an array of `entry_size` byte entries
+///
+/// xmas-elf panics on arrays out of the file or of a partial entry,
+/// so they are checked before the section is parsed.
+fn array_section<'a>(
+    elf: &ElfFile<'a>,
+    name: &'static str,
+    entry_size: usize,
+) -> Result<Option<SectionData<'a>>, LoadError> {
+    let section = match elf.find_section_by_name(name) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let end = section.offset().checked_add(section.size());
+    if end.map_or(true, |end| end > elf.input.len() as u64) {
+        return Err(LoadError::InvalidSection(name));
+    }
+
+    let data = section.raw_data(elf);
+    if data.len() % entry_size != 0 || data.as_ptr() as usize % 8 != 0 {
+        return Err(LoadError::InvalidSection(name));
+    }
+
+    section
+        .get_data(elf)
+        .map(Some)
+        .map_err(|_| LoadError::InvalidSection(name))
+}
+
+/// Resolve a dynamic symbol, undefined (weak) symbols are resolved to 0
+fn symbol_value(symbols: &[DynEntry64], index: u32, base: u64) -> Result<u64, LoadError> {
+    let symbol = symbols
+        .get(index as usize)
+        .ok_or(LoadError::MissingSymbol(index))?;
+
+    if symbol.shndx() == 0 {
+        warn!("Relocation refers to undefined symbol #{}", index);
+        Ok(0)
+    } else {
+        Ok(base + symbol.value())
+    }
+}
+
+/// Write a relocated value to `addr` in the target page table
+fn write_relocation(
+    addr: u64,
+    value: u64,
+    physical_offset: u64,
+    page_table: &impl Mapper<Size4KiB>,
+) -> Result<(), LoadError> {
+    // the value is written through a single frame, it must not cross pages
+    if addr & 0xfff > 0x1000 - size_of::<u64>() as u64 {
+        return Err(LoadError::UnmappedTarget(addr));
+    }
+
+    let addr = VirtAddr::try_new(addr).map_err(|_| LoadError::UnmappedTarget(addr))?;
+    let page: Page<Size4KiB> = Page::containing_address(addr);
+    let frame = page_table
+        .translate_page(page)
+        .map_err(|_| LoadError::UnmappedTarget(addr.as_u64()))?;
+
+    let ptr = frame.start_address().as_u64() + physical_offset + (addr.as_u64() & 0xfff);
+
+    unsafe {
+        (ptr as *mut u64).write_unaligned(value);
+    }
     Ok(())
 }
 
 /// Load & Map ELF segment
 ///
 /// load segment to new frame and set page table
+///
+/// the new mappings are not flushed: the pages were not present before, and
+/// x86 never caches a not-present translation in the TLB. The page table is
+/// usually not the active one either (the bootloader builds the kernel's,
+/// the kernel builds a new process's), so `invlpg` would only evict entries
+/// of the wrong address space.
 fn load_segment(
     file_buf: *const u8,
+    base: u64,
     physical_offset: u64,
     segment: &program::ProgramHeader,
     page_table: &mut impl Mapper<Size4KiB>,
@@ -125,7 +310,9 @@ fn load_segment(
     let mem_size = segment.mem_size();
     let file_size = segment.file_size();
     let file_offset = segment.offset() & !0xfff;
-    let virt_start_addr = VirtAddr::new(segment.virtual_addr());
+    // PIE segments are not always page aligned, count from the page start
+    let page_offset = segment.offset() & 0xfff;
+    let virt_start_addr = VirtAddr::new(base + segment.virtual_addr());
     
     let mut page_table_flags = PageTableFlags::PRESENT;
 
@@ -147,68 +334,73 @@ fn load_segment(
     trace!("Segment page table flag: {:?}", page_table_flags);
 
     let start_page = Page::containing_address(virt_start_addr);
-    let end_page = Page::containing_address(virt_start_addr + file_size - 1u64);
-    let pages = Page::range_inclusive(start_page, end_page);
-
+    let file_end = file_size + page_offset;
     let data = unsafe { file_buf.add(file_offset as usize) };
 
-    for (idx, page) in pages.enumerate() {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
+    // the first page left for .bss, after the pages holding file data
+    let mut bss_page = start_page;
 
-        let offset = idx as u64 * page.size();
-        let count = if file_size - offset < page.size() {
-            file_size - offset
-        } else {
-            page.size()
-        };
+    if file_size > 0 {
+        let end_page = Page::containing_address(virt_start_addr + file_size - 1u64);
+        bss_page = end_page + 1;
 
-        unsafe {
-            copy_nonoverlapping(
-                data.add(idx * page.size() as usize),
-                (frame.start_address().as_u64() + physical_offset) as *mut u8,
-                count as usize,
-            );
+        for (idx, page) in Page::range_inclusive(start_page, end_page).enumerate() {
+            let frame = frame_allocator
+                .allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?;
 
-            page_table
-                .map_to(page, frame, page_table_flags, frame_allocator)?
-                .flush();
+            let offset = idx as u64 * page.size();
+            let count = if file_end - offset < page.size() {
+                file_end - offset
+            } else {
+                page.size()
+            };
 
-            if count < page.size() {
-                // zero the rest of the page
-                trace!(
-                    "Zeroing rest of the page: {:#x}",
-                    page.start_address().as_u64()
-                );
-                write_bytes(
-                    (frame.start_address().as_u64() + physical_offset + count) as *mut u8,
-                    0,
-                    (page.size() - count) as usize,
+            unsafe {
+                copy_nonoverlapping(
+                    data.add(idx * page.size() as usize),
+                    (frame.start_address().as_u64() + physical_offset) as *mut u8,
+                    count as usize,
                 );
+
+                // not flushed, see above
+                page_table
+                    .map_to(page, frame, page_table_flags, frame_allocator)?
+                    .ignore();
+
+                if count < page.size() {
+                    // zero the rest of the page
+                    trace!(
+                        "Zeroing rest of the page: {:#x}",
+                        page.start_address().as_u64()
+                    );
+                    write_bytes(
+                        (frame.start_address().as_u64() + physical_offset + count) as *mut u8,
+                        0,
+                        (page.size() - count) as usize,
+                    );
+                }
             }
         }
     }
 
     if mem_size > file_size {
         // .bss section (or similar), which needs to be zeroed
-        let zero_start = virt_start_addr + file_size;
         let zero_end = virt_start_addr + mem_size;
 
         // Map additional frames.
-        let start_address = VirtAddr::new(align_up(zero_start.as_u64(), Size4KiB::SIZE));
-        let start_page: Page = Page::containing_address(start_address);
-        let end_page = Page::containing_address(zero_end);
+        let end_page = Page::containing_address(zero_end - 1u64);
 
-        for page in Page::range_inclusive(start_page, end_page) {
+        for page in Page::range_inclusive(bss_page, end_page) {
             let frame = frame_allocator
                 .allocate_frame()
                 .ok_or(MapToError::FrameAllocationFailed)?;
 
             unsafe {
+                // not flushed, see above
                 page_table
                     .map_to(page, frame, page_table_flags, frame_allocator)?
-                    .flush();
+                    .ignore();
 
                 // zero bss section
                 write_bytes(
@@ -222,3 +414,215 @@ fn load_segment(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// A page of host memory, standing for a physical frame
+    #[repr(align(4096))]
+    struct Frame([u8; 4096]);
+
+    /// Frames from the host heap, with the physical memory mapped at offset 0
+    #[derive(Default)]
+    struct HostFrames(Vec<Box<Frame>>);
+
+    unsafe impl FrameAllocator<Size4KiB> for HostFrames {
+        fn allocate_frame(&mut self) -> Option<PhysFrame> {
+            // filled with garbage, so zeroing is checked
+            let frame = Box::new(Frame([0xcc; 4096]));
+            let addr = PhysAddr::new(&*frame as *const Frame as u64);
+            self.0.push(frame);
+            PhysFrame::from_start_address(addr).ok()
+        }
+    }
+
+    /// ELF header and one PT_LOAD program header, in a file of `len` bytes
+    fn elf_image(len: usize, offset: u64, vaddr: u64, file_size: u64, mem_size: u64) -> Vec<u8> {
+        let mut image = Vec::new();
+        image.extend_from_slice(b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0");
+        image.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+        image.extend_from_slice(&0x3eu16.to_le_bytes()); // x86_64
+        image.extend_from_slice(&1u32.to_le_bytes());
+        image.extend_from_slice(&vaddr.to_le_bytes()); // entry
+        image.extend_from_slice(&64u64.to_le_bytes()); // program headers
+        image.extend_from_slice(&0u64.to_le_bytes()); // no sections
+        image.extend_from_slice(&0u32.to_le_bytes());
+        for half in [64u16, 56, 1, 64, 0, 0] {
+            image.extend_from_slice(&half.to_le_bytes());
+        }
+
+        image.extend_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+        image.extend_from_slice(&6u32.to_le_bytes()); // RW
+        for word in [offset, vaddr, vaddr, file_size, mem_size, 0x1000] {
+            image.extend_from_slice(&word.to_le_bytes());
+        }
+
+        image.resize(len, 0);
+        image
+    }
+
+    /// A PIE of one RW segment covering the whole 0x2000 byte file,
+    /// relocations `(offset, info, addend)` go to `.rela.dyn` at 0x1200
+    ///
+    /// `.dynsym` holds the null symbol, `defined` at 0x1800 and an undefined one,
+    /// `.rela.dyn` is `rela_size` bytes long.
+    fn pie_image(relocations: &[(u64, u64, i64)], rela_size: u64) -> Vec<u8> {
+        let mut image = elf_image(0x2000, 0, 0, 0x2000, 0x2000);
+        image[16..18].copy_from_slice(&3u16.to_le_bytes()); // ET_DYN
+        image[24..32].copy_from_slice(&0x1000u64.to_le_bytes()); // entry
+        image[40..48].copy_from_slice(&0x1400u64.to_le_bytes()); // section headers
+        image[58..64].copy_from_slice(&[64, 0, 4, 0, 3, 0]); // 4 sections, names in #3
+
+        let put = |image: &mut Vec<u8>, at: usize, bytes: &[u8]| {
+            image[at..at + bytes.len()].copy_from_slice(bytes)
+        };
+
+        // .dynsym: name, info, other, shndx, value, size
+        let mut symbols = vec![0u8; 24];
+        for (shndx, value) in [(1u16, 0x1800u64), (0, 0)] {
+            symbols.extend_from_slice(&[0, 0, 0, 0, 0x12, 0]);
+            symbols.extend_from_slice(&shndx.to_le_bytes());
+            symbols.extend_from_slice(&value.to_le_bytes());
+            symbols.extend_from_slice(&0u64.to_le_bytes());
+        }
+        put(&mut image, 0x1100, &symbols);
+
+        let mut rela = Vec::new();
+        for &(offset, info, addend) in relocations {
+            rela.extend_from_slice(&offset.to_le_bytes());
+            rela.extend_from_slice(&info.to_le_bytes());
+            rela.extend_from_slice(&addend.to_le_bytes());
+        }
+        put(&mut image, 0x1200, &rela);
+
+        put(&mut image, 0x1300, b"\0.rela.dyn\0.dynsym\0.shstrtab\0");
+
+        // name, type, offset, size, entry size; the null section is left zeroed
+        let sections = [
+            (1u32, 4u32, 0x1200u64, rela_size, 24u64),
+            (11, 11, 0x1100, 72, 24),
+            (19, 3, 0x1300, 29, 0),
+        ];
+        for (idx, (name, ty, offset, size, entsize)) in sections.into_iter().enumerate() {
+            let mut header = Vec::new();
+            header.extend_from_slice(&name.to_le_bytes());
+            header.extend_from_slice(&ty.to_le_bytes());
+            for word in [0, offset, offset, size] {
+                header.extend_from_slice(&word.to_le_bytes());
+            }
+            header.extend_from_slice(&[0; 8]); // link, info
+            header.extend_from_slice(&8u64.to_le_bytes());
+            header.extend_from_slice(&entsize.to_le_bytes());
+            put(&mut image, 0x1440 + idx * 64, &header);
+        }
+
+        image
+    }
+
+    /// Load `image` at `base` into a new page table, returns the bytes at `addr`
+    /// for each page in `pages`, None for a page left unmapped
+    fn load(
+        image: &[u8],
+        base: u64,
+        addr: u64,
+        pages: usize,
+    ) -> Result<Vec<Option<Vec<u8>>>, LoadError> {
+        let mut frames = HostFrames::default();
+        let mut p4 = Box::new(PageTable::new());
+        let mut page_table = unsafe { OffsetPageTable::new(&mut p4, VirtAddr::new(0)) };
+
+        let elf = ElfFile::new(image).unwrap();
+        load_elf_at(&elf, base, 0, &mut page_table, &mut frames)?;
+
+        Ok((0..pages as u64)
+            .map(|idx| {
+                let page = Page::<Size4KiB>::containing_address(VirtAddr::new(addr + idx * 0x1000));
+                page_table.translate_page(page).ok().map(|frame| {
+                    let ptr = frame.start_address().as_u64() as *const u8;
+                    unsafe { core::slice::from_raw_parts(ptr, 0x1000) }.to_vec()
+                })
+            })
+            .collect())
+    }
+
+    #[test]
+    fn bss_only_segment() {
+        let image = elf_image(0x1000, 0, 0x40_0000, 0, 0x2010);
+        let pages = load(&image, 0, 0x40_0000, 4).unwrap();
+
+        for page in &pages[..3] {
+            assert!(page.as_ref().unwrap().iter().all(|&b| b == 0));
+        }
+        assert!(pages[3].is_none());
+    }
+
+    #[test]
+    fn bss_after_file_data() {
+        // 0x20 bytes of data across a page boundary, then bss up to the next page
+        let mut image = elf_image(0x2010, 0x1ff0, 0x60_0ff0, 0x20, 0x1020);
+        image[0x1ff0..0x2010].fill(0xab);
+        let pages = load(&image, 0, 0x60_0000, 4).unwrap();
+
+        let first = pages[0].as_ref().unwrap();
+        assert!(first[0xff0..].iter().all(|&b| b == 0xab));
+        let second = pages[1].as_ref().unwrap();
+        assert!(second[..0x10].iter().all(|&b| b == 0xab));
+        assert!(second[0x10..].iter().all(|&b| b == 0));
+        assert!(pages[2].as_ref().unwrap().iter().all(|&b| b == 0));
+        assert!(pages[3].is_none());
+    }
+
+    #[test]
+    fn pie_relocations() {
+        const BASE: u64 = 0x1000_0000;
+        let relocations = [
+            (0x1000, R_X86_64_RELATIVE as u64, 0x1234),
+            (0x1008, 1 << 32 | R_X86_64_64 as u64, 0x10),
+            (0x1010, 1 << 32 | R_X86_64_GLOB_DAT as u64, 0),
+            (0x1018, 2 << 32 | R_X86_64_GLOB_DAT as u64, 0),
+            (0x1020, R_X86_64_NONE as u64, 0),
+        ];
+        let mut image = pie_image(&relocations, 5 * 24);
+        image[0x1020..0x1028].fill(0xab);
+
+        let pages = load(&image, BASE, BASE + 0x1000, 1).unwrap();
+        let page = pages[0].as_ref().unwrap();
+        let word = |offset: usize| u64::from_le_bytes(page[offset..offset + 8].try_into().unwrap());
+
+        assert_eq!(word(0x00), BASE + 0x1234);
+        assert_eq!(word(0x08), BASE + 0x1810);
+        assert_eq!(word(0x10), BASE + 0x1800);
+        assert_eq!(word(0x18), 0);
+        assert_eq!(word(0x20), 0xabab_abab_abab_abab);
+    }
+
+    #[test]
+    fn bad_relocations() {
+        let unknown = pie_image(&[(0x1000, 37, 0)], 24);
+        assert!(matches!(
+            load(&unknown, 0x1000_0000, 0, 0),
+            Err(LoadError::UnsupportedRelocation(37))
+        ));
+
+        let missing = pie_image(&[(0x1000, 9 << 32 | R_X86_64_64 as u64, 0)], 24);
+        assert!(matches!(load(&missing, 0x1000_0000, 0, 0), Err(LoadError::MissingSymbol(9))));
+
+        let unmapped = pie_image(&[(0x8000, R_X86_64_RELATIVE as u64, 0)], 24);
+        assert!(matches!(
+            load(&unmapped, 0x1000_0000, 0, 0),
+            Err(LoadError::UnmappedTarget(0x1000_8000))
+        ));
+
+        // a partial entry, and a section past the end of the file
+        for size in [20, 0x1000] {
+            let image = pie_image(&[(0x1000, R_X86_64_RELATIVE as u64, 0)], size);
+            assert!(matches!(
+                load(&image, 0x1000_0000, 0, 0),
+                Err(LoadError::InvalidSection(".rela.dyn"))
+            ));
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use super::sdt::{read_table, GenericAddress, SdtHeader};
+
+/// Fixed ACPI Description Table, up to the ACPI 2.0 `X_DSDT` field
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct Fadt {
+    header: SdtHeader,
+    firmware_ctrl: u32,
+    dsdt: u32,
+    reserved0: u8,
+    preferred_pm_profile: u8,
+    sci_int: u16,
+    smi_cmd: u32,
+    acpi_enable: u8,
+    acpi_disable: u8,
+    s4bios_req: u8,
+    pstate_cnt: u8,
+    pm1a_evt_blk: u32,
+    pm1b_evt_blk: u32,
+    pm1a_cnt_blk: u32,
+    pm1b_cnt_blk: u32,
+    pm2_cnt_blk: u32,
+    pm_tmr_blk: u32,
+    gpe0_blk: u32,
+    gpe1_blk: u32,
+    pm1_evt_len: u8,
+    pm1_cnt_len: u8,
+    pm2_cnt_len: u8,
+    pm_tmr_len: u8,
+    gpe0_blk_len: u8,
+    gpe1_blk_len: u8,
+    gpe1_base: u8,
+    cst_cnt: u8,
+    p_lvl2_lat: u16,
+    p_lvl3_lat: u16,
+    flush_size: u16,
+    flush_stride: u16,
+    duty_offset: u8,
+    duty_width: u8,
+    day_alrm: u8,
+    mon_alrm: u8,
+    century: u8,
+    iapc_boot_arch: u16,
+    reserved1: u8,
+    flags: u32,
+    reset_reg: GenericAddress,
+    reset_value: u8,
+    arm_boot_arch: u16,
+    minor_version: u8,
+    x_firmware_ctrl: u64,
+    x_dsdt: u64,
+}
+
+/// FADT flag: the PM timer is 32 bits wide (24 bits otherwise)
+const TMR_VAL_EXT: u32 = 1 << 8;
+/// FADT flag: `reset_reg` is supported
+const RESET_REG_SUP: u32 = 1 << 10;
+
+/// IA-PC boot flag: the system has an 8042 keyboard controller
+const BOOT_ARCH_8042: u16 = 1 << 1;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FadtInfo {
+    /// Physical address of the DSDT
+    pub dsdt: u64,
+    /// ISA IRQ of the SCI interrupt
+    pub sci_irq: u16,
+    pub smi_cmd: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_cnt_blk: u32,
+    pub pm1b_cnt_blk: u32,
+    /// IO port of the ACPI PM timer, 0 if absent
+    pub pm_timer_port: u32,
+    pub pm_timer_32bit: bool,
+    /// CMOS index of the RTC century register, 0 if absent
+    pub century: u8,
+    pub has_8042: bool,
+    pub reset_reg: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+/// Parse the FADT at physical address `addr`
+pub unsafe fn parse(addr: u64) -> FadtInfo {
+    let length = SdtHeader::read(addr).length as usize;
+    let fadt: Fadt = read_table(addr, length);
+
+    let flags = fadt.flags;
+    let x_dsdt = fadt.x_dsdt;
+    let reset_reg = fadt.reset_reg;
+
+    FadtInfo {
+        dsdt: if x_dsdt != 0 { x_dsdt } else { fadt.dsdt as u64 },
+        sci_irq: fadt.sci_int,
+        smi_cmd: fadt.smi_cmd,
+        acpi_enable: fadt.acpi_enable,
+        acpi_disable: fadt.acpi_disable,
+        pm1a_cnt_blk: fadt.pm1a_cnt_blk,
+        pm1b_cnt_blk: fadt.pm1b_cnt_blk,
+        pm_timer_port: fadt.pm_tmr_blk,
+        pm_timer_32bit: flags & TMR_VAL_EXT != 0,
+        century: fadt.century,
+        // the flag is only defined since ACPI 2.0
+        has_8042: fadt.header.revision < 2 || fadt.iapc_boot_arch & BOOT_ARCH_8042 != 0,
+        reset_reg: (flags & RESET_REG_SUP != 0 && reset_reg.is_valid()).then_some(reset_reg),
+        reset_value: fadt.reset_value,
+    }
+}
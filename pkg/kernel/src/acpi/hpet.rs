@@ -0,0 +1,51 @@
+use super::sdt::{read_table, GenericAddress, SdtHeader};
+use bit_field::BitField;
+use core::mem::size_of;
+
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct Hpet {
+    header: SdtHeader,
+    event_timer_block_id: u32,
+    base_address: GenericAddress,
+    hpet_number: u8,
+    minimum_tick: u16,
+    page_protection: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HpetInfo {
+    /// Physical address of the HPET registers
+    pub base_address: u64,
+    pub hardware_rev: u8,
+    pub comparator_count: u8,
+    pub counter_64bit: bool,
+    pub legacy_replacement: bool,
+    pub pci_vendor_id: u16,
+    pub hpet_number: u8,
+    /// Minimum clock ticks in periodic mode
+    pub minimum_tick: u16,
+}
+
+/// Parse the HPET table at physical address `addr`
+pub unsafe fn parse(addr: u64) -> Option<HpetInfo> {
+    let hpet: Hpet = read_table(addr, size_of::<Hpet>());
+    let id = hpet.event_timer_block_id;
+    let base = hpet.base_address;
+
+    if base.space_id != GenericAddress::SPACE_SYSTEM_MEMORY || !base.is_valid() {
+        warn!("HPET is not memory mapped, ignored.");
+        return None;
+    }
+
+    Some(HpetInfo {
+        base_address: base.address,
+        hardware_rev: id.get_bits(0..8) as u8,
+        comparator_count: id.get_bits(8..13) as u8 + 1,
+        counter_64bit: id.get_bit(13),
+        legacy_replacement: id.get_bit(15),
+        pci_vendor_id: id.get_bits(16..32) as u16,
+        hpet_number: hpet.hpet_number,
+        minimum_tick: hpet.minimum_tick,
+    })
+}
@@ -0,0 +1,160 @@
+use super::sdt::{read_table, SdtHeader};
+use crate::memory::physical_to_virtual;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct Madt {
+    header: SdtHeader,
+    local_apic_address: u32,
+    flags: u32,
+}
+
+/// MADT flag: the system also has a PC-AT-compatible dual 8259
+const PCAT_COMPAT: u32 = 1;
+
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_OVERRIDE: u8 = 5;
+const ENTRY_LOCAL_X2APIC: u8 = 9;
+
+/// Processor flag: this processor is usable
+const PROCESSOR_ENABLED: u32 = 1;
+/// Processor flag: this processor can be enabled at runtime
+const PROCESSOR_ONLINE_CAPABLE: u32 = 2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+    pub acpi_id: u32,
+    pub apic_id: u32,
+    pub enabled: bool,
+    pub online_capable: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApicInfo {
+    pub id: u8,
+    /// Physical address of the IO APIC registers
+    pub address: u64,
+    /// First global system interrupt handled by this IO APIC
+    pub gsi_base: u32,
+}
+
+/// Polarity of an interrupt input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    /// Conforms to the bus specification
+    Conforming,
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// Trigger mode of an interrupt input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// Conforms to the bus specification
+    Conforming,
+    Edge,
+    Level,
+}
+
+/// Mapping of an ISA IRQ to a global system interrupt
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+#[derive(Debug, Clone)]
+pub struct MadtInfo {
+    pub local_apic_address: u64,
+    pub pcat_compat: bool,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApicInfo>,
+    pub overrides: Vec<InterruptOverride>,
+}
+
+/// Parse the MADT at physical address `addr`
+pub unsafe fn parse(addr: u64) -> MadtInfo {
+    let madt: Madt = read_table(addr, size_of::<Madt>());
+    let length = madt.header.length as u64;
+
+    let mut info = MadtInfo {
+        local_apic_address: madt.local_apic_address as u64,
+        pcat_compat: madt.flags & PCAT_COMPAT != 0,
+        processors: Vec::new(),
+        io_apics: Vec::new(),
+        overrides: Vec::new(),
+    };
+
+    let mut offset = size_of::<Madt>() as u64;
+    while offset + 2 <= length {
+        let entry = physical_to_virtual(addr + offset) as *const u8;
+        let ty = *entry;
+        let len = *entry.add(1) as u64;
+        if len < 2 || offset + len > length {
+            warn!("Malformed MADT entry at offset {:#x}", offset);
+            break;
+        }
+
+        match ty {
+            ENTRY_LOCAL_APIC => {
+                let flags = read::<u32>(entry, 4);
+                info.processors.push(Processor {
+                    acpi_id: *entry.add(2) as u32,
+                    apic_id: *entry.add(3) as u32,
+                    enabled: flags & PROCESSOR_ENABLED != 0,
+                    online_capable: flags & PROCESSOR_ONLINE_CAPABLE != 0,
+                });
+            }
+            ENTRY_IO_APIC => info.io_apics.push(IoApicInfo {
+                id: *entry.add(2),
+                address: read::<u32>(entry, 4) as u64,
+                gsi_base: read::<u32>(entry, 8),
+            }),
+            ENTRY_INTERRUPT_OVERRIDE => {
+                let flags = read::<u16>(entry, 8);
+                info.overrides.push(InterruptOverride {
+                    irq: *entry.add(3),
+                    gsi: read::<u32>(entry, 4),
+                    polarity: match flags & 0b11 {
+                        0b01 => Polarity::ActiveHigh,
+                        0b11 => Polarity::ActiveLow,
+                        _ => Polarity::Conforming,
+                    },
+                    trigger: match (flags >> 2) & 0b11 {
+                        0b01 => TriggerMode::Edge,
+                        0b11 => TriggerMode::Level,
+                        _ => TriggerMode::Conforming,
+                    },
+                });
+            }
+            ENTRY_LOCAL_APIC_OVERRIDE => {
+                info.local_apic_address = read::<u64>(entry, 4);
+            }
+            ENTRY_LOCAL_X2APIC => {
+                let flags = read::<u32>(entry, 8);
+                info.processors.push(Processor {
+                    acpi_id: read::<u32>(entry, 12),
+                    apic_id: read::<u32>(entry, 4),
+                    enabled: flags & PROCESSOR_ENABLED != 0,
+                    online_capable: flags & PROCESSOR_ONLINE_CAPABLE != 0,
+                });
+            }
+            _ => trace!("Skip MADT entry type {}", ty),
+        }
+
+        offset += len;
+    }
+
+    info
+}
+
+#[inline]
+unsafe fn read<T: Copy>(entry: *const u8, offset: usize) -> T {
+    core::ptr::read_unaligned(entry.add(offset) as *const T)
+}
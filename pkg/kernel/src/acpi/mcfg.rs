@@ -0,0 +1,52 @@
+use super::sdt::{read_table, SdtHeader};
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct Mcfg {
+    header: SdtHeader,
+    reserved: u64,
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct McfgAllocation {
+    base_address: u64,
+    segment: u16,
+    start_bus: u8,
+    end_bus: u8,
+    reserved: u32,
+}
+
+/// A PCI Express enhanced configuration space (ECAM) window
+#[derive(Debug, Clone, Copy)]
+pub struct McfgEntry {
+    /// Physical address of the configuration space of `start_bus`
+    pub base_address: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+/// Parse the MCFG table at physical address `addr`
+pub unsafe fn parse(addr: u64) -> Vec<McfgEntry> {
+    let mcfg: Mcfg = read_table(addr, size_of::<Mcfg>());
+    let length = mcfg.header.length as usize;
+
+    let mut entries = Vec::new();
+    let mut offset = size_of::<Mcfg>();
+    while offset + size_of::<McfgAllocation>() <= length {
+        let entry: McfgAllocation =
+            read_table(addr + offset as u64, size_of::<McfgAllocation>());
+        entries.push(McfgEntry {
+            base_address: entry.base_address,
+            segment: entry.segment,
+            start_bus: entry.start_bus,
+            end_bus: entry.end_bus,
+        });
+        offset += size_of::<McfgAllocation>();
+    }
+
+    entries
+}
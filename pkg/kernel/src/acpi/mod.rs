@@ -0,0 +1,223 @@
+//! ACPI (Advanced Configuration and Power Interface) tables
+//!
+//! Find the RSDP from the UEFI configuration tables and parse
+//! the MADT, FADT, HPET and MCFG tables for other subsystems.
+//!
+//! Reference: [OSDev Wiki](https://wiki.osdev.org/RSDP)
+
+mod fadt;
+mod hpet;
+mod madt;
+mod mcfg;
+mod rsdp;
+mod sdt;
+
+pub use fadt::FadtInfo;
+pub use hpet::HpetInfo;
+pub use madt::{InterruptOverride, IoApicInfo, MadtInfo, Polarity, Processor, TriggerMode};
+pub use mcfg::McfgEntry;
+pub use sdt::{GenericAddress, SdtHeader};
+
+use crate::interrupt::{IOAPIC_ADDR, LAPIC_ADDR};
+use alloc::vec;
+use alloc::vec::Vec;
+use boot::{ACPI2_GUID, ACPI_GUID};
+use rsdp::Rsdp;
+use sdt::RootTable;
+
+pub static ACPI: spin::Once<AcpiInfo> = spin::Once::new();
+
+/// Number of ISA IRQs, which MADT interrupt source overrides may remap
+const ISA_IRQ_COUNT: u8 = 16;
+
+#[derive(Debug)]
+pub struct AcpiInfo {
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    root: Option<RootTable>,
+    pub madt: MadtInfo,
+    pub fadt: Option<FadtInfo>,
+    pub hpet: Option<HpetInfo>,
+    /// PCI Express configuration space windows
+    pub mcfg: Vec<McfgEntry>,
+}
+
+pub fn init(boot_info: &'static boot::BootInfo) {
+    let info = match find_rsdp(boot_info) {
+        Some(rsdp) => unsafe { parse(&rsdp) },
+        None => {
+            warn!("ACPI RSDP not found, using default APIC layout.");
+            fallback()
+        }
+    };
+
+    info!(
+        "ACPI {} ({}): {} CPU(s), {} IO APIC(s), LAPIC at {:#x}",
+        if info.revision >= 2 { "2.0+" } else { "1.0" },
+        core::str::from_utf8(&info.oem_id).unwrap_or("??????").trim_end(),
+        info.madt.processors.iter().filter(|p| p.enabled).count(),
+        info.madt.io_apics.len(),
+        info.madt.local_apic_address
+    );
+
+    if let Some(hpet) = &info.hpet {
+        info!(
+            "HPET at {:#x}, {} comparators",
+            hpet.base_address, hpet.comparator_count
+        );
+    }
+
+    for entry in info.mcfg.iter() {
+        info!(
+            "PCIe ECAM at {:#x}, segment {}, bus {}-{}",
+            entry.base_address, entry.segment, entry.start_bus, entry.end_bus
+        );
+    }
+
+    ACPI.call_once(|| info);
+}
+
+/// Get the parsed ACPI information
+#[inline]
+pub fn get_acpi() -> &'static AcpiInfo {
+    ACPI.get().expect("ACPI not initialized")
+}
+
+impl AcpiInfo {
+    /// Find a table by its signature, return its physical address
+    pub fn find_table(&self, signature: &[u8; 4]) -> Option<u64> {
+        self.root.as_ref().and_then(|root| root.find(signature))
+    }
+
+    /// Physical address of the local APIC
+    #[inline]
+    pub fn lapic_addr(&self) -> u64 {
+        self.madt.local_apic_address
+    }
+
+    /// Find the IO APIC handling the global system interrupt `gsi`
+    pub fn io_apic_for(&self, gsi: u32) -> Option<&IoApicInfo> {
+        self.madt
+            .io_apics
+            .iter()
+            .filter(|io| io.gsi_base <= gsi)
+            .max_by_key(|io| io.gsi_base)
+    }
+
+    /// Translate an IRQ line into its global system interrupt
+    ///
+    /// ISA interrupts (0..16) are edge-triggered and active high
+    /// unless the MADT overrides them, higher lines are PCI INTx
+    /// interrupts, which are level-triggered and active low.
+    pub fn irq_route(&self, irq: u8) -> InterruptOverride {
+        let (polarity, trigger) = if irq < ISA_IRQ_COUNT {
+            (Polarity::ActiveHigh, TriggerMode::Edge)
+        } else {
+            (Polarity::ActiveLow, TriggerMode::Level)
+        };
+        let default = InterruptOverride {
+            irq,
+            gsi: irq as u32,
+            polarity,
+            trigger,
+        };
+
+        match self.madt.overrides.iter().find(|o| o.irq == irq) {
+            Some(o) => InterruptOverride {
+                polarity: match o.polarity {
+                    Polarity::Conforming => default.polarity,
+                    polarity => polarity,
+                },
+                trigger: match o.trigger {
+                    TriggerMode::Conforming => default.trigger,
+                    trigger => trigger,
+                },
+                ..*o
+            },
+            None => default,
+        }
+    }
+}
+
+fn find_rsdp(boot_info: &'static boot::BootInfo) -> Option<Rsdp> {
+    let tables = boot_info.system_table.config_table();
+
+    // prefer the ACPI 2.0 RSDP
+    [ACPI2_GUID, ACPI_GUID].iter().find_map(|guid| {
+        tables
+            .iter()
+            .find(|entry| entry.guid == *guid)
+            .and_then(|entry| unsafe { Rsdp::read(entry.address as u64) })
+    })
+}
+
+unsafe fn parse(rsdp: &Rsdp) -> AcpiInfo {
+    let root = if rsdp.has_xsdt() {
+        RootTable::new(rsdp.xsdt_address, true)
+    } else {
+        RootTable::new(rsdp.rsdt_address as u64, false)
+    };
+
+    let mut info = AcpiInfo {
+        revision: rsdp.revision,
+        oem_id: rsdp.oem_id,
+        root,
+        ..fallback()
+    };
+
+    let root = match root {
+        Some(root) => root,
+        None => {
+            warn!("Invalid ACPI root table, using default APIC layout.");
+            return info;
+        }
+    };
+
+    for addr in root.tables() {
+        let header = SdtHeader::read(addr);
+        trace!(
+            "ACPI Table {} at {:#x}, OEM {}",
+            header.signature(),
+            addr,
+            header.oem_id()
+        );
+    }
+
+    match root.find(b"APIC") {
+        Some(addr) => info.madt = madt::parse(addr),
+        None => warn!("ACPI MADT not found, using default APIC layout."),
+    }
+    info.fadt = root.find(b"FACP").map(|addr| fadt::parse(addr));
+    info.hpet = root.find(b"HPET").and_then(|addr| hpet::parse(addr));
+    info.mcfg = root.find(b"MCFG").map(|addr| mcfg::parse(addr)).unwrap_or_default();
+
+    info
+}
+
+/// Default layout of a single core PC
+fn fallback() -> AcpiInfo {
+    AcpiInfo {
+        revision: 0,
+        oem_id: *b"      ",
+        root: None,
+        madt: MadtInfo {
+            local_apic_address: LAPIC_ADDR,
+            pcat_compat: true,
+            processors: vec![Processor {
+                acpi_id: 0,
+                apic_id: 0,
+                enabled: true,
+                online_capable: false,
+            }],
+            io_apics: vec![IoApicInfo {
+                id: 0,
+                address: IOAPIC_ADDR,
+                gsi_base: 0,
+            }],
+            overrides: vec![],
+        },
+        fadt: None,
+        hpet: None,
+        mcfg: vec![],
+    }
+}
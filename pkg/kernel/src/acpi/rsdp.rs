@@ -0,0 +1,43 @@
+use super::sdt::checksum;
+use crate::memory::physical_to_virtual;
+
+/// Root System Description Pointer
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct Rsdp {
+    pub signature: [u8; 8],
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub revision: u8,
+    pub rsdt_address: u32,
+    // fields below are only valid since ACPI 2.0
+    pub length: u32,
+    pub xsdt_address: u64,
+    pub extended_checksum: u8,
+    pub reserved: [u8; 3],
+}
+
+/// Size of the ACPI 1.0 part of RSDP
+const RSDP_V1_LENGTH: usize = 20;
+
+impl Rsdp {
+    /// Read and validate the RSDP at physical address `addr`
+    pub unsafe fn read(addr: u64) -> Option<Self> {
+        let rsdp = core::ptr::read_unaligned(physical_to_virtual(addr) as *const Self);
+
+        if &rsdp.signature != b"RSD PTR " || !checksum(addr, RSDP_V1_LENGTH) {
+            return None;
+        }
+
+        if rsdp.revision >= 2 && !checksum(addr, rsdp.length as usize) {
+            return None;
+        }
+
+        Some(rsdp)
+    }
+
+    /// Whether the XSDT should be used instead of the RSDT
+    pub fn has_xsdt(&self) -> bool {
+        self.revision >= 2 && self.xsdt_address != 0
+    }
+}
@@ -0,0 +1,166 @@
+use crate::memory::physical_to_virtual;
+use core::mem::{size_of, MaybeUninit};
+
+/// Common header of all System Description Tables
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+impl SdtHeader {
+    /// Read the header of the table at physical address `addr`
+    pub unsafe fn read(addr: u64) -> Self {
+        core::ptr::read_unaligned(physical_to_virtual(addr) as *const Self)
+    }
+
+    pub fn signature(&self) -> &str {
+        core::str::from_utf8(&self.signature).unwrap_or("????")
+    }
+
+    pub fn oem_id(&self) -> &str {
+        core::str::from_utf8(&self.oem_id).unwrap_or("??????").trim_end()
+    }
+}
+
+/// ACPI Generic Address Structure
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GenericAddress {
+    pub space_id: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    pub const SPACE_SYSTEM_MEMORY: u8 = 0;
+    pub const SPACE_SYSTEM_IO: u8 = 1;
+
+    pub fn is_valid(&self) -> bool {
+        let address = self.address;
+        address != 0
+    }
+}
+
+/// Check that the `len` bytes at physical address `addr` sum to zero
+pub unsafe fn checksum(addr: u64, len: usize) -> bool {
+    let bytes = core::slice::from_raw_parts(physical_to_virtual(addr) as *const u8, len);
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
+
+/// Copy a table of `len` bytes into `T`, zero-filling the fields
+/// beyond the end of the table (older revisions are shorter).
+pub unsafe fn read_table<T: Copy>(addr: u64, len: usize) -> T {
+    let mut table = MaybeUninit::<T>::zeroed();
+    core::ptr::copy_nonoverlapping(
+        physical_to_virtual(addr) as *const u8,
+        table.as_mut_ptr() as *mut u8,
+        len.min(size_of::<T>()),
+    );
+    table.assume_init()
+}
+
+/// The RSDT or XSDT, listing all other tables
+#[derive(Debug, Clone, Copy)]
+pub struct RootTable {
+    addr: u64,
+    /// 4 for RSDT, 8 for XSDT
+    entry_size: usize,
+    count: usize,
+}
+
+impl RootTable {
+    pub unsafe fn new(addr: u64, extended: bool) -> Option<Self> {
+        let header = SdtHeader::read(addr);
+        let expected = if extended { b"XSDT" } else { b"RSDT" };
+        let length = header.length as usize;
+        // a corrupted length would make the entry count underflow
+        if &header.signature != expected
+            || length < size_of::<SdtHeader>()
+            || !checksum(addr, length)
+        {
+            return None;
+        }
+
+        let entry_size = if extended { 8 } else { 4 };
+        let count = (length - size_of::<SdtHeader>()) / entry_size;
+        Some(Self {
+            addr,
+            entry_size,
+            count,
+        })
+    }
+
+    /// Physical addresses of all tables
+    pub fn tables(&self) -> impl Iterator<Item = u64> + '_ {
+        let entries = self.addr + size_of::<SdtHeader>() as u64;
+        (0..self.count).map(move |i| {
+            let ptr = physical_to_virtual(entries + (i * self.entry_size) as u64);
+            unsafe {
+                if self.entry_size == 8 {
+                    core::ptr::read_unaligned(ptr as *const u64)
+                } else {
+                    core::ptr::read_unaligned(ptr as *const u32) as u64
+                }
+            }
+        })
+    }
+
+    /// Find a table by its signature, checking its checksum
+    pub fn find(&self, signature: &[u8; 4]) -> Option<u64> {
+        self.tables().find(|&addr| unsafe {
+            let header = SdtHeader::read(addr);
+            &header.signature == signature && checksum(addr, header.length as usize)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::address::PHYSICAL_OFFSET;
+    use alloc::vec::Vec;
+
+    /// A root table with `length` in its header and `entries` after it
+    fn root_table(signature: &[u8; 4], length: u32, entries: &[u8]) -> Vec<u8> {
+        let mut table = alloc::vec![0u8; size_of::<SdtHeader>()];
+        table[..4].copy_from_slice(signature);
+        table[4..8].copy_from_slice(&length.to_le_bytes());
+        table.extend_from_slice(entries);
+        let sum = table[..(length as usize).min(table.len())]
+            .iter()
+            .fold(0u8, |sum, b| sum.wrapping_add(*b));
+        table[9] = sum.wrapping_neg();
+        table
+    }
+
+    #[test]
+    fn root_table_length() {
+        // tables are read in place
+        PHYSICAL_OFFSET.call_once(|| 0);
+
+        let entries = [0x1000u64.to_le_bytes(), 0x2000u64.to_le_bytes()].concat();
+        let table = root_table(b"XSDT", 36 + 16, &entries);
+        let root = unsafe { RootTable::new(table.as_ptr() as u64, true) }.unwrap();
+        assert_eq!(root.tables().collect::<Vec<_>>(), [0x1000, 0x2000]);
+        let root = unsafe { RootTable::new(table.as_ptr() as u64, false) };
+        assert!(root.is_none());
+
+        // the checksum holds over the short length, the header does not fit
+        let table = root_table(b"RSDT", 20, &[]);
+        assert!(unsafe { RootTable::new(table.as_ptr() as u64, false) }.is_none());
+        let table = root_table(b"RSDT", 36, &[]);
+        let root = unsafe { RootTable::new(table.as_ptr() as u64, false) }.unwrap();
+        assert_eq!(root.tables().count(), 0);
+    }
+}
@@ -0,0 +1,410 @@
+//! ATA PIO driver for the legacy IDE channels
+//!
+//! Each channel has a master and a slave drive sharing the same registers,
+//! and raises IRQ 14 (primary) or 15 (secondary) when a sector is ready.
+//! The IRQ goes to the CPU that issued the command, which halts until then.
+//!
+//! Reference: [OSDev Wiki](https://wiki.osdev.org/ATA_PIO_Mode)
+
+use super::*;
+use crate::interrupt::clock::Timeout;
+use crate::interrupt::{self, consts::Irq};
+use alloc::format;
+use alloc::string::String;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+// registers, offsets from the IO base
+const REG_DATA: u16 = 0;
+const REG_ERROR: u16 = 1;
+const REG_SECTOR_COUNT: u16 = 2;
+const REG_LBA_LOW: u16 = 3;
+const REG_LBA_MID: u16 = 4;
+const REG_LBA_HIGH: u16 = 5;
+const REG_DRIVE: u16 = 6;
+const REG_STATUS: u16 = 7;
+const REG_COMMAND: u16 = 7;
+
+// commands
+const CMD_READ_PIO: u8 = 0x20;
+const CMD_READ_PIO_EXT: u8 = 0x24;
+const CMD_WRITE_PIO: u8 = 0x30;
+const CMD_WRITE_PIO_EXT: u8 = 0x34;
+const CMD_CACHE_FLUSH: u8 = 0xE7;
+const CMD_CACHE_FLUSH_EXT: u8 = 0xEA;
+const CMD_IDENTIFY: u8 = 0xEC;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy)]
+    struct Status: u8 {
+        const ERR = 1 << 0;
+        const DRQ = 1 << 3;
+        const DF = 1 << 5;
+        const DRDY = 1 << 6;
+        const BSY = 1 << 7;
+    }
+}
+
+/// Device control register bit to disable interrupts
+const CONTROL_NIEN: u8 = 1 << 1;
+
+/// Milliseconds before a command times out, drives may have to spin up
+const TIMEOUT_MS: u64 = 10_000;
+
+/// Sectors per command, the limit of a 28-bit transfer
+const MAX_SECTORS: usize = 256;
+
+struct Channel {
+    io: u16,
+    ctrl: u16,
+    irq: u8,
+    /// Set by the IRQ handler, cleared before each command
+    irq_fired: AtomicBool,
+    /// Held while a command is in progress on either drive
+    lock: Mutex<()>,
+}
+
+static CHANNELS: [Channel; 2] = [
+    Channel::new(0x1F0, 0x3F6, Irq::Ide0 as u8),
+    Channel::new(0x170, 0x376, Irq::Ide1 as u8),
+];
+
+impl Channel {
+    const fn new(io: u16, ctrl: u16, irq: u8) -> Self {
+        Self {
+            io,
+            ctrl,
+            irq,
+            irq_fired: AtomicBool::new(false),
+            lock: Mutex::new(()),
+        }
+    }
+
+    #[inline]
+    fn read(&self, reg: u16) -> u8 {
+        unsafe { Port::<u8>::new(self.io + reg).read() }
+    }
+
+    #[inline]
+    fn write(&self, reg: u16, value: u8) {
+        unsafe { Port::<u8>::new(self.io + reg).write(value) }
+    }
+
+    /// Read the alternate status, which does not acknowledge the interrupt
+    #[inline]
+    fn alt_status(&self) -> Status {
+        Status::from_bits_retain(unsafe { Port::<u8>::new(self.ctrl).read() })
+    }
+
+    fn read_sector(&self, buf: &mut [u8]) {
+        let mut data = Port::<u16>::new(self.io + REG_DATA);
+        for word in buf.chunks_exact_mut(2) {
+            word.copy_from_slice(&unsafe { data.read() }.to_le_bytes());
+        }
+    }
+
+    fn write_sector(&self, buf: &[u8]) {
+        let mut data = Port::<u16>::new(self.io + REG_DATA);
+        for word in buf.chunks_exact(2) {
+            unsafe { data.write(u16::from_le_bytes([word[0], word[1]])) };
+        }
+    }
+
+    /// Wait 400ns for the status to be valid after selecting a drive
+    fn delay(&self) {
+        for _ in 0..4 {
+            self.alt_status();
+        }
+    }
+
+    fn select(&self, slave: bool, head: u8) {
+        self.write(REG_DRIVE, head | (slave as u8) << 4);
+        self.delay();
+    }
+
+    fn wait_not_busy(&self) -> BlockResult<Status> {
+        let mut timeout = Timeout::new(TIMEOUT_MS);
+        loop {
+            let status = self.alt_status();
+            if !status.contains(Status::BSY) {
+                return Ok(status);
+            }
+            if timeout.expired() {
+                return Err(BlockError::Timeout);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Wait until the drive has data to transfer
+    fn wait_drq(&self) -> BlockResult {
+        let mut timeout = Timeout::new(TIMEOUT_MS);
+        loop {
+            let status = self.alt_status();
+            if status.intersects(Status::ERR | Status::DF) {
+                return Err(BlockError::DeviceError);
+            }
+            if !status.contains(Status::BSY) && status.contains(Status::DRQ) {
+                return Ok(());
+            }
+            if timeout.expired() {
+                return Err(BlockError::Timeout);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Wait for the interrupt of the running command, polling the
+    /// status instead if interrupts are disabled on this CPU.
+    ///
+    /// The CPU halts until the next interrupt between checks. The IRQ is
+    /// routed to the CPU that issued the command, if the process has moved
+    /// to another one since, the timer wakes it instead.
+    fn wait_irq(&self) -> BlockResult {
+        if interrupts::are_enabled() {
+            let mut timeout = Timeout::new(TIMEOUT_MS);
+            loop {
+                // no interrupt may come between the check and `hlt`
+                interrupts::disable();
+                if self.irq_fired.swap(false, Ordering::AcqRel) {
+                    interrupts::enable();
+                    break;
+                }
+                if timeout.expired() {
+                    interrupts::enable();
+                    return Err(BlockError::Timeout);
+                }
+                interrupts::enable_and_hlt();
+            }
+        }
+
+        let status = self.wait_not_busy()?;
+        if status.intersects(Status::ERR | Status::DF) {
+            return Err(BlockError::DeviceError);
+        }
+        Ok(())
+    }
+
+    /// Issue a read or write of `count` sectors at `lba`
+    fn command(&self, slave: bool, lba48: bool, lba: u64, count: usize, cmd: u8) -> BlockResult {
+        self.wait_not_busy()?;
+        self.irq_fired.store(false, Ordering::Release);
+        interrupt::irq::route_to_current(self.irq);
+
+        if lba48 {
+            self.select(slave, 0x40);
+            // high bytes first
+            self.write(REG_SECTOR_COUNT, (count >> 8) as u8);
+            self.write(REG_LBA_LOW, (lba >> 24) as u8);
+            self.write(REG_LBA_MID, (lba >> 32) as u8);
+            self.write(REG_LBA_HIGH, (lba >> 40) as u8);
+        } else {
+            self.select(slave, 0xE0 | ((lba >> 24) & 0xF) as u8);
+        }
+
+        // a count of 0 means 256 sectors for 28-bit commands
+        self.write(REG_SECTOR_COUNT, count as u8);
+        self.write(REG_LBA_LOW, lba as u8);
+        self.write(REG_LBA_MID, (lba >> 8) as u8);
+        self.write(REG_LBA_HIGH, (lba >> 16) as u8);
+        self.write(REG_COMMAND, cmd);
+        Ok(())
+    }
+}
+
+fn primary_irq() {
+    CHANNELS[0].read(REG_STATUS);
+    CHANNELS[0].irq_fired.store(true, Ordering::Release);
+}
+
+fn secondary_irq() {
+    CHANNELS[1].read(REG_STATUS);
+    CHANNELS[1].irq_fired.store(true, Ordering::Release);
+}
+
+pub struct AtaDrive {
+    name: String,
+    channel: &'static Channel,
+    slave: bool,
+    lba48: bool,
+    sectors: u64,
+    model: String,
+}
+
+impl AtaDrive {
+    /// Send IDENTIFY to a drive, None if there is no ATA drive
+    fn identify(channel: &'static Channel, slave: bool, name: String) -> Option<Self> {
+        let _guard = channel.lock.lock();
+
+        channel.select(slave, 0xA0);
+        channel.write(REG_SECTOR_COUNT, 0);
+        channel.write(REG_LBA_LOW, 0);
+        channel.write(REG_LBA_MID, 0);
+        channel.write(REG_LBA_HIGH, 0);
+        channel.write(REG_COMMAND, CMD_IDENTIFY);
+
+        if channel.read(REG_STATUS) == 0 {
+            return None;
+        }
+        channel.wait_not_busy().ok()?;
+
+        // ATAPI and SATA devices set the signature in LBA mid and high
+        if channel.read(REG_LBA_MID) != 0 || channel.read(REG_LBA_HIGH) != 0 {
+            debug!("ATA {}: not an ATA drive, ignored.", name);
+            return None;
+        }
+        channel.wait_drq().ok()?;
+
+        let mut data = [0u8; BLOCK_SIZE];
+        channel.read_sector(&mut data);
+        // clear the interrupt raised by IDENTIFY
+        channel.read(REG_STATUS);
+
+        let word = |idx: usize| u16::from_le_bytes([data[idx * 2], data[idx * 2 + 1]]);
+
+        let lba48 = word(83) & (1 << 10) != 0;
+        let sectors = if lba48 {
+            (0..4).fold(0u64, |acc, i| acc | (word(100 + i) as u64) << (16 * i))
+        } else {
+            word(60) as u64 | (word(61) as u64) << 16
+        };
+
+        // the model string is big endian in each word
+        let model: String = (27..47)
+            .flat_map(|i| word(i).to_be_bytes())
+            .map(|b| b as char)
+            .collect();
+
+        Some(Self {
+            name,
+            channel,
+            slave,
+            lba48,
+            sectors,
+            model: model.trim().into(),
+        })
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Read up to `MAX_SECTORS` sectors with a single command
+    fn read_chunk(&self, lba: u64, buf: &mut [u8]) -> BlockResult {
+        let channel = self.channel;
+        let cmd = if self.lba48 { CMD_READ_PIO_EXT } else { CMD_READ_PIO };
+        channel.command(self.slave, self.lba48, lba, buf.len() / BLOCK_SIZE, cmd)?;
+
+        for sector in buf.chunks_exact_mut(BLOCK_SIZE) {
+            channel.wait_irq()?;
+            channel.wait_drq()?;
+            channel.read_sector(sector);
+        }
+        Ok(())
+    }
+
+    /// Write up to `MAX_SECTORS` sectors with a single command
+    fn write_chunk(&self, lba: u64, buf: &[u8]) -> BlockResult {
+        let channel = self.channel;
+        let cmd = if self.lba48 { CMD_WRITE_PIO_EXT } else { CMD_WRITE_PIO };
+        channel.command(self.slave, self.lba48, lba, buf.len() / BLOCK_SIZE, cmd)?;
+
+        for sector in buf.chunks_exact(BLOCK_SIZE) {
+            channel.wait_drq()?;
+            channel.irq_fired.store(false, Ordering::Release);
+            channel.write_sector(sector);
+            channel.wait_irq()?;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult {
+        check_range(self, lba, buf.len())?;
+        if !self.lba48 && lba + (buf.len() / BLOCK_SIZE) as u64 > 1 << 28 {
+            return Err(BlockError::OutOfRange);
+        }
+
+        let _guard = self.channel.lock.lock();
+        for (i, chunk) in buf.chunks_mut(MAX_SECTORS * BLOCK_SIZE).enumerate() {
+            self.read_chunk(lba + (i * MAX_SECTORS) as u64, chunk)?;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult {
+        check_range(self, lba, buf.len())?;
+        if !self.lba48 && lba + (buf.len() / BLOCK_SIZE) as u64 > 1 << 28 {
+            return Err(BlockError::OutOfRange);
+        }
+
+        let _guard = self.channel.lock.lock();
+        for (i, chunk) in buf.chunks(MAX_SECTORS * BLOCK_SIZE).enumerate() {
+            self.write_chunk(lba + (i * MAX_SECTORS) as u64, chunk)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> BlockResult {
+        let channel = self.channel;
+        let _guard = channel.lock.lock();
+
+        channel.wait_not_busy()?;
+        channel.irq_fired.store(false, Ordering::Release);
+        interrupt::irq::route_to_current(channel.irq);
+        channel.select(self.slave, 0xA0);
+        channel.write(
+            REG_COMMAND,
+            if self.lba48 {
+                CMD_CACHE_FLUSH_EXT
+            } else {
+                CMD_CACHE_FLUSH
+            },
+        );
+        channel.wait_irq()
+    }
+}
+
+/// Probe the drives on both IDE channels and register them as `hda` to `hdd`
+pub fn init() {
+    let handlers: [fn(); 2] = [primary_irq, secondary_irq];
+
+    for (idx, channel) in CHANNELS.iter().enumerate() {
+        // a floating bus reads all ones
+        if channel.read(REG_STATUS) == 0xFF {
+            continue;
+        }
+
+        // enable interrupts from the channel
+        unsafe { Port::<u8>::new(channel.ctrl).write(0) };
+        if interrupt::request_irq(channel.irq, handlers[idx], "ata").is_err() {
+            unsafe { Port::<u8>::new(channel.ctrl).write(CONTROL_NIEN) };
+            continue;
+        }
+
+        for slave in [false, true] {
+            let name = format!("hd{}", (b'a' + (idx * 2 + slave as usize) as u8) as char);
+            if let Some(drive) = AtaDrive::identify(channel, slave, name) {
+                info!(
+                    "ATA {}: {}, {} sectors{}",
+                    drive.name,
+                    drive.model,
+                    drive.sectors,
+                    if drive.lba48 { ", LBA48" } else { "" }
+                );
+                register(alloc::sync::Arc::new(drive));
+            }
+        }
+    }
+}
@@ -0,0 +1,411 @@
+//! Block cache
+//!
+//! Keeps recently used blocks of a device in memory, evicting the least
+//! recently used one when full. Writes only mark blocks dirty, they reach
+//! the device on eviction or [`BlockDevice::flush`].
+//!
+//! The lock is never held across device I/O, blocks being read or written
+//! back are marked busy and waited for instead.
+
+use super::*;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use spin::{Mutex, MutexGuard};
+
+struct CachedBlock {
+    data: Box<[u8; BLOCK_SIZE]>,
+    dirty: bool,
+    /// Value of the access clock when last used
+    last_used: u64,
+    /// Being read from or written to the device, the data must not change
+    busy: bool,
+}
+
+struct Inner {
+    blocks: BTreeMap<u64, CachedBlock>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl Inner {
+    #[inline]
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    #[inline]
+    fn is_busy(&self, lba: u64) -> bool {
+        self.blocks.get(&lba).is_some_and(|b| b.busy)
+    }
+}
+
+pub struct BlockCache {
+    dev: Arc<dyn BlockDevice>,
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+impl BlockCache {
+    /// Cache up to `capacity` blocks of `dev`
+    pub fn new(dev: Arc<dyn BlockDevice>, capacity: usize) -> Self {
+        Self {
+            dev,
+            capacity: capacity.max(1),
+            inner: Mutex::new(Inner {
+                blocks: BTreeMap::new(),
+                clock: 0,
+                hits: 0,
+                misses: 0,
+            }),
+        }
+    }
+
+    /// The cached device
+    #[inline]
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.dev
+    }
+
+    /// Cache hits and misses
+    pub fn stats(&self) -> (u64, u64) {
+        let inner = self.inner.lock();
+        (inner.hits, inner.misses)
+    }
+
+    /// Lock the cache once block `lba` is not busy
+    ///
+    /// the lock is never held across device I/O, so it is released while waiting
+    fn lock_idle(&self, lba: u64) -> MutexGuard<'_, Inner> {
+        loop {
+            let inner = self.inner.lock();
+            if !inner.is_busy(lba) {
+                return inner;
+            }
+            drop(inner);
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Drop the least recently used blocks until the cache fits its capacity,
+    /// writing back the dirty ones with the lock released
+    ///
+    /// busy blocks are skipped, the cache may stay over capacity until they are done.
+    fn shrink<'a>(&'a self, mut inner: MutexGuard<'a, Inner>) -> BlockResult {
+        while inner.blocks.len() > self.capacity {
+            let victim = inner
+                .blocks
+                .iter()
+                .filter(|(_, b)| !b.busy)
+                .min_by_key(|(_, b)| b.last_used)
+                .map(|(&lba, b)| (lba, b.dirty));
+
+            let lba = match victim {
+                Some((lba, false)) => {
+                    inner.blocks.remove(&lba);
+                    continue;
+                }
+                Some((lba, true)) => lba,
+                None => break,
+            };
+
+            let block = inner.blocks.get_mut(&lba).unwrap();
+            block.busy = true;
+            let data = block.data.clone();
+            drop(inner);
+
+            let result = self.dev.write_blocks(lba, data.as_slice());
+
+            inner = self.inner.lock();
+            if let Err(err) = result {
+                // kept dirty, it is written back on the next try
+                inner.blocks.get_mut(&lba).unwrap().busy = false;
+                return Err(err);
+            }
+            inner.blocks.remove(&lba);
+        }
+        Ok(())
+    }
+
+    /// Write back all dirty blocks without flushing the device
+    ///
+    /// waits for blocks being written back by eviction, so all writes made
+    /// before the call are on the device when it returns.
+    pub fn sync(&self) -> BlockResult {
+        let mut next = 0;
+        loop {
+            let mut inner = self.inner.lock();
+            let dirty = inner
+                .blocks
+                .range(next..)
+                .find(|(_, b)| b.dirty)
+                .map(|(&lba, b)| (lba, b.busy));
+
+            let lba = match dirty {
+                Some((_, true)) => {
+                    drop(inner);
+                    core::hint::spin_loop();
+                    continue;
+                }
+                Some((lba, false)) => lba,
+                None => return Ok(()),
+            };
+
+            let block = inner.blocks.get_mut(&lba).unwrap();
+            block.busy = true;
+            let data = block.data.clone();
+            drop(inner);
+
+            let result = self.dev.write_blocks(lba, data.as_slice());
+
+            let mut inner = self.inner.lock();
+            let block = inner.blocks.get_mut(&lba).unwrap();
+            block.busy = false;
+            result?;
+            block.dirty = false;
+            next = lba + 1;
+        }
+    }
+
+    /// Drop all cached blocks after writing back the dirty ones
+    pub fn invalidate(&self) -> BlockResult {
+        self.sync()?;
+        self.inner.lock().blocks.retain(|_, b| b.busy);
+        Ok(())
+    }
+}
+
+impl BlockDevice for BlockCache {
+    fn name(&self) -> &str {
+        self.dev.name()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.dev.block_count()
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult {
+        check_range(self, lba, buf.len())?;
+
+        let count = buf.len() / BLOCK_SIZE;
+        let mut i = 0;
+        while i < count {
+            let start = lba + i as u64;
+            let mut guard = self.lock_idle(start);
+            let inner = &mut *guard;
+            if let Some(block) = inner.blocks.get_mut(&start) {
+                inner.clock += 1;
+                block.last_used = inner.clock;
+                buf[i * BLOCK_SIZE..][..BLOCK_SIZE].copy_from_slice(block.data.as_slice());
+                inner.hits += 1;
+                i += 1;
+                continue;
+            }
+
+            // claim the run of missing blocks as busy and read it with one request,
+            // at most as many blocks as the cache holds
+            let run = (i..count)
+                .take(self.capacity)
+                .take_while(|&j| !inner.blocks.contains_key(&(lba + j as u64)))
+                .count();
+            let clock = inner.tick();
+            for j in 0..run as u64 {
+                inner.blocks.insert(
+                    start + j,
+                    CachedBlock {
+                        data: Box::new([0; BLOCK_SIZE]),
+                        dirty: false,
+                        last_used: clock,
+                        busy: true,
+                    },
+                );
+            }
+            inner.misses += run as u64;
+            drop(guard);
+
+            let chunk = &mut buf[i * BLOCK_SIZE..(i + run) * BLOCK_SIZE];
+            let result = self.dev.read_blocks(start, chunk);
+
+            let mut inner = self.inner.lock();
+            for (j, data) in chunk.chunks(BLOCK_SIZE).enumerate() {
+                let lba = start + j as u64;
+                if result.is_err() {
+                    inner.blocks.remove(&lba);
+                    continue;
+                }
+                let block = inner.blocks.get_mut(&lba).unwrap();
+                block.data.copy_from_slice(data);
+                block.busy = false;
+            }
+            result?;
+            self.shrink(inner)?;
+            i += run;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult {
+        check_range(self, lba, buf.len())?;
+        if self.dev.is_read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+
+        for (i, chunk) in buf.chunks(BLOCK_SIZE).enumerate() {
+            // whole blocks are overwritten, no need to read them first
+            let lba = lba + i as u64;
+            let mut inner = self.lock_idle(lba);
+            let clock = inner.tick();
+            let block = inner.blocks.entry(lba).or_insert_with(|| CachedBlock {
+                data: Box::new([0; BLOCK_SIZE]),
+                dirty: false,
+                last_used: clock,
+                busy: false,
+            });
+            block.data.copy_from_slice(chunk);
+            block.dirty = true;
+            block.last_used = clock;
+            self.shrink(inner)?;
+        }
+        Ok(())
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.dev.is_read_only()
+    }
+
+    fn flush(&self) -> BlockResult {
+        self.sync()?;
+        self.dev.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ramdisk::tests::block_of;
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A RAM disk counting the requests that reach it
+    struct Counted {
+        disk: RamDisk,
+        reads: AtomicUsize,
+        writes: AtomicUsize,
+    }
+
+    impl Counted {
+        fn new(blocks: usize) -> Arc<Self> {
+            Arc::new(Self {
+                disk: RamDisk::new("ram", blocks),
+                reads: AtomicUsize::new(0),
+                writes: AtomicUsize::new(0),
+            })
+        }
+    }
+
+    impl BlockDevice for Counted {
+        fn name(&self) -> &str {
+            self.disk.name()
+        }
+
+        fn block_count(&self) -> u64 {
+            self.disk.block_count()
+        }
+
+        fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.disk.read_blocks(lba, buf)
+        }
+
+        fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult {
+            self.writes.fetch_add(1, Ordering::Relaxed);
+            self.disk.write_blocks(lba, buf)
+        }
+    }
+
+    #[test]
+    fn cache_write_back() {
+        let dev = Counted::new(16);
+        let cache = BlockCache::new(dev.clone(), 4);
+
+        cache.write_blocks(2, &[0xaa; 2 * BLOCK_SIZE]).unwrap();
+        assert_eq!(dev.writes.load(Ordering::Relaxed), 0);
+        assert_eq!(block_of(&dev.disk, 3), [0; BLOCK_SIZE]);
+        assert_eq!(block_of(&cache, 3), [0xaa; BLOCK_SIZE]);
+        assert_eq!(dev.reads.load(Ordering::Relaxed), 0);
+
+        cache.flush().unwrap();
+        assert_eq!(dev.writes.load(Ordering::Relaxed), 2);
+        assert_eq!(block_of(&dev.disk, 2), [0xaa; BLOCK_SIZE]);
+        assert_eq!(block_of(&dev.disk, 3), [0xaa; BLOCK_SIZE]);
+
+        // clean blocks are not written again
+        cache.flush().unwrap();
+        assert_eq!(dev.writes.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn cache_eviction() {
+        let dev = Counted::new(16);
+        let cache = BlockCache::new(dev.clone(), 2);
+
+        cache.write_blocks(0, &[1; BLOCK_SIZE]).unwrap();
+        cache.write_blocks(1, &[2; BLOCK_SIZE]).unwrap();
+        // block 1 becomes the least recently used
+        assert_eq!(block_of(&cache, 0), [1; BLOCK_SIZE]);
+
+        cache.write_blocks(2, &[3; BLOCK_SIZE]).unwrap();
+        assert_eq!(block_of(&dev.disk, 0), [0; BLOCK_SIZE]);
+        assert_eq!(block_of(&dev.disk, 1), [2; BLOCK_SIZE]);
+
+        // block 1 is read back from the disk, evicting block 0
+        assert_eq!(block_of(&cache, 1), [2; BLOCK_SIZE]);
+        assert_eq!(block_of(&dev.disk, 0), [1; BLOCK_SIZE]);
+        assert_eq!(cache.stats(), (1, 1));
+
+        cache.invalidate().unwrap();
+        assert_eq!(block_of(&dev.disk, 2), [3; BLOCK_SIZE]);
+    }
+
+    #[test]
+    fn cache_batches_misses() {
+        let dev = Counted::new(16);
+        for lba in 0..8 {
+            dev.disk.write_blocks(lba, &[lba as u8; BLOCK_SIZE]).unwrap();
+        }
+        let cache = BlockCache::new(dev.clone(), 8);
+
+        block_of(&cache, 3);
+        assert_eq!(dev.reads.load(Ordering::Relaxed), 1);
+
+        // blocks 0..3 and 4..8 are read with one request each
+        let mut buf = [0u8; 8 * BLOCK_SIZE];
+        cache.read_blocks(0, &mut buf).unwrap();
+        assert_eq!(dev.reads.load(Ordering::Relaxed), 3);
+        for (lba, block) in buf.chunks(BLOCK_SIZE).enumerate() {
+            assert!(block.iter().all(|&b| b == lba as u8));
+        }
+        assert_eq!(cache.stats(), (1, 8));
+
+        cache.read_blocks(0, &mut buf).unwrap();
+        assert_eq!(dev.reads.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn cache_larger_than_capacity() {
+        let dev = Counted::new(16);
+        let cache = BlockCache::new(dev.clone(), 2);
+
+        let data: Vec<u8> = (0..6 * BLOCK_SIZE).map(|i| (i / BLOCK_SIZE) as u8).collect();
+        cache.write_blocks(4, &data).unwrap();
+        let mut buf = vec![0u8; data.len()];
+        cache.read_blocks(4, &mut buf).unwrap();
+        assert_eq!(buf, data);
+
+        cache.flush().unwrap();
+        dev.disk.read_blocks(4, &mut buf).unwrap();
+        assert_eq!(buf, data);
+    }
+}
@@ -0,0 +1,105 @@
+//! Block devices
+//!
+//! Storage drivers implement [`BlockDevice`] and [`register`] their disks,
+//! which can then be found by name by the rest of the kernel.
+//! The partitions on each disk are registered as devices of their own.
+
+pub mod ata;
+mod cache;
+mod partition;
+mod ramdisk;
+pub mod virtio_blk;
+
+pub use cache::BlockCache;
+pub use partition::{Partition, PartitionKind};
+pub use ramdisk::RamDisk;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::RwLock;
+
+/// Size of a sector, the unit of all block device operations
+pub const BLOCK_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The blocks are beyond the end of the device
+    OutOfRange,
+    /// The buffer is not a whole number of blocks
+    InvalidBuffer,
+    /// The device did not respond in time
+    Timeout,
+    /// The device reported an error
+    DeviceError,
+    ReadOnly,
+}
+
+pub type BlockResult<T = ()> = Result<T, BlockError>;
+
+pub trait BlockDevice: Send + Sync {
+    /// Name of the device, like `hda`
+    fn name(&self) -> &str;
+
+    /// Size of a block in bytes, all devices use `BLOCK_SIZE` for now
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    /// Number of blocks of `BLOCK_SIZE` bytes
+    fn block_count(&self) -> u64;
+
+    /// Read `buf.len() / BLOCK_SIZE` blocks starting at `lba`
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult;
+
+    /// Write `buf.len() / BLOCK_SIZE` blocks starting at `lba`
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult;
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Write back any data cached by the device
+    fn flush(&self) -> BlockResult {
+        Ok(())
+    }
+}
+
+/// Check that `len` bytes starting at `lba` are whole blocks within `dev`
+pub fn check_range(dev: &dyn BlockDevice, lba: u64, len: usize) -> BlockResult<u64> {
+    if len % BLOCK_SIZE != 0 {
+        return Err(BlockError::InvalidBuffer);
+    }
+
+    let count = (len / BLOCK_SIZE) as u64;
+    match lba.checked_add(count) {
+        Some(end) if end <= dev.block_count() => Ok(count),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+static DEVICES: RwLock<Vec<Arc<dyn BlockDevice>>> = RwLock::new(Vec::new());
+
+/// Make the disk `dev` and its partitions available to the rest of the kernel
+pub fn register(dev: Arc<dyn BlockDevice>) {
+    add(dev.clone());
+
+    for part in partition::scan(&dev) {
+        add(Arc::new(part));
+    }
+}
+
+fn add(dev: Arc<dyn BlockDevice>) {
+    let (size, unit) = crate::humanized_size(dev.block_count() * BLOCK_SIZE as u64);
+    info!("Block device {}: {:.*} {}", dev.name(), 1, size, unit);
+    DEVICES.write().push(dev);
+}
+
+/// Find a block device by its name
+pub fn get(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES.read().iter().find(|dev| dev.name() == name).cloned()
+}
+
+/// All registered block devices
+pub fn devices() -> Vec<Arc<dyn BlockDevice>> {
+    DEVICES.read().clone()
+}
@@ -0,0 +1,344 @@
+//! MBR and GPT partition tables
+//!
+//! Reference: [OSDev Wiki](https://wiki.osdev.org/Partition_Table),
+//! [GPT](https://wiki.osdev.org/GPT)
+
+use super::*;
+use alloc::format;
+use alloc::string::String;
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const MBR_TABLE_OFFSET: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+
+const MBR_TYPE_EMPTY: u8 = 0x00;
+const MBR_TYPE_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+
+/// Logical partitions followed in an extended partition
+const MAX_LOGICAL: usize = 64;
+
+/// GPT entries scanned, as many as the usual 16KiB entry array holds
+const MAX_GPT_ENTRIES: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// MBR partition type
+    Mbr(u8),
+    /// GPT partition type GUID, in its on-disk byte order
+    Gpt([u8; 16]),
+}
+
+/// A range of blocks of a disk
+pub struct Partition {
+    name: String,
+    disk: Arc<dyn BlockDevice>,
+    start: u64,
+    count: u64,
+    kind: PartitionKind,
+}
+
+impl Partition {
+    #[inline]
+    pub fn kind(&self) -> PartitionKind {
+        self.kind
+    }
+
+    /// First block on the disk
+    #[inline]
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_count(&self) -> u64 {
+        self.count
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult {
+        check_range(self, lba, buf.len())?;
+        self.disk.read_blocks(self.start + lba, buf)
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult {
+        check_range(self, lba, buf.len())?;
+        self.disk.write_blocks(self.start + lba, buf)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.disk.is_read_only()
+    }
+
+    fn flush(&self) -> BlockResult {
+        self.disk.flush()
+    }
+}
+
+#[inline]
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+#[inline]
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+/// Find the partitions on `disk`, named after the disk and their number
+pub fn scan(disk: &Arc<dyn BlockDevice>) -> Vec<Partition> {
+    let mut mbr = [0u8; BLOCK_SIZE];
+    if disk.block_count() == 0 || disk.read_blocks(0, &mut mbr).is_err() {
+        return Vec::new();
+    }
+
+    if mbr[510..512] != MBR_SIGNATURE {
+        return Vec::new();
+    }
+
+    let entries = || {
+        (0..4).map(|i| &mbr[MBR_TABLE_OFFSET + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE])
+    };
+
+    let found = if entries().any(|e| e[4] == MBR_TYPE_GPT_PROTECTIVE) {
+        scan_gpt(disk)
+    } else {
+        scan_mbr(disk, &mbr)
+    };
+
+    let disk_end = disk.block_count();
+    found
+        .into_iter()
+        .filter(|&(_, start, count, _)| {
+            count > 0 && start.checked_add(count).is_some_and(|end| end <= disk_end)
+        })
+        .map(|(number, start, count, kind)| Partition {
+            name: format!("{}{}", disk.name(), number),
+            disk: disk.clone(),
+            start,
+            count,
+            kind,
+        })
+        .collect()
+}
+
+type Found = (usize, u64, u64, PartitionKind);
+
+fn scan_mbr(disk: &Arc<dyn BlockDevice>, mbr: &[u8]) -> Vec<Found> {
+    let mut found = Vec::new();
+
+    for i in 0..4 {
+        let entry = &mbr[MBR_TABLE_OFFSET + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        let ty = entry[4];
+        let start = u32_at(entry, 8) as u64;
+        let count = u32_at(entry, 12) as u64;
+
+        if ty == MBR_TYPE_EMPTY {
+            continue;
+        }
+
+        if MBR_TYPE_EXTENDED.contains(&ty) {
+            scan_logical(disk, start, &mut found);
+        } else {
+            found.push((i + 1, start, count, PartitionKind::Mbr(ty)));
+        }
+    }
+
+    found
+}
+
+/// Follow the chain of extended boot records, logical partitions are numbered from 5
+fn scan_logical(disk: &Arc<dyn BlockDevice>, extended: u64, found: &mut Vec<Found>) {
+    let mut ebr_lba = extended;
+    let mut ebr = [0u8; BLOCK_SIZE];
+
+    for number in 5..5 + MAX_LOGICAL {
+        if disk.read_blocks(ebr_lba, &mut ebr).is_err() || ebr[510..512] != MBR_SIGNATURE {
+            break;
+        }
+
+        // the first entry is relative to this EBR, the second to the extended partition
+        let entry = &ebr[MBR_TABLE_OFFSET..][..MBR_ENTRY_SIZE];
+        if entry[4] != MBR_TYPE_EMPTY {
+            let start = ebr_lba + u32_at(entry, 8) as u64;
+            found.push((number, start, u32_at(entry, 12) as u64, PartitionKind::Mbr(entry[4])));
+        }
+
+        let next = &ebr[MBR_TABLE_OFFSET + MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        if next[4] == MBR_TYPE_EMPTY {
+            break;
+        }
+        ebr_lba = extended + u32_at(next, 8) as u64;
+    }
+}
+
+fn scan_gpt(disk: &Arc<dyn BlockDevice>) -> Vec<Found> {
+    let mut found = Vec::new();
+
+    let mut header = [0u8; BLOCK_SIZE];
+    if disk.read_blocks(1, &mut header).is_err() || &header[0..8] != GPT_SIGNATURE {
+        warn!("{}: protective MBR without a GPT header.", disk.name());
+        return found;
+    }
+
+    let entries_lba = u64_at(&header, 72);
+    let entry_count = u32_at(&header, 80) as usize;
+    let entry_size = u32_at(&header, 84) as usize;
+    if !(128..=BLOCK_SIZE).contains(&entry_size) || BLOCK_SIZE % entry_size != 0 {
+        warn!("{}: unsupported GPT entry size {}.", disk.name(), entry_size);
+        return found;
+    }
+
+    // the header is not checksummed here, do not trust a huge count
+    if entry_count > MAX_GPT_ENTRIES {
+        warn!(
+            "{}: {} GPT entries, only the first {} are scanned.",
+            disk.name(),
+            entry_count,
+            MAX_GPT_ENTRIES
+        );
+    }
+    let entry_count = entry_count.min(MAX_GPT_ENTRIES);
+
+    let per_block = BLOCK_SIZE / entry_size;
+    let mut block = [0u8; BLOCK_SIZE];
+    for idx in 0..entry_count {
+        if idx % per_block == 0
+            && disk
+                .read_blocks(entries_lba + (idx / per_block) as u64, &mut block)
+                .is_err()
+        {
+            break;
+        }
+
+        let entry = &block[(idx % per_block) * entry_size..][..entry_size];
+        let type_guid: [u8; 16] = entry[0..16].try_into().unwrap();
+        if type_guid == [0; 16] {
+            continue;
+        }
+
+        let first = u64_at(entry, 32);
+        let last = u64_at(entry, 40);
+        if last < first {
+            continue;
+        }
+        found.push((idx + 1, first, last - first + 1, PartitionKind::Gpt(type_guid)));
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn set_mbr_entry(block: &mut [u8], i: usize, ty: u8, start: u32, count: u32) {
+        let entry = &mut block[446 + i * 16..][..16];
+        entry[4] = ty;
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&count.to_le_bytes());
+        block[510..512].copy_from_slice(&[0x55, 0xAA]);
+    }
+
+    fn partitions(image: Vec<u8>) -> Vec<(String, u64, u64, PartitionKind)> {
+        let disk: Arc<dyn BlockDevice> = Arc::new(RamDisk::from_vec("hd", image, false));
+        scan(&disk)
+            .iter()
+            .map(|p| (p.name().into(), p.start(), p.block_count(), p.kind()))
+            .collect()
+    }
+
+    #[test]
+    fn mbr_partitions() {
+        let mut image = vec![0u8; 64 * BLOCK_SIZE];
+        set_mbr_entry(&mut image[..BLOCK_SIZE], 0, 0x0c, 2, 10);
+        set_mbr_entry(&mut image[..BLOCK_SIZE], 1, 0x05, 20, 30);
+        // beyond the end of the disk
+        set_mbr_entry(&mut image[..BLOCK_SIZE], 2, 0x83, 60, 10);
+
+        // logical partitions start after their EBR, the next EBR is relative to the extended one
+        set_mbr_entry(&mut image[20 * BLOCK_SIZE..][..BLOCK_SIZE], 0, 0x83, 1, 4);
+        set_mbr_entry(&mut image[20 * BLOCK_SIZE..][..BLOCK_SIZE], 1, 0x05, 10, 20);
+        set_mbr_entry(&mut image[30 * BLOCK_SIZE..][..BLOCK_SIZE], 0, 0x82, 2, 5);
+
+        assert_eq!(
+            partitions(image),
+            [
+                ("hd1".into(), 2, 10, PartitionKind::Mbr(0x0c)),
+                ("hd5".into(), 21, 4, PartitionKind::Mbr(0x83)),
+                ("hd6".into(), 32, 5, PartitionKind::Mbr(0x82)),
+            ]
+        );
+
+        // no signature, no partitions
+        let mut image = vec![0u8; 64 * BLOCK_SIZE];
+        set_mbr_entry(&mut image[..BLOCK_SIZE], 0, 0x0c, 2, 10);
+        image[511] = 0;
+        assert!(partitions(image).is_empty());
+    }
+
+    #[test]
+    fn gpt_partitions() {
+        let mut image = vec![0u8; 64 * BLOCK_SIZE];
+        set_mbr_entry(&mut image[..BLOCK_SIZE], 0, 0xee, 1, 63);
+
+        let header = &mut image[BLOCK_SIZE..2 * BLOCK_SIZE];
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&6u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+
+        // entries 1 and 3 are unused, 6 spans two blocks of entries
+        for (idx, first, last) in [(0, 34, 40), (2, 41, 50), (4, 60, 70), (5, 51, 59)] {
+            let entry = &mut image[2 * BLOCK_SIZE + idx * 128..][..128];
+            entry[0..16].copy_from_slice(&[idx as u8 + 1; 16]);
+            entry[32..40].copy_from_slice(&(first as u64).to_le_bytes());
+            entry[40..48].copy_from_slice(&(last as u64).to_le_bytes());
+        }
+
+        assert_eq!(
+            partitions(image.clone()),
+            [
+                ("hd1".into(), 34, 7, PartitionKind::Gpt([1; 16])),
+                ("hd3".into(), 41, 10, PartitionKind::Gpt([3; 16])),
+                ("hd6".into(), 51, 9, PartitionKind::Gpt([6; 16])),
+            ]
+        );
+
+        // a protective MBR without a GPT header
+        image[BLOCK_SIZE] = 0;
+        assert!(partitions(image).is_empty());
+    }
+
+    #[test]
+    fn gpt_entry_count_capped() {
+        let mut image = vec![0u8; 300 * BLOCK_SIZE];
+        set_mbr_entry(&mut image[..BLOCK_SIZE], 0, 0xee, 1, 299);
+
+        let header = &mut image[BLOCK_SIZE..2 * BLOCK_SIZE];
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&1000u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+
+        // the last entry scanned, and one past the cap
+        for (idx, first) in [(127, 100), (128, 110)] {
+            let entry = &mut image[2 * BLOCK_SIZE + idx * 128..][..128];
+            entry[0..16].copy_from_slice(&[1; 16]);
+            entry[32..40].copy_from_slice(&(first as u64).to_le_bytes());
+            entry[40..48].copy_from_slice(&(first as u64 + 4).to_le_bytes());
+        }
+
+        assert_eq!(
+            partitions(image),
+            [("hd128".into(), 100, 5, PartitionKind::Gpt([1; 16]))]
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use super::*;
+use alloc::string::String;
+use alloc::vec;
+use spin::RwLock;
+
+/// A block device backed by kernel memory
+pub struct RamDisk {
+    name: String,
+    data: RwLock<Vec<u8>>,
+    read_only: bool,
+}
+
+impl RamDisk {
+    /// Create a zeroed disk of `blocks` blocks
+    pub fn new(name: &str, blocks: usize) -> Self {
+        Self::from_vec(name, vec![0; blocks * BLOCK_SIZE], false)
+    }
+
+    /// Use `data` as the content of the disk, padded to whole blocks
+    pub fn from_vec(name: &str, mut data: Vec<u8>, read_only: bool) -> Self {
+        let len = data.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+        data.resize(len, 0);
+
+        Self {
+            name: name.into(),
+            data: RwLock::new(data),
+            read_only,
+        }
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.data.read().len() / BLOCK_SIZE) as u64
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult {
+        check_range(self, lba, buf.len())?;
+
+        let start = lba as usize * BLOCK_SIZE;
+        buf.copy_from_slice(&self.data.read()[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult {
+        check_range(self, lba, buf.len())?;
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+
+        let start = lba as usize * BLOCK_SIZE;
+        self.data.write()[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// Read block `lba` of `dev`
+    pub(in crate::drivers::block) fn block_of(dev: &dyn BlockDevice, lba: u64) -> [u8; BLOCK_SIZE] {
+        let mut buf = [0; BLOCK_SIZE];
+        dev.read_blocks(lba, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn ramdisk_range() {
+        let disk = RamDisk::new("ram", 4);
+        let mut buf = [0u8; 2 * BLOCK_SIZE];
+        assert_eq!(disk.read_blocks(3, &mut buf), Err(BlockError::OutOfRange));
+        assert_eq!(disk.read_blocks(0, &mut buf[1..]), Err(BlockError::InvalidBuffer));
+
+        let disk = RamDisk::from_vec("rom", vec![1; 600], true);
+        assert_eq!(disk.block_count(), 2);
+        assert_eq!(disk.write_blocks(0, &buf[..BLOCK_SIZE]), Err(BlockError::ReadOnly));
+        assert_eq!(block_of(&disk, 1)[..88], [1; 88]);
+        assert_eq!(block_of(&disk, 1)[88..], [0; BLOCK_SIZE - 88]);
+    }
+}
@@ -0,0 +1,435 @@
+//! Virtio block device
+//!
+//! A transfer is split into requests of up to `CHUNK_SECTORS` sectors,
+//! which are queued together before the device is notified once.
+//! Data goes through DMA bounce buffers, one for each request slot,
+//! a slot is claimed by one transfer until its request completes.
+//!
+//! Completions are reaped from the used ring by the interrupt, and by
+//! the waiters each time their CPU wakes up, as the interrupt may be
+//! delivered to another CPU, or not at all without MSI-X.
+//!
+//! Reference: Virtio 1.1, 5.2 Block Device
+
+use super::*;
+use crate::drivers::pci::{self, PciDevice, PciDriver, PciMatch};
+use crate::drivers::virtio::*;
+use crate::memory::{alloc_dma, physical_to_virtual, PAGE_SIZE};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::{Mutex, RwLock};
+use x86_64::instructions::interrupts;
+
+// feature bits
+const F_RO: u64 = 1 << 5;
+const F_FLUSH: u64 = 1 << 9;
+
+// request types
+const T_IN: u32 = 0;
+const T_OUT: u32 = 1;
+const T_FLUSH: u32 = 4;
+
+const S_OK: u8 = 0;
+
+/// Sectors per request, the size of each bounce buffer
+const CHUNK_SECTORS: usize = 64;
+const CHUNK_PAGES: usize = CHUNK_SECTORS * BLOCK_SIZE / PAGE_SIZE as usize;
+
+/// Most requests in flight, each takes three descriptors
+const MAX_SLOTS: usize = 8;
+/// Descriptors taken by a request
+const REQUEST_DESCRIPTORS: u16 = 3;
+
+#[repr(C)]
+struct RequestHeader {
+    ty: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// DMA memory of a request
+struct Slot {
+    /// Physical address of the header, followed by the status byte
+    header: u64,
+    /// Physical address of the bounce buffer
+    data: u64,
+    /// Taken by a transfer until it has read the result
+    claimed: AtomicBool,
+    /// Set when the device has returned the request
+    done: AtomicBool,
+}
+
+impl Slot {
+    fn new() -> Option<Self> {
+        Some(Self {
+            header: alloc_dma(1)?,
+            data: alloc_dma(CHUNK_PAGES)?,
+            claimed: AtomicBool::new(false),
+            done: AtomicBool::new(false),
+        })
+    }
+
+    fn try_claim(&self) -> bool {
+        let claimed = self
+            .claimed
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok();
+        if claimed {
+            self.done.store(false, Ordering::Relaxed);
+        }
+        claimed
+    }
+
+    #[inline]
+    fn release(&self) {
+        self.claimed.store(false, Ordering::Release);
+    }
+
+    #[inline]
+    fn status_addr(&self) -> u64 {
+        self.header + core::mem::size_of::<RequestHeader>() as u64
+    }
+
+    #[inline]
+    fn data(&self, len: usize) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.data_ptr(), len) }
+    }
+
+    /// The bounce buffer, only written by the transfer that claimed the slot
+    #[inline]
+    fn data_ptr(&self) -> *mut u8 {
+        physical_to_virtual(self.data) as *mut u8
+    }
+
+    /// Fill in the header and status, returns the descriptor chain
+    fn prepare(&self, ty: u32, sector: u64, len: usize, write: bool) -> [Buffer; 3] {
+        unsafe {
+            (physical_to_virtual(self.header) as *mut RequestHeader).write_volatile(RequestHeader {
+                ty,
+                reserved: 0,
+                sector,
+            });
+            (physical_to_virtual(self.status_addr()) as *mut u8).write_volatile(0xff);
+        }
+
+        [
+            Buffer {
+                addr: self.header,
+                len: core::mem::size_of::<RequestHeader>() as u32,
+                writable: false,
+            },
+            Buffer {
+                addr: self.data,
+                len: len as u32,
+                writable: !write,
+            },
+            Buffer {
+                addr: self.status_addr(),
+                len: 1,
+                writable: true,
+            },
+        ]
+    }
+
+    fn status(&self) -> u8 {
+        unsafe { (physical_to_virtual(self.status_addr()) as *const u8).read_volatile() }
+    }
+}
+
+/// Locked with interrupts disabled, as the interrupt takes it
+struct Inner {
+    transport: Transport,
+    queue: VirtQueue,
+    /// Descriptor chain head of the request in flight in each slot
+    tokens: Vec<Option<u16>>,
+}
+
+pub struct VirtioBlk {
+    name: String,
+    capacity: u64,
+    read_only: bool,
+    can_flush: bool,
+    inner: Mutex<Inner>,
+    slots: Vec<Slot>,
+}
+
+/// Devices whose completions are reaped by the interrupt
+static DEVICES: RwLock<Vec<Arc<VirtioBlk>>> = RwLock::new(Vec::new());
+
+/// Mark the requests returned by each device as done,
+/// the waiters see it once their CPU wakes up.
+fn virtio_blk_irq() {
+    for dev in DEVICES.read().iter() {
+        dev.reap();
+    }
+}
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+impl VirtioBlk {
+    fn new(dev: &'static PciDevice) -> Option<Self> {
+        let mut transport = Transport::new(dev)?;
+        dev.enable();
+
+        let features = transport.negotiate(F_RO | F_FLUSH)?;
+
+        let vector = dev.request_msi(0, virtio_blk_irq, "virtio-blk");
+        transport.set_msix(vector.is_some() && dev.msix.is_some());
+        if vector.is_none() {
+            warn!("PCI {}: no MSI-X, virtio-blk polls for completion.", dev.address);
+        }
+
+        let entry = if dev.msix.is_some() && vector.is_some() { Some(0) } else { None };
+        let queue = transport.setup_queue(0, entry)?;
+        if queue.size() < REQUEST_DESCRIPTORS {
+            warn!("PCI {}: virtio-blk queue of {} is too small.", dev.address, queue.size());
+            transport.reset();
+            if let Some(vector) = vector {
+                crate::interrupt::free_msi(vector);
+            }
+            return None;
+        }
+
+        let count = (queue.size() / REQUEST_DESCRIPTORS).min(MAX_SLOTS as u16) as usize;
+        let slots = (0..count).map(|_| Slot::new()).collect::<Option<Vec<_>>>()?;
+
+        transport.driver_ok();
+
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        Some(Self {
+            name: format!("vd{}", (b'a' + id as u8) as char),
+            capacity: transport.read_config64(0),
+            read_only: features & F_RO != 0,
+            can_flush: features & F_FLUSH != 0,
+            inner: Mutex::new(Inner {
+                transport,
+                queue,
+                tokens: alloc::vec![None; count],
+            }),
+            slots,
+        })
+    }
+
+    /// Take the returned requests from the used ring, marking their slots done
+    fn reap(&self) {
+        interrupts::without_interrupts(|| {
+            let mut inner = self.inner.lock();
+            while let Some((token, _)) = inner.queue.pop_used() {
+                match inner.tokens.iter().position(|t| *t == Some(token)) {
+                    Some(idx) => {
+                        inner.tokens[idx] = None;
+                        self.slots[idx].done.store(true, Ordering::Release);
+                    }
+                    None => warn!("{}: unknown request {} completed.", self.name, token),
+                }
+            }
+        })
+    }
+
+    /// Wait until `cond` holds, checking it with interrupts disabled
+    /// so the wake up can not be lost before `hlt`.
+    fn wait(&self, cond: impl Fn() -> bool) {
+        let enabled = interrupts::are_enabled();
+        loop {
+            interrupts::disable();
+            self.reap();
+            if cond() {
+                break;
+            }
+            if enabled {
+                interrupts::enable_and_hlt();
+            } else {
+                core::hint::spin_loop();
+            }
+        }
+        if enabled {
+            interrupts::enable();
+        }
+    }
+
+    /// Claim up to `count` free slots, waiting for one if all are taken
+    fn claim(&self, count: usize) -> Vec<usize> {
+        loop {
+            let claimed: Vec<_> = (0..self.slots.len())
+                .filter(|&idx| self.slots[idx].try_claim())
+                .take(count)
+                .collect();
+            if !claimed.is_empty() {
+                return claimed;
+            }
+            self.wait(|| self.slots.iter().any(|s| !s.claimed.load(Ordering::Relaxed)));
+        }
+    }
+
+    /// Queue the descriptor chain of each claimed slot and notify the device
+    fn submit(&self, requests: &[(usize, &[Buffer])]) {
+        interrupts::without_interrupts(|| {
+            let mut guard = self.inner.lock();
+            let Inner {
+                transport,
+                queue,
+                tokens,
+            } = &mut *guard;
+
+            for &(idx, chain) in requests {
+                match queue.add(chain) {
+                    Some(token) => tokens[idx] = Some(token),
+                    // fails with the status left unset by the device
+                    None => self.slots[idx].done.store(true, Ordering::Release),
+                }
+            }
+            transport.notify(queue);
+        })
+    }
+
+    /// Run requests of `ty` for `len` bytes starting at sector `lba`,
+    /// `fill` and `drain` copy between the bounce buffers and the caller
+    /// for each request, with the offset of the request in the transfer.
+    fn transfer(
+        &self,
+        ty: u32,
+        lba: u64,
+        len: usize,
+        mut fill: impl FnMut(usize, &mut [u8]),
+        mut drain: impl FnMut(usize, &[u8]),
+    ) -> BlockResult {
+        let mut result = Ok(());
+        let mut offset = 0;
+
+        while offset < len {
+            // queue a batch with a request in each claimed slot
+            let claimed = self.claim((len - offset).div_ceil(CHUNK_SECTORS * BLOCK_SIZE));
+            let mut batch = Vec::new();
+            for idx in claimed {
+                let slot = &self.slots[idx];
+                let size = (len - offset).min(CHUNK_SECTORS * BLOCK_SIZE);
+                // claimed by this transfer, with no request in flight
+                fill(offset, unsafe { core::slice::from_raw_parts_mut(slot.data_ptr(), size) });
+                let sector = lba + (offset / BLOCK_SIZE) as u64;
+                let chain = slot.prepare(ty, sector, size, ty == T_OUT);
+
+                batch.push((idx, offset, size, chain));
+                offset += size;
+            }
+
+            let requests: Vec<_> = batch.iter().map(|(idx, _, _, chain)| (*idx, &chain[..])).collect();
+            self.submit(&requests);
+            self.wait(|| {
+                batch
+                    .iter()
+                    .all(|(idx, ..)| self.slots[*idx].done.load(Ordering::Acquire))
+            });
+
+            for (idx, offset, size, _) in batch {
+                let slot = &self.slots[idx];
+                if slot.status() == S_OK {
+                    drain(offset, slot.data(size));
+                } else {
+                    result = Err(BlockError::DeviceError);
+                }
+                slot.release();
+            }
+        }
+
+        result
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_count(&self) -> u64 {
+        self.capacity
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult {
+        check_range(self, lba, buf.len())?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        self.transfer(T_IN, lba, buf.len(), |_, _| {}, |offset, data| {
+            buf[offset..offset + data.len()].copy_from_slice(data)
+        })
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult {
+        check_range(self, lba, buf.len())?;
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        self.transfer(T_OUT, lba, buf.len(), |offset, data| {
+            data.copy_from_slice(&buf[offset..offset + data.len()])
+        }, |_, _| {})
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn flush(&self) -> BlockResult {
+        if !self.can_flush {
+            return Ok(());
+        }
+
+        let idx = self.claim(1)[0];
+        let slot = &self.slots[idx];
+
+        // a flush has no data buffer
+        let chain = slot.prepare(T_FLUSH, 0, 0, false);
+        self.submit(&[(idx, &[chain[0], chain[2]])]);
+        self.wait(|| slot.done.load(Ordering::Acquire));
+
+        let result = match slot.status() {
+            S_OK => Ok(()),
+            _ => Err(BlockError::DeviceError),
+        };
+        slot.release();
+        result
+    }
+}
+
+fn probe(dev: &'static PciDevice) -> bool {
+    match VirtioBlk::new(dev) {
+        Some(blk) => {
+            let blk = Arc::new(blk);
+            interrupts::without_interrupts(|| DEVICES.write().push(blk.clone()));
+            register(blk);
+            true
+        }
+        None => {
+            warn!("PCI {}: failed to initialize virtio-blk.", dev.address);
+            false
+        }
+    }
+}
+
+static DRIVER: PciDriver = PciDriver {
+    name: "virtio-blk",
+    matches: &[
+        // transitional device
+        PciMatch::Id {
+            vendor: VIRTIO_VENDOR_ID,
+            device: Some(0x1001),
+        },
+        // virtio 1.0 device
+        PciMatch::Id {
+            vendor: VIRTIO_VENDOR_ID,
+            device: Some(0x1042),
+        },
+    ],
+    probe,
+};
+
+pub fn init() {
+    pci::register_driver(&DRIVER);
+}
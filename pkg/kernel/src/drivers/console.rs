@@ -0,0 +1,223 @@
+//! Text console on the framebuffer
+//!
+//! Supports `\n`, `\r`, `\t`, backspace and the SGR color subset
+//! of ANSI escape sequences (`ESC [ ... m`) used by the logger.
+
+use super::font::*;
+use super::framebuffer::{Color, FrameBuffer};
+use crate::memory::physical_to_virtual;
+use core::fmt;
+
+once_mutex!(pub CONSOLE: Console);
+
+guard_access_fn!(pub get_console(CONSOLE: Console));
+
+pub fn init(boot_info: &'static boot::BootInfo) {
+    let info = match &boot_info.graphic_info {
+        Some(info) => info,
+        None => {
+            info!("No framebuffer, console disabled.");
+            return;
+        }
+    };
+
+    let fb = unsafe { FrameBuffer::new(physical_to_virtual(info.base), info) };
+    init_CONSOLE(Console::new(fb));
+
+    info!(
+        "Framebuffer Console: {}x{} ({}x{} chars)",
+        info.width,
+        info.height,
+        info.width / FONT_WIDTH,
+        info.height / FONT_HEIGHT
+    );
+}
+
+/// Standard ANSI colors, followed by the bright variants
+const PALETTE: [Color; 16] = [
+    Color::new(0x00, 0x00, 0x00), // black
+    Color::new(0xaa, 0x00, 0x00), // red
+    Color::new(0x00, 0xaa, 0x00), // green
+    Color::new(0xaa, 0x55, 0x00), // yellow
+    Color::new(0x00, 0x00, 0xaa), // blue
+    Color::new(0xaa, 0x00, 0xaa), // magenta
+    Color::new(0x00, 0xaa, 0xaa), // cyan
+    Color::new(0xaa, 0xaa, 0xaa), // white
+    Color::new(0x55, 0x55, 0x55),
+    Color::new(0xff, 0x55, 0x55),
+    Color::new(0x55, 0xff, 0x55),
+    Color::new(0xff, 0xff, 0x55),
+    Color::new(0x55, 0x55, 0xff),
+    Color::new(0xff, 0x55, 0xff),
+    Color::new(0x55, 0xff, 0xff),
+    Color::new(0xff, 0xff, 0xff),
+];
+
+const DEFAULT_FG: Color = PALETTE[7];
+const DEFAULT_BG: Color = PALETTE[0];
+
+const MAX_PARAMS: usize = 8;
+
+/// State of the ANSI escape sequence parser
+enum EscapeState {
+    Normal,
+    /// Got `ESC`
+    Escape,
+    /// Got `ESC [`, collecting parameters
+    Csi {
+        params: [u16; MAX_PARAMS],
+        count: usize,
+    },
+}
+
+pub struct Console {
+    fb: FrameBuffer,
+    cols: usize,
+    rows: usize,
+    col: usize,
+    row: usize,
+    fg: Color,
+    bg: Color,
+    bright: bool,
+    state: EscapeState,
+}
+
+impl Console {
+    pub fn new(mut fb: FrameBuffer) -> Self {
+        let cols = fb.width() / FONT_WIDTH;
+        let rows = fb.height() / FONT_HEIGHT;
+        let (width, height) = (fb.width(), fb.height());
+        fb.fill_rect(0, 0, width, height, DEFAULT_BG);
+
+        Self {
+            fb,
+            cols,
+            rows,
+            col: 0,
+            row: 0,
+            fg: DEFAULT_FG,
+            bg: DEFAULT_BG,
+            bright: false,
+            state: EscapeState::Normal,
+        }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        match core::mem::replace(&mut self.state, EscapeState::Normal) {
+            EscapeState::Normal => self.put_byte(byte),
+            EscapeState::Escape => {
+                if byte == b'[' {
+                    self.state = EscapeState::Csi {
+                        params: [0; MAX_PARAMS],
+                        count: 0,
+                    };
+                }
+            }
+            EscapeState::Csi { mut params, mut count } => match byte {
+                b'0'..=b'9' => {
+                    let idx = count.min(MAX_PARAMS - 1);
+                    params[idx] = params[idx].saturating_mul(10).saturating_add((byte - b'0') as u16);
+                    self.state = EscapeState::Csi { params, count };
+                }
+                b';' => {
+                    count = (count + 1).min(MAX_PARAMS - 1);
+                    self.state = EscapeState::Csi { params, count };
+                }
+                b'm' => self.set_graphic_rendition(&params[..=count]),
+                // other sequences are ignored
+                _ => {}
+            },
+        }
+    }
+
+    fn put_byte(&mut self, byte: u8) {
+        match byte {
+            0x1b => self.state = EscapeState::Escape,
+            b'\n' => self.new_line(),
+            b'\r' => self.col = 0,
+            b'\t' => {
+                for _ in 0..(8 - self.col % 8) {
+                    self.put_byte(b' ');
+                }
+            }
+            0x08 => self.col = self.col.saturating_sub(1),
+            _ => {
+                if self.col >= self.cols {
+                    self.col = 0;
+                    self.new_line();
+                }
+                self.draw_char(byte, self.col, self.row);
+                self.col += 1;
+            }
+        }
+    }
+
+    fn new_line(&mut self) {
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.fb.scroll_up(FONT_HEIGHT, self.bg);
+        }
+    }
+
+    fn draw_char(&mut self, byte: u8, col: usize, row: usize) {
+        let glyph = match byte {
+            0x20..=0x7e => &FONT[(byte - FONT_FIRST_CHAR) as usize],
+            _ => &FONT[FONT.len() - 1],
+        };
+
+        let fg = if self.bright {
+            brighten(self.fg)
+        } else {
+            self.fg
+        };
+
+        let (x0, y0) = (col * FONT_WIDTH, row * FONT_HEIGHT);
+        for (y, bits) in glyph.iter().enumerate() {
+            for x in 0..FONT_WIDTH {
+                let color = if bits & (0x80 >> x) != 0 { fg } else { self.bg };
+                self.fb.set_pixel(x0 + x, y0 + y, color);
+            }
+        }
+    }
+
+    /// Handle `ESC [ ... m`
+    fn set_graphic_rendition(&mut self, params: &[u16]) {
+        for &param in params {
+            match param {
+                0 => {
+                    self.fg = DEFAULT_FG;
+                    self.bg = DEFAULT_BG;
+                    self.bright = false;
+                }
+                1 => self.bright = true,
+                22 => self.bright = false,
+                30..=37 => self.fg = PALETTE[(param - 30) as usize],
+                39 => self.fg = DEFAULT_FG,
+                40..=47 => self.bg = PALETTE[(param - 40) as usize],
+                49 => self.bg = DEFAULT_BG,
+                90..=97 => self.fg = PALETTE[(param - 90 + 8) as usize],
+                100..=107 => self.bg = PALETTE[(param - 100 + 8) as usize],
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Map a standard color to its bright variant, used by SGR 1
+fn brighten(color: Color) -> Color {
+    PALETTE[..8]
+        .iter()
+        .position(|&c| c == color)
+        .map(|idx| PALETTE[idx + 8])
+        .unwrap_or(color)
+}
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
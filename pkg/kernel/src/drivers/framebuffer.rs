@@ -0,0 +1,96 @@
+use boot::{FrameBufferInfo, PixelFormat};
+use core::ptr::{copy, write_volatile};
+
+/// A 24-bit color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+/// A linear 32-bit framebuffer set by the bootloader
+pub struct FrameBuffer {
+    addr: u64,
+    width: usize,
+    height: usize,
+    stride: usize,
+    format: PixelFormat,
+}
+
+impl FrameBuffer {
+    /// Create a framebuffer at virtual address `addr`
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure `addr` maps the framebuffer described by `info`.
+    pub unsafe fn new(addr: u64, info: &FrameBufferInfo) -> Self {
+        Self {
+            addr,
+            width: info.width,
+            height: info.height,
+            stride: info.stride,
+            format: info.format,
+        }
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    #[inline]
+    fn encode(&self, color: Color) -> u32 {
+        match self.format {
+            PixelFormat::Rgb => color.r as u32 | (color.g as u32) << 8 | (color.b as u32) << 16,
+            _ => color.b as u32 | (color.g as u32) << 8 | (color.r as u32) << 16,
+        }
+    }
+
+    #[inline]
+    fn pixel_ptr(&self, x: usize, y: usize) -> *mut u32 {
+        (self.addr as *mut u32).wrapping_add(y * self.stride + x)
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        if x < self.width && y < self.height {
+            let value = self.encode(color);
+            unsafe { write_volatile(self.pixel_ptr(x, y), value) }
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        let value = self.encode(color);
+        let x_end = (x + width).min(self.width);
+        let y_end = (y + height).min(self.height);
+        for y in y..y_end {
+            for x in x..x_end {
+                unsafe { write_volatile(self.pixel_ptr(x, y), value) }
+            }
+        }
+    }
+
+    /// Move the whole screen up by `lines` pixels and fill the bottom with `color`
+    pub fn scroll_up(&mut self, lines: usize, color: Color) {
+        let lines = lines.min(self.height);
+        unsafe {
+            copy(
+                self.pixel_ptr(0, lines),
+                self.pixel_ptr(0, 0),
+                (self.height - lines) * self.stride,
+            );
+        }
+        self.fill_rect(0, self.height - lines, self.width, lines, color);
+    }
+}
@@ -1,16 +1,32 @@
 use crossbeam_queue::ArrayQueue;
 use heapless::String; // 使用heapless的String
-use super::serial::get_serial;
+use super::console::get_console_blocking;
+use super::serial::get_serial_blocking;
+use crate::proc::{self, ProcessId};
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
 type KEY = u8; //输入类型
 lazy_static! { //缓冲区数据结构
   static ref INPUT_BUF: ArrayQueue<KEY> = ArrayQueue::new(128);
 }
+
+/// Processes blocked in [`wait_key`]
+static WAITERS: Mutex<Vec<ProcessId>> = Mutex::new(Vec::new());
+
 #[inline]
 pub fn push_key(key: KEY) {
   //输入到缓冲区
   if INPUT_BUF.push(key).is_err() {
     warn!("Input buffer is full. Dropping key '{:?}'", key);
   }
+
+  // the key is queued before the waiters are taken, so a reader
+  // that did not see it is already in the list
+  let waiters = interrupts::without_interrupts(|| core::mem::take(&mut *WAITERS.lock()));
+  for pid in waiters {
+    proc::wake(pid);
+  }
 }
 
 #[inline]
@@ -27,6 +43,29 @@ pub fn pop_key() -> KEY {
   }
 }
 
+/// Take a key from the buffer, blocking the current process while it is empty
+pub fn wait_key() -> KEY {
+  loop {
+    let key = interrupts::without_interrupts(|| {
+      let mut waiters = WAITERS.lock();
+      let key = try_pop_key();
+      if key.is_none() {
+        // blocked before unlocking, so a key pushed in between wakes it up
+        let pid = proc::block_current();
+        if !waiters.contains(&pid) {
+          waiters.push(pid);
+        }
+      }
+      key
+    });
+
+    match key {
+      Some(key) => return key,
+      None => proc::yield_now(),
+    }
+  }
+}
+
 const MAX_LINE_LENGTH: usize = 128;
 
 pub fn get_line() -> String<MAX_LINE_LENGTH> {
@@ -59,11 +98,14 @@ pub fn get_line() -> String<MAX_LINE_LENGTH> {
 
 // 模拟退格操作的函数，发送0x08, 0x20, 0x08到串口以删除字符
 fn backspace() {
-  if let Some(mut serial_port) = get_serial() {
+  get_serial_blocking(|serial_port| {
     serial_port.send(0x08);
     serial_port.send(0x20);
     serial_port.send(0x08);
-  } else {
-    info!("failed to get_serail");
-  }
+  });
+  get_console_blocking(|console| {
+    console.write_byte(0x08);
+    console.write_byte(0x20);
+    console.write_byte(0x08);
+  });
 }
@@ -1,3 +1,9 @@
 pub mod uart16550;
 pub mod serial;
 pub mod input;
+pub mod font;
+pub mod framebuffer;
+pub mod console;
+pub mod pci;
+pub mod virtio;
+pub mod block;
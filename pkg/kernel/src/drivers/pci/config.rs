@@ -0,0 +1,122 @@
+//! PCI configuration space access
+//!
+//! Use the PCI Express ECAM windows from the ACPI MCFG table when present,
+//! otherwise the legacy configuration mechanism #1 on ports 0xCF8/0xCFC.
+
+use crate::acpi::McfgEntry;
+use crate::memory::physical_to_virtual;
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+/// Location of a PCI function on segment 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            bus,
+            device,
+            function,
+        }
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+pub struct ConfigSpace {
+    /// ECAM windows of segment 0
+    ecam: Vec<McfgEntry>,
+    /// The address and data ports must be used as a pair
+    port_lock: Mutex<()>,
+}
+
+impl ConfigSpace {
+    pub fn new(mcfg: &[McfgEntry]) -> Self {
+        Self {
+            ecam: mcfg.iter().filter(|e| e.segment == 0).copied().collect(),
+            port_lock: Mutex::new(()),
+        }
+    }
+
+    /// If the enhanced configuration mechanism is used
+    #[inline]
+    pub fn is_ecam(&self) -> bool {
+        !self.ecam.is_empty()
+    }
+
+    /// Virtual address of the dword at `offset` through ECAM
+    fn ecam_addr(&self, addr: PciAddress, offset: u16) -> Option<u64> {
+        let entry = self
+            .ecam
+            .iter()
+            .find(|e| (e.start_bus..=e.end_bus).contains(&addr.bus))?;
+        let phys = entry.base_address
+            + (((addr.bus - entry.start_bus) as u64) << 20
+                | (addr.device as u64) << 15
+                | (addr.function as u64) << 12
+                | (offset & 0xffc) as u64);
+        Some(physical_to_virtual(phys))
+    }
+
+    /// Value of CONFIG_ADDRESS for the dword at `offset`
+    #[inline]
+    fn port_addr(addr: PciAddress, offset: u16) -> u32 {
+        1 << 31
+            | (addr.bus as u32) << 16
+            | (addr.device as u32) << 11
+            | (addr.function as u32) << 8
+            | (offset & 0xfc) as u32
+    }
+
+    pub fn read(&self, addr: PciAddress, offset: u16) -> u32 {
+        if let Some(ptr) = self.ecam_addr(addr, offset) {
+            return unsafe { core::ptr::read_volatile(ptr as *const u32) };
+        }
+
+        if offset >= 0x100 {
+            return u32::MAX;
+        }
+
+        without_interrupts(|| {
+            let _guard = self.port_lock.lock();
+            unsafe {
+                Port::<u32>::new(CONFIG_ADDRESS).write(Self::port_addr(addr, offset));
+                Port::<u32>::new(CONFIG_DATA).read()
+            }
+        })
+    }
+
+    pub fn write(&self, addr: PciAddress, offset: u16, value: u32) {
+        if let Some(ptr) = self.ecam_addr(addr, offset) {
+            unsafe { core::ptr::write_volatile(ptr as *mut u32, value) };
+            return;
+        }
+
+        if offset >= 0x100 {
+            return;
+        }
+
+        without_interrupts(|| {
+            let _guard = self.port_lock.lock();
+            unsafe {
+                Port::<u32>::new(CONFIG_ADDRESS).write(Self::port_addr(addr, offset));
+                Port::<u32>::new(CONFIG_DATA).write(value);
+            }
+        })
+    }
+}
@@ -0,0 +1,362 @@
+use super::config::PciAddress;
+use super::config_space;
+use alloc::vec::Vec;
+use bit_field::BitField;
+use core::fmt;
+
+// offsets in the common configuration header
+pub const REG_ID: u16 = 0x00;
+pub const REG_COMMAND: u16 = 0x04;
+pub const REG_CLASS: u16 = 0x08;
+pub const REG_HEADER: u16 = 0x0C;
+pub const REG_BAR0: u16 = 0x10;
+pub const REG_CAPABILITIES: u16 = 0x34;
+pub const REG_INTERRUPT: u16 = 0x3C;
+
+const STATUS_CAPABILITIES: u32 = 1 << 20;
+
+bitflags! {
+    /// Bits of the command register
+    #[derive(Debug, Clone, Copy)]
+    pub struct Command: u16 {
+        const IO_SPACE = 1 << 0;
+        const MEMORY_SPACE = 1 << 1;
+        const BUS_MASTER = 1 << 2;
+        const INTERRUPT_DISABLE = 1 << 10;
+    }
+}
+
+/// Capability IDs
+pub const CAP_MSI: u8 = 0x05;
+pub const CAP_VENDOR: u8 = 0x09;
+pub const CAP_PCIE: u8 = 0x10;
+pub const CAP_MSIX: u8 = 0x11;
+
+/// A decoded Base Address Register
+#[derive(Debug, Clone, Copy)]
+pub enum Bar {
+    Memory {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+        is_64bit: bool,
+    },
+    Io {
+        port: u16,
+        size: u32,
+    },
+}
+
+impl Bar {
+    /// Physical address for memory BARs, port for IO BARs
+    pub fn address(&self) -> u64 {
+        match *self {
+            Bar::Memory { address, .. } => address,
+            Bar::Io { port, .. } => port as u64,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        match *self {
+            Bar::Memory { size, .. } => size,
+            Bar::Io { size, .. } => size as u64,
+        }
+    }
+}
+
+/// An entry of the capability list
+#[derive(Debug, Clone, Copy)]
+pub struct Capability {
+    pub id: u8,
+    /// Offset in the configuration space
+    pub offset: u8,
+}
+
+/// Message Signaled Interrupts capability
+#[derive(Debug, Clone, Copy)]
+pub struct MsiInfo {
+    pub offset: u8,
+    /// Vectors the function can request, a power of two
+    pub max_vectors: u8,
+    pub is_64bit: bool,
+    pub per_vector_mask: bool,
+}
+
+/// MSI-X capability
+#[derive(Debug, Clone, Copy)]
+pub struct MsixInfo {
+    pub offset: u8,
+    pub table_size: u16,
+    pub table_bar: u8,
+    pub table_offset: u32,
+    pub pba_bar: u8,
+    pub pba_offset: u32,
+}
+
+/// A PCI function found by enumeration
+#[derive(Debug, Clone)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub header_type: u8,
+    pub subsystem_vendor_id: u16,
+    pub subsystem_id: u16,
+    pub bars: [Option<Bar>; 6],
+    /// Legacy INTx line set by the firmware, 0xff if none
+    pub interrupt_line: u8,
+    /// INTA# to INTD# as 1 to 4, 0 if none
+    pub interrupt_pin: u8,
+    pub capabilities: Vec<Capability>,
+    pub msi: Option<MsiInfo>,
+    pub msix: Option<MsixInfo>,
+}
+
+impl PciDevice {
+    /// Read the function at `address`, None if it is not present
+    pub fn probe(address: PciAddress) -> Option<Self> {
+        let config = config_space();
+
+        let id = config.read(address, REG_ID);
+        if id.get_bits(0..16) == 0xffff {
+            return None;
+        }
+
+        let class = config.read(address, REG_CLASS);
+        let header_type = config.read(address, REG_HEADER).get_bits(16..23) as u8;
+        let (subsystem_vendor_id, subsystem_id) = if header_type == 0 {
+            let subsystem = config.read(address, 0x2C);
+            (subsystem.get_bits(0..16) as u16, subsystem.get_bits(16..32) as u16)
+        } else {
+            (0, 0)
+        };
+        let interrupt = config.read(address, REG_INTERRUPT);
+
+        let mut device = Self {
+            address,
+            vendor_id: id.get_bits(0..16) as u16,
+            device_id: id.get_bits(16..32) as u16,
+            class: class.get_bits(24..32) as u8,
+            subclass: class.get_bits(16..24) as u8,
+            prog_if: class.get_bits(8..16) as u8,
+            revision: class.get_bits(0..8) as u8,
+            header_type,
+            subsystem_vendor_id,
+            subsystem_id,
+            bars: [None; 6],
+            interrupt_line: interrupt.get_bits(0..8) as u8,
+            interrupt_pin: interrupt.get_bits(8..16) as u8,
+            capabilities: Vec::new(),
+            msi: None,
+            msix: None,
+        };
+
+        device.read_bars();
+        device.read_capabilities();
+
+        Some(device)
+    }
+
+    /// If the function is a PCI-to-PCI bridge
+    #[inline]
+    pub fn is_bridge(&self) -> bool {
+        self.header_type == 1
+    }
+
+    #[inline]
+    pub fn read(&self, offset: u16) -> u32 {
+        config_space().read(self.address, offset)
+    }
+
+    #[inline]
+    pub fn write(&self, offset: u16, value: u32) {
+        config_space().write(self.address, offset, value)
+    }
+
+    pub fn read16(&self, offset: u16) -> u16 {
+        let shift = (offset & 2) * 8;
+        (self.read(offset & !3) >> shift) as u16
+    }
+
+    pub fn write16(&self, offset: u16, value: u16) {
+        let shift = (offset & 2) * 8;
+        let mut dword = self.read(offset & !3);
+        dword &= !(0xffff << shift);
+        dword |= (value as u32) << shift;
+        self.write(offset & !3, dword);
+    }
+
+    pub fn command(&self) -> Command {
+        Command::from_bits_retain(self.read16(REG_COMMAND))
+    }
+
+    pub fn set_command(&self, command: Command) {
+        self.write16(REG_COMMAND, command.bits());
+    }
+
+    /// Enable decoding of memory and IO BARs and DMA by the device
+    pub fn enable(&self) {
+        self.set_command(
+            self.command() | Command::IO_SPACE | Command::MEMORY_SPACE | Command::BUS_MASTER,
+        );
+    }
+
+    /// Find the first capability with `id`
+    pub fn capability(&self, id: u8) -> Option<u8> {
+        self.capabilities.iter().find(|c| c.id == id).map(|c| c.offset)
+    }
+
+    fn read_bars(&mut self) {
+        let count = match self.header_type {
+            0 => 6,
+            1 => 2,
+            _ => 0,
+        };
+
+        // stop decoding while the BARs are sized
+        let command = self.command();
+        self.set_command(command - Command::IO_SPACE - Command::MEMORY_SPACE);
+
+        let mut idx = 0;
+        while idx < count {
+            let offset = REG_BAR0 + idx as u16 * 4;
+            let bar = self.read(offset);
+
+            if bar.get_bit(0) {
+                let size = !(self.size_bar(offset, bar) & !0x3) & 0xffff;
+                if bar & !0x3 != 0 {
+                    self.bars[idx] = Some(Bar::Io {
+                        port: (bar & !0x3) as u16,
+                        size: size.wrapping_add(1),
+                    });
+                }
+                idx += 1;
+                continue;
+            }
+
+            let is_64bit = bar.get_bits(1..3) == 0b10 && idx + 1 < count;
+            let mut address = (bar & !0xf) as u64;
+            let mut mask = (self.size_bar(offset, bar) & !0xf) as u64;
+            if is_64bit {
+                let high = self.read(offset + 4);
+                address |= (high as u64) << 32;
+                mask |= (self.size_bar(offset + 4, high) as u64) << 32;
+            } else {
+                mask |= 0xffff_ffff << 32;
+            }
+
+            if mask != 0xffff_ffff_ffff_ffff << 32 || address != 0 {
+                self.bars[idx] = Some(Bar::Memory {
+                    address,
+                    size: (!mask).wrapping_add(1),
+                    prefetchable: bar.get_bit(3),
+                    is_64bit,
+                });
+            }
+
+            idx += if is_64bit { 2 } else { 1 };
+        }
+
+        self.set_command(command);
+    }
+
+    /// Write all ones to the BAR at `offset` and read back its size mask
+    fn size_bar(&self, offset: u16, original: u32) -> u32 {
+        self.write(offset, u32::MAX);
+        let mask = self.read(offset);
+        self.write(offset, original);
+        mask
+    }
+
+    fn read_capabilities(&mut self) {
+        if self.read(REG_COMMAND) & STATUS_CAPABILITIES == 0 {
+            return;
+        }
+
+        let mut offset = self.read(REG_CAPABILITIES) as u8 & 0xfc;
+        // guard against malformed lists
+        for _ in 0..48 {
+            if offset == 0 {
+                break;
+            }
+
+            let header = self.read(offset as u16);
+            let id = header.get_bits(0..8) as u8;
+            self.capabilities.push(Capability { id, offset });
+
+            let control = header.get_bits(16..32) as u16;
+            match id {
+                CAP_MSI => {
+                    self.msi = Some(MsiInfo {
+                        offset,
+                        max_vectors: 1 << control.get_bits(1..4),
+                        is_64bit: control.get_bit(7),
+                        per_vector_mask: control.get_bit(8),
+                    })
+                }
+                CAP_MSIX => {
+                    let table = self.read(offset as u16 + 4);
+                    let pba = self.read(offset as u16 + 8);
+                    self.msix = Some(MsixInfo {
+                        offset,
+                        table_size: control.get_bits(0..11) + 1,
+                        table_bar: table.get_bits(0..3) as u8,
+                        table_offset: table & !0x7,
+                        pba_bar: pba.get_bits(0..3) as u8,
+                        pba_offset: pba & !0x7,
+                    })
+                }
+                _ => {}
+            }
+
+            offset = header.get_bits(8..16) as u8 & 0xfc;
+        }
+    }
+}
+
+impl fmt::Display for PciDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} [{:04x}:{:04x}] {} ({:02x}.{:02x}.{:02x})",
+            self.address,
+            self.vendor_id,
+            self.device_id,
+            class_name(self.class, self.subclass),
+            self.class,
+            self.subclass,
+            self.prog_if
+        )?;
+        if self.msix.is_some() {
+            write!(f, " MSI-X")?;
+        } else if self.msi.is_some() {
+            write!(f, " MSI")?;
+        }
+        Ok(())
+    }
+}
+
+/// Name of common device classes
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x01) => "IDE controller",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "NVMe controller",
+        (0x01, _) => "Storage controller",
+        (0x02, _) => "Network controller",
+        (0x03, _) => "Display controller",
+        (0x04, _) => "Multimedia controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x0C, 0x03) => "USB controller",
+        (0x0C, 0x05) => "SMBus",
+        (0x0C, _) => "Serial bus controller",
+        _ => "Device",
+    }
+}
@@ -0,0 +1,146 @@
+//! PCI bus enumeration
+//!
+//! Scan every bus, device and function at boot, and bind the functions
+//! to the drivers registered with [`register_driver`].
+//!
+//! Reference: [OSDev Wiki](https://wiki.osdev.org/PCI)
+
+mod config;
+mod device;
+mod msi;
+
+pub use config::{ConfigSpace, PciAddress};
+pub use device::*;
+
+use crate::acpi::get_acpi;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+static CONFIG: spin::Once<ConfigSpace> = spin::Once::new();
+static DEVICES: spin::Once<Vec<PciDevice>> = spin::Once::new();
+
+/// Name of the driver bound to each function
+static BOUND: Mutex<BTreeMap<PciAddress, &'static str>> = Mutex::new(BTreeMap::new());
+
+pub fn init() {
+    let config = CONFIG.call_once(|| ConfigSpace::new(&get_acpi().mcfg));
+    let devices = DEVICES.call_once(scan);
+
+    info!(
+        "PCI Initialized ({}), {} function(s):",
+        if config.is_ecam() { "ECAM" } else { "port IO" },
+        devices.len()
+    );
+    for device in devices.iter() {
+        info!("  {}", device);
+    }
+}
+
+#[inline]
+pub(crate) fn config_space() -> &'static ConfigSpace {
+    CONFIG.get().expect("PCI not initialized")
+}
+
+/// All functions found at boot
+#[inline]
+pub fn devices() -> &'static [PciDevice] {
+    DEVICES.get().map(|d| d.as_slice()).unwrap_or(&[])
+}
+
+/// Find the function at `address`
+pub fn get(address: PciAddress) -> Option<&'static PciDevice> {
+    devices().iter().find(|d| d.address == address)
+}
+
+fn scan() -> Vec<PciDevice> {
+    let mut devices = Vec::new();
+
+    for bus in 0..=255u8 {
+        for slot in 0..32u8 {
+            let device = match PciDevice::probe(PciAddress::new(bus, slot, 0)) {
+                Some(device) => device,
+                None => continue,
+            };
+
+            let multifunction = device.read(REG_HEADER) & (1 << 23) != 0;
+            devices.push(device);
+
+            if multifunction {
+                devices.extend((1..8).filter_map(|func| {
+                    PciDevice::probe(PciAddress::new(bus, slot, func))
+                }));
+            }
+        }
+    }
+
+    devices
+}
+
+/// How a driver selects the functions it supports
+#[derive(Debug, Clone, Copy)]
+pub enum PciMatch {
+    /// Vendor and device ID, any device of the vendor if `device` is None
+    Id { vendor: u16, device: Option<u16> },
+    /// Class code, any programming interface if `prog_if` is None
+    Class {
+        class: u8,
+        subclass: u8,
+        prog_if: Option<u8>,
+    },
+}
+
+impl PciMatch {
+    pub fn matches(&self, dev: &PciDevice) -> bool {
+        match *self {
+            PciMatch::Id { vendor, device } => {
+                dev.vendor_id == vendor && device.map_or(true, |id| dev.device_id == id)
+            }
+            PciMatch::Class {
+                class,
+                subclass,
+                prog_if,
+            } => {
+                dev.class == class
+                    && dev.subclass == subclass
+                    && prog_if.map_or(true, |p| dev.prog_if == p)
+            }
+        }
+    }
+}
+
+pub struct PciDriver {
+    pub name: &'static str,
+    pub matches: &'static [PciMatch],
+    /// Called for each unbound matching function, returns if it is taken
+    pub probe: fn(&'static PciDevice) -> bool,
+}
+
+/// Probe `driver` with every unbound function it matches,
+/// returns the number of functions bound to it.
+pub fn register_driver(driver: &PciDriver) -> usize {
+    let mut count = 0;
+
+    for device in devices() {
+        if !driver.matches.iter().any(|m| m.matches(device)) {
+            continue;
+        }
+
+        if BOUND.lock().contains_key(&device.address) {
+            continue;
+        }
+
+        if (driver.probe)(device) {
+            BOUND.lock().insert(device.address, driver.name);
+            info!("PCI {} bound to {}.", device.address, driver.name);
+            count += 1;
+        }
+    }
+
+    count
+}
+
+/// Name of the driver bound to the function at `address`
+pub fn bound_driver(address: PciAddress) -> Option<&'static str> {
+    BOUND.lock().get(&address).copied()
+}
@@ -0,0 +1,175 @@
+//! Message Signaled Interrupts
+//!
+//! A message is a write of the vector to the local APIC address range,
+//! delivered to the CPU in the destination field without the IO APIC.
+//!
+//! Reference: Intel SDM Vol. 3A, 11.11 Message Signalled Interrupts
+
+use super::device::*;
+use crate::interrupt::{self, IrqHandler};
+use crate::memory::map_mmio;
+use crate::proc::processor;
+use bit_field::BitField;
+
+const MSI_ADDRESS_BASE: u32 = 0xFEE0_0000;
+
+// offsets in the MSI capability
+const MSI_CONTROL: u16 = 0x02;
+const MSI_ADDRESS: u16 = 0x04;
+
+// bits in the message control register
+const MSI_ENABLE: u16 = 1 << 0;
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_ENABLE: u16 = 1 << 15;
+
+const MSIX_ENTRY_SIZE: u64 = 16;
+const MSIX_ENTRY_MASKED: u32 = 1 << 0;
+
+/// Message address and data for fixed, edge triggered delivery
+/// of `vector` to the local APIC `apic_id` in physical mode
+fn message(vector: u8, apic_id: u32) -> Option<(u32, u32)> {
+    if apic_id > 0xff {
+        warn!("APIC {} can not be addressed by MSI.", apic_id);
+        return None;
+    }
+
+    Some((MSI_ADDRESS_BASE | apic_id << 12, vector as u32))
+}
+
+impl PciDevice {
+    /// Program the MSI capability to send `vector` to `apic_id` and enable it
+    pub fn enable_msi(&self, vector: u8, apic_id: u32) -> bool {
+        let msi = match self.msi {
+            Some(msi) => msi,
+            None => return false,
+        };
+        let (address, data) = match message(vector, apic_id) {
+            Some(message) => message,
+            None => return false,
+        };
+
+        let offset = msi.offset as u16;
+        self.write(offset + MSI_ADDRESS, address);
+        if msi.is_64bit {
+            self.write(offset + MSI_ADDRESS + 4, 0);
+            self.write16(offset + 0x0C, data as u16);
+        } else {
+            self.write16(offset + 0x08, data as u16);
+        }
+
+        // single message, no multiple message enable
+        let mut control = self.read16(offset + MSI_CONTROL);
+        control.set_bits(4..7, 0);
+        self.write16(offset + MSI_CONTROL, control | MSI_ENABLE);
+        self.set_command(self.command() | Command::INTERRUPT_DISABLE);
+        true
+    }
+
+    pub fn disable_msi(&self) {
+        if let Some(msi) = self.msi {
+            let offset = msi.offset as u16 + MSI_CONTROL;
+            self.write16(offset, self.read16(offset) & !MSI_ENABLE);
+        }
+    }
+
+    /// Virtual address of the MSI-X table entry `entry`
+    fn msix_entry(&self, msix: &MsixInfo, entry: u16) -> Option<*mut u32> {
+        if entry >= msix.table_size {
+            return None;
+        }
+
+        match self.bars[msix.table_bar as usize] {
+            Some(Bar::Memory { address, .. }) => {
+                let addr = address + msix.table_offset as u64 + entry as u64 * MSIX_ENTRY_SIZE;
+                Some(map_mmio(addr, MSIX_ENTRY_SIZE) as *mut u32)
+            }
+            _ => None,
+        }
+    }
+
+    /// Set MSI-X table entry `entry` to send `vector` to `apic_id` and unmask it
+    pub fn set_msix_vector(&self, entry: u16, vector: u8, apic_id: u32) -> bool {
+        let msix = match self.msix {
+            Some(msix) => msix,
+            None => return false,
+        };
+        let (ptr, (address, data)) = match (self.msix_entry(&msix, entry), message(vector, apic_id)) {
+            (Some(ptr), Some(message)) => (ptr, message),
+            _ => return false,
+        };
+
+        unsafe {
+            ptr.write_volatile(address);
+            ptr.add(1).write_volatile(0);
+            ptr.add(2).write_volatile(data);
+            let control = ptr.add(3).read_volatile();
+            ptr.add(3).write_volatile(control & !MSIX_ENTRY_MASKED);
+        }
+        true
+    }
+
+    /// Mask MSI-X table entry `entry`
+    pub fn mask_msix_vector(&self, entry: u16) {
+        if let Some(ptr) = self.msix.and_then(|msix| self.msix_entry(&msix, entry)) {
+            unsafe {
+                let control = ptr.add(3).read_volatile();
+                ptr.add(3).write_volatile(control | MSIX_ENTRY_MASKED);
+            }
+        }
+    }
+
+    /// Enable MSI-X, the entries should be set before
+    pub fn enable_msix(&self) -> bool {
+        let msix = match self.msix {
+            Some(msix) => msix,
+            None => return false,
+        };
+
+        let offset = msix.offset as u16 + MSI_CONTROL;
+        let control = self.read16(offset);
+        self.write16(offset, (control | MSIX_ENABLE) & !MSIX_FUNCTION_MASK);
+        self.set_command(self.command() | Command::INTERRUPT_DISABLE);
+        true
+    }
+
+    pub fn disable_msix(&self) {
+        if let Some(msix) = self.msix {
+            let offset = msix.offset as u16 + MSI_CONTROL;
+            self.write16(offset, self.read16(offset) & !MSIX_ENABLE);
+        }
+    }
+
+    /// Allocate a vector for `handler` and deliver it to the calling CPU
+    /// with MSI-X entry `entry` if supported, or MSI otherwise.
+    ///
+    /// Returns the vector, None if the device can not send messages.
+    pub fn request_msi(&self, entry: u16, handler: IrqHandler, name: &'static str) -> Option<u8> {
+        if self.msix.is_none() && self.msi.is_none() {
+            return None;
+        }
+
+        let vector = interrupt::request_msi(handler, name)?;
+        let apic_id = processor::apic_id(processor::current_id());
+
+        let enabled = if self.msix.is_some() {
+            self.set_msix_vector(entry, vector, apic_id) && self.enable_msix()
+        } else {
+            entry == 0 && self.enable_msi(vector, apic_id)
+        };
+
+        if !enabled {
+            interrupt::free_msi(vector);
+            return None;
+        }
+
+        info!(
+            "PCI {} {} entry {} -> vector {:#x} on CPU {}",
+            self.address,
+            if self.msix.is_some() { "MSI-X" } else { "MSI" },
+            entry,
+            vector,
+            apic_id
+        );
+        Some(vector)
+    }
+}
@@ -1,4 +1,6 @@
+use super::input;
 use super::uart16550::SerialPort;
+use crate::interrupt::consts::Irq;
 //use x86_64::instructions::port::*;
 const SERIAL_IO_PORT: u16 = 0x3F8; // COM1
 
@@ -14,3 +16,21 @@ pub fn init() {
 
 guard_access_fn!(pub get_serial(SERIAL: SerialPort));
 
+/// Receive input from the serial port by interrupt
+///
+/// Must be called after the interrupt system is initialized.
+pub fn init_irq() {
+    crate::interrupt::request_irq(Irq::Serial0 as u8, receive, "serial")
+        .expect("Failed to request serial IRQ");
+    info!("Serial IRQ Enabled.");
+}
+
+/// Receive character from uart 16550, put it into INPUT_BUFFER
+fn receive() {
+    // another CPU may hold the lock to print
+    let ch = get_serial_blocking(|serial| serial.receive()).flatten();
+    if let Some(ch) = ch {
+        input::push_key(ch); //将数据放入缓冲区
+    }
+}
+
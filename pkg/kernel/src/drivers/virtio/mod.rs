@@ -0,0 +1,327 @@
+//! Virtio over PCI
+//!
+//! Supports both the legacy transport in IO BAR 0 and the virtio 1.0
+//! transport described by vendor specific PCI capabilities.
+//!
+//! Reference: Virtio 1.1, 4.1 Virtio Over PCI Bus
+
+mod queue;
+
+pub use queue::{Buffer, VirtQueue};
+
+use crate::drivers::pci::{Bar, PciDevice, CAP_VENDOR};
+use crate::memory::map_mmio;
+use core::ptr::{read_volatile, write_volatile};
+use x86_64::instructions::port::Port;
+
+pub const VIRTIO_VENDOR_ID: u16 = 0x1af4;
+
+// device status bits
+pub const STATUS_ACKNOWLEDGE: u8 = 1;
+pub const STATUS_DRIVER: u8 = 2;
+pub const STATUS_DRIVER_OK: u8 = 4;
+pub const STATUS_FEATURES_OK: u8 = 8;
+pub const STATUS_FAILED: u8 = 128;
+
+/// Device conforms to virtio 1.0
+pub const F_VERSION_1: u64 = 1 << 32;
+
+/// No MSI-X vector for an event
+pub const NO_VECTOR: u16 = 0xffff;
+
+// virtio 1.0 capability types
+const CAP_COMMON_CFG: u8 = 1;
+const CAP_NOTIFY_CFG: u8 = 2;
+const CAP_ISR_CFG: u8 = 3;
+const CAP_DEVICE_CFG: u8 = 4;
+
+// legacy registers in IO BAR 0
+const LEGACY_DEVICE_FEATURES: u16 = 0x00;
+const LEGACY_DRIVER_FEATURES: u16 = 0x04;
+const LEGACY_QUEUE_PFN: u16 = 0x08;
+const LEGACY_QUEUE_SIZE: u16 = 0x0C;
+const LEGACY_QUEUE_SELECT: u16 = 0x0E;
+const LEGACY_QUEUE_NOTIFY: u16 = 0x10;
+const LEGACY_STATUS: u16 = 0x12;
+const LEGACY_ISR: u16 = 0x13;
+const LEGACY_CONFIG_VECTOR: u16 = 0x14;
+const LEGACY_QUEUE_VECTOR: u16 = 0x16;
+
+// virtio 1.0 common configuration registers
+const COMMON_DEVICE_FEATURE_SELECT: u64 = 0x00;
+const COMMON_DEVICE_FEATURE: u64 = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: u64 = 0x08;
+const COMMON_DRIVER_FEATURE: u64 = 0x0C;
+const COMMON_CONFIG_VECTOR: u64 = 0x10;
+const COMMON_STATUS: u64 = 0x14;
+const COMMON_QUEUE_SELECT: u64 = 0x16;
+const COMMON_QUEUE_SIZE: u64 = 0x18;
+const COMMON_QUEUE_VECTOR: u64 = 0x1A;
+const COMMON_QUEUE_ENABLE: u64 = 0x1C;
+const COMMON_QUEUE_NOTIFY_OFF: u64 = 0x1E;
+const COMMON_QUEUE_DESC: u64 = 0x20;
+const COMMON_QUEUE_DRIVER: u64 = 0x28;
+const COMMON_QUEUE_DEVICE: u64 = 0x30;
+
+/// Largest queue the drivers use, smaller queues save DMA memory
+const MAX_QUEUE_SIZE: u16 = 128;
+
+pub enum Transport {
+    Legacy {
+        io: u16,
+        /// The device configuration moves when MSI-X is enabled
+        msix: bool,
+    },
+    Modern {
+        /// Virtual addresses of the configuration structures
+        common: u64,
+        notify: u64,
+        notify_multiplier: u32,
+        isr: u64,
+        device: u64,
+    },
+}
+
+/// Virtual address of the structure described by the capability at `cap`
+fn map_capability(dev: &PciDevice, cap: u8) -> Option<u64> {
+    let bar = dev.read(cap as u16 + 4) as u8;
+    let offset = dev.read(cap as u16 + 8) as u64;
+    let length = dev.read(cap as u16 + 12) as u64;
+
+    match dev.bars.get(bar as usize).copied().flatten() {
+        Some(Bar::Memory { address, .. }) => Some(map_mmio(address + offset, length)),
+        _ => None,
+    }
+}
+
+impl Transport {
+    /// Find the transport of a virtio device, preferring virtio 1.0
+    pub fn new(dev: &PciDevice) -> Option<Self> {
+        let (mut common, mut notify, mut isr, mut device) = (None, None, None, None);
+        let mut notify_multiplier = 0;
+
+        for cap in dev.capabilities.iter().filter(|c| c.id == CAP_VENDOR) {
+            let cfg_type = (dev.read(cap.offset as u16) >> 24) as u8;
+            let slot = match cfg_type {
+                CAP_COMMON_CFG => &mut common,
+                CAP_NOTIFY_CFG => {
+                    notify_multiplier = dev.read(cap.offset as u16 + 16);
+                    &mut notify
+                }
+                CAP_ISR_CFG => &mut isr,
+                CAP_DEVICE_CFG => &mut device,
+                _ => continue,
+            };
+            // use the first capability of each type
+            if slot.is_none() {
+                *slot = map_capability(dev, cap.offset);
+            }
+        }
+
+        if let (Some(common), Some(notify), Some(isr), Some(device)) = (common, notify, isr, device) {
+            return Some(Transport::Modern {
+                common,
+                notify,
+                notify_multiplier,
+                isr,
+                device,
+            });
+        }
+
+        match dev.bars[0] {
+            Some(Bar::Io { port, .. }) => Some(Transport::Legacy { io: port, msix: false }),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn is_modern(&self) -> bool {
+        matches!(self, Transport::Modern { .. })
+    }
+
+    pub fn status(&self) -> u8 {
+        match *self {
+            Transport::Legacy { io, .. } => unsafe { Port::<u8>::new(io + LEGACY_STATUS).read() },
+            Transport::Modern { common, .. } => unsafe {
+                read_volatile((common + COMMON_STATUS) as *const u8)
+            },
+        }
+    }
+
+    pub fn set_status(&self, status: u8) {
+        match *self {
+            Transport::Legacy { io, .. } => unsafe {
+                Port::<u8>::new(io + LEGACY_STATUS).write(status)
+            },
+            Transport::Modern { common, .. } => unsafe {
+                write_volatile((common + COMMON_STATUS) as *mut u8, status)
+            },
+        }
+    }
+
+    /// Reset the device and wait until it is done
+    pub fn reset(&self) {
+        self.set_status(0);
+        while self.status() != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    pub fn device_features(&self) -> u64 {
+        match *self {
+            Transport::Legacy { io, .. } => unsafe {
+                Port::<u32>::new(io + LEGACY_DEVICE_FEATURES).read() as u64
+            },
+            Transport::Modern { common, .. } => unsafe {
+                let mut features = 0;
+                for select in 0..2u32 {
+                    write_volatile((common + COMMON_DEVICE_FEATURE_SELECT) as *mut u32, select);
+                    let bits = read_volatile((common + COMMON_DEVICE_FEATURE) as *const u32);
+                    features |= (bits as u64) << (32 * select);
+                }
+                features
+            },
+        }
+    }
+
+    pub fn set_driver_features(&self, features: u64) {
+        match *self {
+            Transport::Legacy { io, .. } => unsafe {
+                Port::<u32>::new(io + LEGACY_DRIVER_FEATURES).write(features as u32)
+            },
+            Transport::Modern { common, .. } => unsafe {
+                for select in 0..2u32 {
+                    write_volatile((common + COMMON_DRIVER_FEATURE_SELECT) as *mut u32, select);
+                    write_volatile(
+                        (common + COMMON_DRIVER_FEATURE) as *mut u32,
+                        (features >> (32 * select)) as u32,
+                    );
+                }
+            },
+        }
+    }
+
+    /// Run the initialization up to feature negotiation,
+    /// returns the accepted features of `supported`.
+    pub fn negotiate(&self, supported: u64) -> Option<u64> {
+        self.reset();
+        self.set_status(STATUS_ACKNOWLEDGE);
+        self.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        let mut features = self.device_features() & supported;
+        if self.is_modern() {
+            features |= F_VERSION_1;
+        }
+        self.set_driver_features(features);
+
+        // legacy devices do not have FEATURES_OK
+        if self.is_modern() {
+            let status = STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK;
+            self.set_status(status);
+            if self.status() & STATUS_FEATURES_OK == 0 {
+                self.set_status(STATUS_FAILED);
+                return None;
+            }
+        }
+
+        Some(features)
+    }
+
+    /// Tell the device the driver is ready
+    pub fn driver_ok(&self) {
+        self.set_status(self.status() | STATUS_DRIVER_OK);
+    }
+
+    /// Create virtqueue `index` and give it to the device,
+    /// delivering its interrupts to MSI-X entry `vector`.
+    pub fn setup_queue(&mut self, index: u16, vector: Option<u16>) -> Option<VirtQueue> {
+        match *self {
+            Transport::Legacy { io, msix } => unsafe {
+                Port::<u16>::new(io + LEGACY_QUEUE_SELECT).write(index);
+                // the size of legacy queues can not be changed
+                let size = Port::<u16>::new(io + LEGACY_QUEUE_SIZE).read();
+                let queue = VirtQueue::new(index, size)?;
+
+                if msix {
+                    let mut port = Port::<u16>::new(io + LEGACY_QUEUE_VECTOR);
+                    port.write(vector.unwrap_or(NO_VECTOR));
+                    Port::<u16>::new(io + LEGACY_CONFIG_VECTOR).write(NO_VECTOR);
+                }
+
+                let (desc, _, _) = queue.addresses();
+                Port::<u32>::new(io + LEGACY_QUEUE_PFN).write((desc >> 12) as u32);
+                Some(queue)
+            },
+            Transport::Modern { common, .. } => unsafe {
+                let reg = |offset: u64| common + offset;
+                write_volatile(reg(COMMON_QUEUE_SELECT) as *mut u16, index);
+
+                let max = read_volatile(reg(COMMON_QUEUE_SIZE) as *const u16);
+                let size = max.min(MAX_QUEUE_SIZE);
+                let mut queue = VirtQueue::new(index, size)?;
+                write_volatile(reg(COMMON_QUEUE_SIZE) as *mut u16, size);
+
+                write_volatile(reg(COMMON_CONFIG_VECTOR) as *mut u16, NO_VECTOR);
+                write_volatile(reg(COMMON_QUEUE_VECTOR) as *mut u16, vector.unwrap_or(NO_VECTOR));
+
+                let (desc, driver, device) = queue.addresses();
+                write_volatile(reg(COMMON_QUEUE_DESC) as *mut u64, desc);
+                write_volatile(reg(COMMON_QUEUE_DRIVER) as *mut u64, driver);
+                write_volatile(reg(COMMON_QUEUE_DEVICE) as *mut u64, device);
+
+                queue.notify_off = read_volatile(reg(COMMON_QUEUE_NOTIFY_OFF) as *const u16);
+                write_volatile(reg(COMMON_QUEUE_ENABLE) as *mut u16, 1);
+                Some(queue)
+            },
+        }
+    }
+
+    /// Record that MSI-X has been enabled on the PCI function
+    pub fn set_msix(&mut self, enabled: bool) {
+        if let Transport::Legacy { msix, .. } = self {
+            *msix = enabled;
+        }
+    }
+
+    /// Tell the device there are new buffers in `queue`
+    pub fn notify(&self, queue: &VirtQueue) {
+        match *self {
+            Transport::Legacy { io, .. } => unsafe {
+                Port::<u16>::new(io + LEGACY_QUEUE_NOTIFY).write(queue.index())
+            },
+            Transport::Modern {
+                notify,
+                notify_multiplier,
+                ..
+            } => unsafe {
+                let addr = notify + queue.notify_off as u64 * notify_multiplier as u64;
+                write_volatile(addr as *mut u16, queue.index());
+            },
+        }
+    }
+
+    /// Read and clear the interrupt status, needed for INTx only
+    pub fn ack_interrupt(&self) -> u8 {
+        match *self {
+            Transport::Legacy { io, .. } => unsafe { Port::<u8>::new(io + LEGACY_ISR).read() },
+            Transport::Modern { isr, .. } => unsafe { read_volatile(isr as *const u8) },
+        }
+    }
+
+    /// Read the device specific configuration at `offset`
+    pub fn read_config(&self, offset: u16) -> u32 {
+        match *self {
+            Transport::Legacy { io, msix } => {
+                let base = if msix { 0x18 } else { 0x14 };
+                unsafe { Port::<u32>::new(io + base + offset).read() }
+            }
+            Transport::Modern { device, .. } => unsafe {
+                read_volatile((device + offset as u64) as *const u32)
+            },
+        }
+    }
+
+    pub fn read_config64(&self, offset: u16) -> u64 {
+        self.read_config(offset) as u64 | (self.read_config(offset + 4) as u64) << 32
+    }
+}
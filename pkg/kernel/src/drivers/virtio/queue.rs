@@ -0,0 +1,216 @@
+//! Split virtqueue
+//!
+//! The descriptor table, available ring and used ring are allocated
+//! together with the legacy layout, which also works for virtio 1.0.
+//!
+//! Reference: Virtio 1.1, 2.6 Split Virtqueues
+
+use crate::memory::{alloc_dma, physical_to_virtual, PAGE_SIZE};
+use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+/// Alignment of the used ring in the legacy layout
+const USED_ALIGN: usize = PAGE_SIZE as usize;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// A buffer in physical memory for the device
+#[derive(Debug, Clone, Copy)]
+pub struct Buffer {
+    pub addr: u64,
+    pub len: u32,
+    /// If the device writes to the buffer
+    pub writable: bool,
+}
+
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    /// Physical address of the descriptor table
+    phys: u64,
+    desc: *mut Descriptor,
+    /// flags, idx, ring[size], used_event
+    avail: *mut u16,
+    /// flags, idx, then the elements
+    used: *mut u16,
+    free_head: u16,
+    num_free: u16,
+    avail_idx: u16,
+    last_used: u16,
+    /// Queue notify offset of virtio 1.0 devices
+    pub notify_off: u16,
+}
+
+// the rings are only accessed through `&mut self`
+unsafe impl Send for VirtQueue {}
+unsafe impl Sync for VirtQueue {}
+
+#[inline]
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+impl VirtQueue {
+    /// Bytes of the descriptor table and available ring
+    fn driver_area_size(size: u16) -> usize {
+        size as usize * size_of::<Descriptor>() + (3 + size as usize) * size_of::<u16>()
+    }
+
+    /// Bytes of all structures in the legacy layout
+    pub fn layout_size(size: u16) -> usize {
+        align_up(Self::driver_area_size(size), USED_ALIGN)
+            + 3 * size_of::<u16>()
+            + size as usize * size_of::<UsedElem>()
+    }
+
+    pub fn new(index: u16, size: u16) -> Option<Self> {
+        if size == 0 || !size.is_power_of_two() {
+            return None;
+        }
+
+        let pages = align_up(Self::layout_size(size), PAGE_SIZE as usize) / PAGE_SIZE as usize;
+        let phys = alloc_dma(pages)?;
+        let virt = physical_to_virtual(phys);
+
+        let desc = virt as *mut Descriptor;
+        let avail = (virt as usize + size as usize * size_of::<Descriptor>()) as *mut u16;
+        let used = (virt as usize + align_up(Self::driver_area_size(size), USED_ALIGN)) as *mut u16;
+
+        // chain all descriptors into the free list
+        for i in 0..size {
+            unsafe {
+                (*desc.add(i as usize)).next = (i + 1) % size;
+            }
+        }
+
+        Some(Self {
+            index,
+            size,
+            phys,
+            desc,
+            avail,
+            used,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used: 0,
+            notify_off: 0,
+        })
+    }
+
+    #[inline]
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    #[inline]
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    #[inline]
+    pub fn num_free(&self) -> u16 {
+        self.num_free
+    }
+
+    /// Physical addresses of the descriptor table, available and used rings
+    pub fn addresses(&self) -> (u64, u64, u64) {
+        let offset = |ptr: u64| self.phys + (ptr - self.desc as u64);
+        (self.phys, offset(self.avail as u64), offset(self.used as u64))
+    }
+
+    /// Add a chain of `buffers`, returns the head descriptor as its token
+    ///
+    /// The device is not notified, so several chains can be batched.
+    pub fn add(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        if buffers.is_empty() || buffers.len() > self.num_free as usize {
+            return None;
+        }
+
+        let head = self.free_head;
+        let mut last = head;
+        for (i, buf) in buffers.iter().enumerate() {
+            let idx = if i == 0 { head } else { unsafe { (*self.desc.add(last as usize)).next } };
+            let desc = unsafe { &mut *self.desc.add(idx as usize) };
+            desc.addr = buf.addr;
+            desc.len = buf.len;
+            desc.flags = if buf.writable { DESC_F_WRITE } else { 0 };
+            if i + 1 < buffers.len() {
+                desc.flags |= DESC_F_NEXT;
+            }
+            last = idx;
+        }
+
+        self.free_head = unsafe { (*self.desc.add(last as usize)).next };
+        self.num_free -= buffers.len() as u16;
+
+        unsafe {
+            let slot = self.avail.add(2 + (self.avail_idx % self.size) as usize);
+            write_volatile(slot, head);
+            // the descriptors must be visible before the index
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            write_volatile(self.avail.add(1), self.avail_idx);
+            fence(Ordering::SeqCst);
+        }
+
+        Some(head)
+    }
+
+    /// If the device has returned any chain
+    #[inline]
+    pub fn has_used(&self) -> bool {
+        self.last_used != unsafe { read_volatile(self.used.add(1)) }
+    }
+
+    /// Take a chain returned by the device, returns its token
+    /// and the bytes written by the device.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.has_used() {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+
+        let elems = unsafe { self.used.add(2) as *const UsedElem };
+        let elem = unsafe { read_volatile(elems.add((self.last_used % self.size) as usize)) };
+        self.last_used = self.last_used.wrapping_add(1);
+
+        // return the chain to the free list
+        let head = elem.id as u16;
+        let mut idx = head;
+        let mut count = 1;
+        loop {
+            let desc = unsafe { &*self.desc.add(idx as usize) };
+            if desc.flags & DESC_F_NEXT == 0 {
+                break;
+            }
+            idx = desc.next;
+            count += 1;
+        }
+        unsafe {
+            (*self.desc.add(idx as usize)).next = self.free_head;
+        }
+        self.free_head = head;
+        self.num_free += count;
+
+        Some((head, elem.len))
+    }
+}
@@ -0,0 +1,200 @@
+//! Path resolution
+//!
+//! A path is resolved by walking from the root, each component gives a
+//! [`Dentry`] that remembers its parent, so `..` goes back the way the
+//! walk came, across mount points too. Relative paths start at the root,
+//! as processes have no working directory.
+
+use super::*;
+
+/// A name of an inode in the tree, as reached by a path walk
+pub struct Dentry {
+    name: String,
+    parent: Option<Arc<Dentry>>,
+    inode: Arc<dyn Inode>,
+}
+
+impl Dentry {
+    pub(super) fn root(inode: Arc<dyn Inode>) -> Arc<Self> {
+        Arc::new(Self {
+            name: String::new(),
+            parent: None,
+            inode,
+        })
+    }
+
+    /// Last component of the path, empty for the root
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    #[inline]
+    pub fn parent(&self) -> Option<&Arc<Dentry>> {
+        self.parent.as_ref()
+    }
+
+    /// Absolute path without `.` and `..`
+    pub fn path(&self) -> String {
+        match &self.parent {
+            None => String::from("/"),
+            Some(parent) if parent.parent.is_none() => format!("/{}", self.name),
+            Some(parent) => format!("{}/{}", parent.path(), self.name),
+        }
+    }
+
+    /// Absolute path of `name` in this directory
+    pub(super) fn child_path(&self, name: &str) -> String {
+        match &self.parent {
+            None => format!("/{}", name),
+            Some(_) => format!("{}/{}", self.path(), name),
+        }
+    }
+
+    /// Look up `name` in this directory, entering mounted filesystems
+    pub fn child(self: &Arc<Self>, name: &str) -> FsResult<Arc<Dentry>> {
+        // a mount point does not need to exist in the filesystem below it
+        let inode = match mount::mounted_at(&self.child_path(name)) {
+            Some(fs) => fs.root(),
+            None => self.inode.lookup(name)?,
+        };
+
+        Ok(Arc::new(Dentry {
+            name: name.into(),
+            parent: Some(self.clone()),
+            inode,
+        }))
+    }
+
+    /// Entries of this directory, with the filesystems mounted in it
+    pub fn read_dir(&self) -> FsResult<Vec<DirEntry>> {
+        let mut entries = self.inode.read_dir()?;
+
+        let path = self.path();
+        for mount in mount::mounts() {
+            let (dir, name) = match mount.path.rsplit_once('/') {
+                Some(("", name)) => ("/", name),
+                Some(split) => split,
+                None => continue,
+            };
+            if dir != path || name.is_empty() || entries.iter().any(|e| e.name == name) {
+                continue;
+            }
+            entries.push(DirEntry {
+                name: name.into(),
+                ty: FileType::Directory,
+                inode: mount.fs.root().metadata()?.inode,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+/// Resolve `path` to a dentry
+pub fn resolve(path: &str) -> FsResult<Arc<Dentry>> {
+    walk(mount::root()?, path)
+}
+
+fn walk(mut current: Arc<Dentry>, path: &str) -> FsResult<Arc<Dentry>> {
+    for name in path.split('/') {
+        match name {
+            "" | "." => {}
+            ".." => {
+                if let Some(parent) = current.parent.clone() {
+                    current = parent;
+                }
+            }
+            name => {
+                if !current.inode.metadata()?.is_dir() {
+                    return Err(FsError::NotADirectory);
+                }
+                current = current.child(name)?;
+            }
+        }
+    }
+    Ok(current)
+}
+
+/// Resolve the directory containing `path`, returns it with the last component
+pub fn resolve_parent(path: &str) -> FsResult<(Arc<Dentry>, &str)> {
+    let path = path.trim_end_matches('/');
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (dir, name),
+        None => ("", path),
+    };
+
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(FsError::InvalidPath);
+    }
+
+    let parent = resolve(dir)?;
+    if !parent.inode.metadata()?.is_dir() {
+        return Err(FsError::NotADirectory);
+    }
+    Ok((parent, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::tree;
+    use super::*;
+
+    #[test]
+    fn walk_paths() {
+        let _tree = tree();
+        create_dir("/mnt/empty").unwrap();
+
+        for path in ["mnt/data/file", "//mnt/./data//file", "/mnt/empty/../data/file"] {
+            let dentry = resolve(path).unwrap();
+            assert_eq!((dentry.name(), dentry.path()), ("file", "/mnt/data/file".into()));
+        }
+        assert_eq!(resolve("").unwrap().path(), "/");
+        assert_eq!(resolve("/mnt/missing").err(), Some(FsError::NotFound));
+        assert_eq!(resolve("/mnt/data/file/x").err(), Some(FsError::NotADirectory));
+        // `..` does not look into the file
+        assert_eq!(resolve("/mnt/data/file/..").unwrap().path(), "/mnt/data");
+
+        let (parent, name) = resolve_parent("/mnt/data/new/").unwrap();
+        assert_eq!((parent.path(), name), ("/mnt/data".into(), "new"));
+        let (parent, name) = resolve_parent("top").unwrap();
+        assert_eq!((parent.path(), name), ("/".into(), "top"));
+        for path in ["/", "/mnt/.", "/mnt/..", ""] {
+            assert_eq!(resolve_parent(path).err(), Some(FsError::InvalidPath));
+        }
+        assert_eq!(resolve_parent("/mnt/data/file/x").err(), Some(FsError::NotADirectory));
+        assert_eq!(resolve_parent("/missing/x").err(), Some(FsError::NotFound));
+    }
+
+    #[test]
+    fn mount_points() {
+        let _tree = tree();
+
+        // `..` from the root of a mounted filesystem goes back to the one below
+        let data = resolve("/mnt/data").unwrap();
+        assert_eq!(data.inode().read_dir().unwrap().len(), 1);
+        let mnt = resolve("/mnt/data/..").unwrap();
+        assert_eq!(mnt.path(), "/mnt");
+        assert!(Arc::ptr_eq(mnt.inode(), &resolve("/mnt").unwrap().inode().clone()));
+        assert_eq!(resolve("/mnt/data/./../..").unwrap().path(), "/");
+        assert_eq!(resolve("/..").unwrap().path(), "/");
+
+        // the mount point is listed, though it is not in the directory
+        let entries = resolve("/mnt").unwrap().read_dir().unwrap();
+        let names: Vec<_> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["data"]);
+        assert!(resolve("/mnt").unwrap().inode().read_dir().unwrap().is_empty());
+
+        // a directory on the filesystem below is hidden by the mount
+        create_dir("/mnt/other").unwrap();
+        write("/mnt/other/below", b"").unwrap();
+        mount("/mnt/other", "other", RamFs::new()).unwrap();
+        assert_eq!(resolve("/mnt/other/below").err(), Some(FsError::NotFound));
+        assert_eq!(read_dir("/mnt").unwrap().len(), 2);
+        assert_eq!(mount("/mnt/other", "again", RamFs::new()).err(), Some(FsError::Busy));
+    }
+}
@@ -0,0 +1,211 @@
+//! Boot sector and BIOS parameter block
+
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl core::fmt::Display for FatType {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            FatType::Fat12 => write!(f, "FAT12"),
+            FatType::Fat16 => write!(f, "FAT16"),
+            FatType::Fat32 => write!(f, "FAT32"),
+        }
+    }
+}
+
+/// Layout of a volume, in sectors of `BLOCK_SIZE` bytes
+#[derive(Debug, Clone)]
+pub struct Bpb {
+    pub fat_type: FatType,
+    pub sectors_per_cluster: u32,
+    /// First sector of the first FAT
+    pub reserved_sectors: u32,
+    pub fat_count: u32,
+    /// Sectors of each FAT
+    pub fat_sectors: u32,
+    /// Entries of the fixed root directory, 0 on FAT32
+    pub root_entries: u32,
+    /// First sector of the fixed root directory
+    pub root_sector: u32,
+    /// First cluster of the root directory on FAT32
+    pub root_cluster: u32,
+    /// First sector of cluster 2
+    pub data_sector: u32,
+    /// Number of data clusters, valid clusters are `2..cluster_count + 2`
+    pub cluster_count: u32,
+    pub total_sectors: u32,
+    /// Sector of the FAT32 FSInfo structure
+    pub fsinfo_sector: Option<u32>,
+    pub label: [u8; 11],
+}
+
+impl Bpb {
+    pub fn parse(sector: &[u8; BLOCK_SIZE]) -> FatResult<Self> {
+        if sector[510..512] != [0x55, 0xAA] || ![0xEB, 0xE9].contains(&sector[0]) {
+            return Err(FatError::InvalidFs);
+        }
+
+        let bytes_per_sector = u16_at(sector, 11) as usize;
+        let sectors_per_cluster = sector[13] as u32;
+        let reserved_sectors = u16_at(sector, 14) as u32;
+        let fat_count = sector[16] as u32;
+        let root_entries = u16_at(sector, 17) as u32;
+
+        let total_sectors = match u16_at(sector, 19) {
+            0 => u32_at(sector, 32),
+            count => count as u32,
+        };
+        let fat_sectors = match u16_at(sector, 22) {
+            0 => u32_at(sector, 36),
+            count => count as u32,
+        };
+
+        if !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || fat_count == 0
+            || fat_sectors == 0
+            || !(512..=4096).contains(&bytes_per_sector)
+            || !bytes_per_sector.is_power_of_two()
+        {
+            return Err(FatError::InvalidFs);
+        }
+
+        if bytes_per_sector != BLOCK_SIZE {
+            warn!("FAT: {} bytes sectors are not supported.", bytes_per_sector);
+            return Err(FatError::Unsupported);
+        }
+
+        let root_sector = reserved_sectors + fat_count * fat_sectors;
+        let root_sectors = (root_entries * ENTRY_SIZE as u32).div_ceil(BLOCK_SIZE as u32);
+        let data_sector = root_sector + root_sectors;
+        if data_sector >= total_sectors {
+            return Err(FatError::InvalidFs);
+        }
+
+        // the type is decided by the number of clusters only
+        let cluster_count = (total_sectors - data_sector) / sectors_per_cluster;
+        if cluster_count == 0 {
+            return Err(FatError::InvalidFs);
+        }
+        let fat_type = if cluster_count < 4085 {
+            FatType::Fat12
+        } else if cluster_count < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+
+        let (root_cluster, fsinfo_sector, label_offset) = if fat_type == FatType::Fat32 {
+            let root_cluster = u32_at(sector, 44);
+            if root_entries != 0 || root_cluster < 2 || root_cluster >= cluster_count + 2 {
+                return Err(FatError::InvalidFs);
+            }
+            let fsinfo = match u16_at(sector, 48) {
+                0 | 0xFFFF => None,
+                fsinfo => Some(fsinfo as u32),
+            };
+            (root_cluster, fsinfo, 71)
+        } else {
+            if root_entries == 0 {
+                return Err(FatError::InvalidFs);
+            }
+            (0, None, 43)
+        };
+
+        Ok(Self {
+            fat_type,
+            sectors_per_cluster,
+            reserved_sectors,
+            fat_count,
+            fat_sectors,
+            root_entries,
+            root_sector,
+            root_cluster,
+            data_sector,
+            cluster_count,
+            total_sectors,
+            fsinfo_sector,
+            label: sector[label_offset..label_offset + 11].try_into().unwrap(),
+        })
+    }
+
+    /// Bytes of a cluster
+    #[inline]
+    pub fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster as usize * BLOCK_SIZE
+    }
+
+    /// Byte offset of `cluster` on the volume
+    #[inline]
+    pub fn cluster_offset(&self, cluster: u32) -> u64 {
+        let sector = self.data_sector as u64 + (cluster as u64 - 2) * self.sectors_per_cluster as u64;
+        sector * BLOCK_SIZE as u64
+    }
+
+    /// Whether `cluster` is a data cluster of the volume
+    #[inline]
+    pub fn is_valid_cluster(&self, cluster: u32) -> bool {
+        (2..self.cluster_count + 2).contains(&cluster)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{boot_sector, Layout, FAT12, FAT16, FAT32};
+    use super::*;
+
+    #[test]
+    fn fat_types() {
+        let volumes = [
+            (FAT12, FatType::Fat12, 27, 2021),
+            (FAT16, FatType::Fat16, 97, 8095),
+            (FAT32, FatType::Fat32, 1074, 65526),
+        ];
+        for (layout, fat_type, data_sector, cluster_count) in volumes {
+            let bpb = Bpb::parse(&boot_sector(layout)).unwrap();
+            assert_eq!(bpb.fat_type, fat_type);
+            assert_eq!(bpb.data_sector, data_sector);
+            assert_eq!(bpb.cluster_count, cluster_count);
+            assert_eq!(bpb.root_entries, layout.root_entries as u32);
+            assert_eq!(&bpb.label, b"NO NAME    ");
+        }
+
+        let bpb = Bpb::parse(&boot_sector(FAT32)).unwrap();
+        assert_eq!((bpb.root_cluster, bpb.fsinfo_sector), (2, Some(1)));
+        assert!(bpb.is_valid_cluster(65527) && !bpb.is_valid_cluster(65528));
+    }
+
+    #[test]
+    fn invalid_volumes() {
+        // room for the metadata but not for a single cluster
+        let empty = Layout {
+            total: 28,
+            cluster: 2,
+            ..FAT12
+        };
+        assert_eq!(Bpb::parse(&boot_sector(empty)).unwrap_err(), FatError::InvalidFs);
+
+        let mut sector = boot_sector(FAT16);
+        sector[510] = 0;
+        assert_eq!(Bpb::parse(&sector).unwrap_err(), FatError::InvalidFs);
+
+        let mut sector = boot_sector(FAT16);
+        sector[13] = 3;
+        assert_eq!(Bpb::parse(&sector).unwrap_err(), FatError::InvalidFs);
+
+        let mut sector = boot_sector(FAT16);
+        sector[11..13].copy_from_slice(&4096u16.to_le_bytes());
+        assert_eq!(Bpb::parse(&sector).unwrap_err(), FatError::Unsupported);
+
+        // the root directory must be a data cluster
+        let mut sector = boot_sector(FAT32);
+        sector[44..48].copy_from_slice(&65528u32.to_le_bytes());
+        assert_eq!(Bpb::parse(&sector).unwrap_err(), FatError::InvalidFs);
+    }
+}
@@ -0,0 +1,630 @@
+//! Directory entries and long file names
+//!
+//! A long name is stored in the entries before the short entry of a file,
+//! 13 UCS-2 characters each, last part first.
+
+use super::*;
+use alloc::vec;
+
+pub(super) const ENTRY_SIZE: usize = 32;
+
+/// Directories hold at most 65536 entries
+const MAX_ENTRIES: u32 = 65536;
+
+const END: u8 = 0x00;
+const DELETED: u8 = 0xE5;
+/// First byte of a short name starting with 0xE5
+const KANJI_E5: u8 = 0x05;
+
+const LFN_LAST: u8 = 0x40;
+const LFN_CHARS: usize = 13;
+const LFN_MAX: usize = 255;
+/// Offsets of the characters in a long name entry
+const LFN_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+// case of short names, used by Windows NT
+const NT_LOWER_BASE: u8 = 0x08;
+const NT_LOWER_EXT: u8 = 0x10;
+
+/// 1980-01-01, there is no real time clock to date files yet
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Attributes: u8 {
+        const READ_ONLY = 0x01;
+        const HIDDEN = 0x02;
+        const SYSTEM = 0x04;
+        const VOLUME_ID = 0x08;
+        const DIRECTORY = 0x10;
+        const ARCHIVE = 0x20;
+        const LONG_NAME = 0x0F;
+    }
+}
+
+type RawEntry = [u8; ENTRY_SIZE];
+
+/// Where the entries of a file are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Location {
+    /// First cluster of the directory, 0 for a fixed root directory
+    pub dir: u32,
+    /// Index of the short entry in the directory
+    pub index: u32,
+    /// Long name entries before the short entry
+    pub lfn_count: u32,
+}
+
+/// A file or directory of a FAT volume
+#[derive(Debug, Clone)]
+pub struct FatEntry {
+    name: String,
+    short_name: [u8; 11],
+    attributes: Attributes,
+    pub(super) cluster: u32,
+    pub(super) size: u32,
+    /// `None` for the root directory
+    pub(super) location: Option<Location>,
+}
+
+impl FatEntry {
+    pub(super) fn root(cluster: u32) -> Self {
+        Self {
+            name: String::from("/"),
+            short_name: [b' '; 11],
+            attributes: Attributes::DIRECTORY,
+            cluster,
+            size: 0,
+            location: None,
+        }
+    }
+
+    /// Long name if there is one, otherwise the short name
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The 8.3 name, like `KERNEL.ELF`
+    pub fn short_name(&self) -> String {
+        display_short_name(&self.short_name, 0)
+    }
+
+    #[inline]
+    pub fn attributes(&self) -> Attributes {
+        self.attributes
+    }
+
+    #[inline]
+    pub fn is_dir(&self) -> bool {
+        self.attributes.contains(Attributes::DIRECTORY)
+    }
+
+    #[inline]
+    pub fn is_root(&self) -> bool {
+        self.location.is_none()
+    }
+
+    /// Size in bytes, 0 for directories
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.size as u64
+    }
+
+    /// First cluster of the content, 0 if empty
+    #[inline]
+    pub fn cluster(&self) -> u32 {
+        self.cluster
+    }
+
+    /// Number identifying the entry on the volume, 1 for the root
+    pub fn id(&self) -> u64 {
+        match self.location {
+            None => 1,
+            Some(location) => ((location.dir as u64) << 16 | location.index as u64) + 2,
+        }
+    }
+
+    /// Whether `name` refers to this entry, names are case insensitive
+    pub fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name) || self.short_name().eq_ignore_ascii_case(name)
+    }
+
+    #[inline]
+    fn is_dot(&self) -> bool {
+        self.short_name[0] == b'.'
+    }
+}
+
+/// Checksum of a short name, stored in its long name entries
+fn checksum(name: &[u8; 11]) -> u8 {
+    name.iter()
+        .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+fn display_short_name(name: &[u8; 11], nt_flags: u8) -> String {
+    let part = |bytes: &[u8], lower: bool| -> String {
+        let mut s: String = bytes.iter().map(|&b| b as char).collect();
+        s.truncate(s.trim_end_matches(' ').len());
+        if lower {
+            s.make_ascii_lowercase();
+        }
+        s
+    };
+
+    let mut base = name[..8].to_vec();
+    if base[0] == KANJI_E5 {
+        base[0] = DELETED;
+    }
+
+    let mut s = part(&base, nt_flags & NT_LOWER_BASE != 0);
+    let ext = part(&name[8..], nt_flags & NT_LOWER_EXT != 0);
+    if !ext.is_empty() {
+        s.push('.');
+        s.push_str(&ext);
+    }
+    s
+}
+
+#[inline]
+fn is_short_char(c: u8) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || b"!#$%&'()-@^_`{}~".contains(&c)
+}
+
+/// Check a long name, returns it without the trailing dots and spaces
+fn validate_name(name: &str) -> FatResult<&str> {
+    let name = name.trim_end_matches(['.', ' ']);
+    if name.is_empty()
+        || name.encode_utf16().count() > LFN_MAX
+        || name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c))
+    {
+        return Err(FatError::InvalidName);
+    }
+    Ok(name)
+}
+
+/// The short name of `name` if it fits 8.3 by itself, with the case flags
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = match name.rsplit_once('.') {
+        Some((base, ext)) => (base, ext),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || base.contains('.') {
+        return None;
+    }
+
+    let mut short = [b' '; 11];
+    let mut flags = 0;
+    for (part, dest, lower_flag) in [(base, 0, NT_LOWER_BASE), (ext, 8, NT_LOWER_EXT)] {
+        let has_lower = part.bytes().any(|b| b.is_ascii_lowercase());
+        let has_upper = part.bytes().any(|b| b.is_ascii_uppercase());
+        if has_lower && has_upper {
+            return None;
+        }
+        if has_lower {
+            flags |= lower_flag;
+        }
+
+        for (i, b) in part.bytes().enumerate() {
+            let b = b.to_ascii_uppercase();
+            if !is_short_char(b) {
+                return None;
+            }
+            short[dest + i] = b;
+        }
+    }
+
+    if short[0] == DELETED {
+        short[0] = KANJI_E5;
+    }
+    Some((short, flags))
+}
+
+/// Generate a short name `BASE~N.EXT` for a long name, unique among `existing`
+fn numbered_short_name(name: &str, existing: &[[u8; 11]]) -> FatResult<[u8; 11]> {
+    let convert = |part: &str, max: usize| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| {
+                let b = if c.is_ascii() { c.to_ascii_uppercase() as u8 } else { b'_' };
+                if is_short_char(b) { b } else { b'_' }
+            })
+            .take(max)
+            .collect()
+    };
+
+    let name = name.trim_start_matches('.');
+    let (base, ext) = match name.rsplit_once('.') {
+        Some((base, ext)) => (convert(base, 8), convert(ext, 3)),
+        None => (convert(name, 8), Vec::new()),
+    };
+
+    for n in 1..1_000_000 {
+        let tail = format!("~{}", n);
+        let keep = base.len().min(8 - tail.len());
+
+        let mut short = [b' '; 11];
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        short[8..8 + ext.len()].copy_from_slice(&ext);
+
+        if !existing.contains(&short) {
+            return Ok(short);
+        }
+    }
+    Err(FatError::NoSpace)
+}
+
+fn short_entry(name: &[u8; 11], attributes: Attributes, nt_flags: u8, cluster: u32, size: u32) -> RawEntry {
+    let mut entry = [0u8; ENTRY_SIZE];
+    entry[..11].copy_from_slice(name);
+    entry[11] = attributes.bits();
+    entry[12] = nt_flags;
+    entry[16..18].copy_from_slice(&DOS_DATE.to_le_bytes());
+    entry[18..20].copy_from_slice(&DOS_DATE.to_le_bytes());
+    entry[24..26].copy_from_slice(&DOS_DATE.to_le_bytes());
+    set_entry_data(&mut entry, cluster, size);
+    entry
+}
+
+fn set_entry_data(entry: &mut RawEntry, cluster: u32, size: u32) {
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+}
+
+/// Long name entries of `name`, in on-disk order
+fn lfn_entries(name: &str, short: &[u8; 11]) -> Vec<RawEntry> {
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.len().div_ceil(LFN_CHARS);
+    // terminated by a null unless it fills the last entry, padded with 0xFFFF
+    if units.len() % LFN_CHARS != 0 {
+        units.push(0);
+    }
+    units.resize(count * LFN_CHARS, 0xFFFF);
+
+    let sum = checksum(short);
+    (1..=count)
+        .rev()
+        .map(|ord| {
+            let mut entry = [0u8; ENTRY_SIZE];
+            entry[0] = ord as u8 | if ord == count { LFN_LAST } else { 0 };
+            entry[11] = Attributes::LONG_NAME.bits();
+            entry[13] = sum;
+            for (i, &offset) in LFN_OFFSETS.iter().enumerate() {
+                let unit = units[(ord - 1) * LFN_CHARS + i];
+                entry[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            entry
+        })
+        .collect()
+}
+
+/// Long name being collected from its entries
+struct LongName {
+    units: Vec<u16>,
+    count: u8,
+    checksum: u8,
+    /// Order of the next expected entry
+    next: u8,
+}
+
+impl LongName {
+    fn add(&mut self, entry: &RawEntry) {
+        let ord = entry[0] & 0x1F;
+        let start = (ord as usize - 1) * LFN_CHARS;
+        for (i, &offset) in LFN_OFFSETS.iter().enumerate() {
+            self.units[start + i] = u16_at(entry, offset);
+        }
+        self.next = ord - 1;
+    }
+
+    fn name(&self) -> String {
+        let end = self.units.iter().position(|&u| u == 0).unwrap_or(self.units.len());
+        char::decode_utf16(self.units[..end].iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect()
+    }
+}
+
+impl FatFs {
+    /// Byte offset of entry `index` of directory `dir`, the directory is
+    /// extended when `state` is given and the entry is past its end.
+    fn entry_offset(&self, mut state: Option<&mut State>, dir: u32, index: u32) -> FatResult<u64> {
+        if index >= MAX_ENTRIES {
+            return Err(FatError::NoSpace);
+        }
+
+        let offset = index as u64 * ENTRY_SIZE as u64;
+        if dir == 0 {
+            if index >= self.bpb.root_entries {
+                return Err(FatError::NoSpace);
+            }
+            return Ok(self.bpb.root_sector as u64 * BLOCK_SIZE as u64 + offset);
+        }
+
+        let size = self.bpb.cluster_size() as u64;
+        let mut cluster = dir;
+        for _ in 0..offset / size {
+            cluster = match (self.next_cluster(cluster)?, state.as_deref_mut()) {
+                (Some(next), _) => next,
+                (None, Some(state)) => self.alloc_cluster(state, Some(cluster))?,
+                (None, None) => return Err(FatError::InvalidFs),
+            };
+        }
+        Ok(self.bpb.cluster_offset(cluster) + offset % size)
+    }
+
+    /// Entries of directory `dir` up to the end marker
+    fn raw_entries(&self, dir: u32) -> FatResult<Vec<RawEntry>> {
+        let mut entries = Vec::new();
+        let mut push_all = |buf: &[u8]| {
+            for entry in buf.chunks_exact(ENTRY_SIZE) {
+                if entry[0] == END {
+                    return true;
+                }
+                entries.push(entry.try_into().unwrap());
+            }
+            false
+        };
+
+        if dir == 0 {
+            let mut buf = vec![0u8; self.bpb.root_entries as usize * ENTRY_SIZE];
+            self.read_at(self.bpb.root_sector as u64 * BLOCK_SIZE as u64, &mut buf)?;
+            push_all(&buf);
+        } else {
+            let mut buf = vec![0u8; self.bpb.cluster_size()];
+            let mut cluster = Some(dir);
+            let mut count = 0;
+            while let Some(current) = cluster {
+                self.read_at(self.bpb.cluster_offset(current), &mut buf)?;
+                if push_all(&buf) {
+                    break;
+                }
+
+                count += 1;
+                if count > self.bpb.cluster_count {
+                    return Err(FatError::InvalidFs);
+                }
+                cluster = self.next_cluster(current)?;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Files of directory `dir`, including `.` and `..`
+    pub(super) fn dir_entries(&self, dir: u32) -> FatResult<Vec<FatEntry>> {
+        let mut entries = Vec::new();
+        let mut long_name: Option<LongName> = None;
+
+        for (index, raw) in self.raw_entries(dir)?.iter().enumerate() {
+            if raw[0] == DELETED {
+                long_name = None;
+                continue;
+            }
+
+            let attributes = Attributes::from_bits_retain(raw[11]);
+            if attributes & Attributes::LONG_NAME == Attributes::LONG_NAME {
+                let ord = raw[0] & 0x1F;
+                if ord == 0 || ord as usize * LFN_CHARS > LFN_MAX + LFN_CHARS {
+                    long_name = None;
+                } else if raw[0] & LFN_LAST != 0 {
+                    let mut name = LongName {
+                        units: vec![0xFFFF; ord as usize * LFN_CHARS],
+                        count: ord,
+                        checksum: raw[13],
+                        next: ord,
+                    };
+                    name.add(raw);
+                    long_name = Some(name);
+                } else if let Some(name) = long_name.as_mut().filter(|n| n.next == ord && n.checksum == raw[13]) {
+                    name.add(raw);
+                } else {
+                    long_name = None;
+                }
+                continue;
+            }
+
+            let lfn = long_name.take();
+            if attributes.contains(Attributes::VOLUME_ID) {
+                continue;
+            }
+
+            let short_name: [u8; 11] = raw[..11].try_into().unwrap();
+            let (name, lfn_count) = match lfn.filter(|n| n.next == 0 && n.checksum == checksum(&short_name)) {
+                Some(lfn) => (lfn.name(), lfn.count as u32),
+                None => (display_short_name(&short_name, raw[12]), 0),
+            };
+
+            entries.push(FatEntry {
+                name,
+                short_name,
+                attributes,
+                cluster: ((u16_at(raw, 20) as u32) << 16) | u16_at(raw, 26) as u32,
+                size: u32_at(raw, 28),
+                location: Some(Location {
+                    dir,
+                    index: index as u32,
+                    lfn_count,
+                }),
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Files of directory `dir`, without `.` and `..`
+    pub(super) fn list(&self, dir: u32) -> FatResult<Vec<FatEntry>> {
+        let mut entries = self.dir_entries(dir)?;
+        entries.retain(|e| !e.is_dot());
+        Ok(entries)
+    }
+
+    /// Add an entry named `name` to directory `dir`
+    pub(super) fn insert_entry(
+        &self,
+        state: &mut State,
+        dir: u32,
+        name: &str,
+        attributes: Attributes,
+        cluster: u32,
+    ) -> FatResult<FatEntry> {
+        let name = validate_name(name)?;
+        let existing = self.dir_entries(dir)?;
+        if existing.iter().any(|e| e.matches(name)) {
+            return Err(FatError::AlreadyExists);
+        }
+
+        let (short, nt_flags, lfn) = match exact_short_name(name) {
+            Some((short, flags)) => (short, flags, Vec::new()),
+            None => {
+                let shorts: Vec<_> = existing.iter().map(|e| e.short_name).collect();
+                let short = numbered_short_name(name, &shorts)?;
+                (short, 0, lfn_entries(name, &short))
+            }
+        };
+
+        // find a run of free slots, or use the end of the directory
+        let raw = self.raw_entries(dir)?;
+        let needed = lfn.len() + 1;
+        let mut start = raw.len();
+        let mut run = 0;
+        for (index, entry) in raw.iter().enumerate() {
+            if entry[0] == DELETED {
+                run += 1;
+                if run == needed {
+                    start = index + 1 - needed;
+                    break;
+                }
+            } else {
+                run = 0;
+            }
+        }
+        if start == raw.len() {
+            // a free run at the end continues past the end marker
+            start -= run;
+        }
+
+        let entry = short_entry(&short, attributes, nt_flags, cluster, 0);
+        for (i, raw) in lfn.iter().chain(core::iter::once(&entry)).enumerate() {
+            let offset = self.entry_offset(Some(state), dir, (start + i) as u32)?;
+            self.write_at(offset, raw)?;
+        }
+
+        Ok(FatEntry {
+            name: name.into(),
+            short_name: short,
+            attributes,
+            cluster,
+            size: 0,
+            location: Some(Location {
+                dir,
+                index: (start + lfn.len()) as u32,
+                lfn_count: lfn.len() as u32,
+            }),
+        })
+    }
+
+    /// Write the first cluster and size of `entry` back to its directory
+    pub(super) fn update_entry(&self, entry: &FatEntry) -> FatResult {
+        let location = match entry.location {
+            Some(location) => location,
+            None => return Ok(()),
+        };
+
+        let offset = self.entry_offset(None, location.dir, location.index)?;
+        let mut raw = [0u8; ENTRY_SIZE];
+        self.read_at(offset, &mut raw)?;
+        set_entry_data(&mut raw, entry.cluster, entry.size);
+        raw[11] = entry.attributes.bits();
+        raw[24..26].copy_from_slice(&DOS_DATE.to_le_bytes());
+        self.write_at(offset, &raw)
+    }
+
+    /// Mark the entries of `entry` as deleted
+    pub(super) fn remove_entry(&self, entry: &FatEntry) -> FatResult {
+        let location = entry.location.ok_or(FatError::InvalidName)?;
+
+        for index in location.index - location.lfn_count..=location.index {
+            let offset = self.entry_offset(None, location.dir, index)?;
+            self.write_at(offset, &[DELETED])?;
+        }
+        Ok(())
+    }
+
+    /// Write the `.` and `..` entries of a new directory at `cluster`
+    pub(super) fn init_dir(&self, cluster: u32, parent: u32) -> FatResult {
+        let offset = self.bpb.cluster_offset(cluster);
+        let mut dot = [b' '; 11];
+        dot[0] = b'.';
+        self.write_at(offset, &short_entry(&dot, Attributes::DIRECTORY, 0, cluster, 0))?;
+        dot[1] = b'.';
+        self.write_at(offset + ENTRY_SIZE as u64, &short_entry(&dot, Attributes::DIRECTORY, 0, parent, 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{format, FAT16};
+    use super::*;
+
+    fn short(name: &str) -> [u8; 11] {
+        name.as_bytes().try_into().unwrap()
+    }
+
+    #[test]
+    fn long_names() {
+        assert_eq!(checksum(&short("FOO     BAR")), 0x53);
+
+        let fs = FatFs::mount(format(FAT16)).unwrap();
+        let root = fs.root();
+        let long = "x".repeat(250) + ".text";
+        // 13 characters fill an entry without a terminator
+        let names = ["exactly13char", "Ünïcödé name ✓.txt", long.as_str()];
+        for name in names {
+            let short_name = fs.create(&root, name).unwrap().short_name;
+            let entries = lfn_entries(name, &short_name);
+            assert_eq!(entries.len(), name.encode_utf16().count().div_ceil(LFN_CHARS));
+            assert_eq!(entries[0][0], entries.len() as u8 | LFN_LAST);
+            assert!(entries.iter().all(|e| e[13] == checksum(&short_name)));
+        }
+
+        let listed: Vec<_> = fs.read_dir(&root).unwrap();
+        let listed: Vec<_> = listed.iter().map(|e| e.name()).collect();
+        assert_eq!(listed, names);
+        assert_eq!(fs.create(&root, &(long + "x")).unwrap_err(), FatError::InvalidName);
+    }
+
+    #[test]
+    fn long_name_checksum_mismatch() {
+        let fs = FatFs::mount(format(FAT16)).unwrap();
+        let file = fs.create(&fs.root(), "mismatch.text").unwrap();
+        let location = file.location.unwrap();
+        assert_eq!(location.lfn_count, 1);
+
+        // the short name is used when the long one belongs to another entry
+        let offset = fs.entry_offset(None, 0, location.index - 1).unwrap();
+        fs.write_at(offset + 13, &[checksum(&file.short_name) ^ 1]).unwrap();
+        let entry = &fs.read_dir(&fs.root()).unwrap()[0];
+        assert_eq!(entry.name(), "MISMAT~1.TEX");
+    }
+
+    #[test]
+    fn numbered_short_names() {
+        assert_eq!(numbered_short_name("long file.txt", &[]), Ok(short("LONGFI~1TXT")));
+
+        let mut existing = Vec::new();
+        for _ in 0..9 {
+            existing.push(numbered_short_name("long file.txt", &existing).unwrap());
+        }
+        assert_eq!(existing[8], short("LONGFI~9TXT"));
+        // the base is cut shorter to fit a longer number
+        assert_eq!(numbered_short_name("long file.txt", &existing), Ok(short("LONGF~10TXT")));
+
+        // leading dots and spaces are dropped, other characters become `_`
+        assert_eq!(numbered_short_name(".hidden+file", &[]), Ok(short("HIDDEN~1   ")));
+        assert_eq!(numbered_short_name("ü b.tar.gz", &[]), Ok(short("_BTAR~1 GZ ")));
+        assert_eq!(numbered_short_name("ReadMe.md", &[]), Ok(short("README~1MD ")));
+    }
+}
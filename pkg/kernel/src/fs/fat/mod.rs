@@ -0,0 +1,646 @@
+//! FAT12/16/32 filesystem
+//!
+//! Volumes are accessed through a [`BlockCache`], so the FAT and
+//! directories are not read from the disk again for each operation.
+//! Dates are not maintained as there is no clock yet.
+//!
+//! Reference: Microsoft FAT Specification (2005)
+
+mod bpb;
+mod dir;
+mod table;
+mod vfs;
+
+pub use bpb::{Bpb, FatType};
+pub use dir::{Attributes, FatEntry};
+pub use vfs::FatFileSystem;
+
+use crate::drivers::block::{self, BlockCache, BlockDevice, BlockError, BLOCK_SIZE};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use dir::ENTRY_SIZE;
+use spin::{Mutex, RwLock};
+
+/// Blocks cached for each volume
+const CACHE_BLOCKS: usize = 256;
+
+const FSINFO_LEAD_SIG: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIG: u32 = 0x6141_7272;
+const FSINFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatError {
+    Device(BlockError),
+    /// Not a FAT volume, or a corrupted one
+    InvalidFs,
+    Unsupported,
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    DirectoryNotEmpty,
+    InvalidName,
+    NoSpace,
+    ReadOnly,
+}
+
+impl From<BlockError> for FatError {
+    fn from(err: BlockError) -> Self {
+        FatError::Device(err)
+    }
+}
+
+pub type FatResult<T = ()> = Result<T, FatError>;
+
+#[inline]
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+#[inline]
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+/// Allocation state, its lock also serializes modifications of the volume
+struct State {
+    /// Where to start looking for a free cluster
+    next_free: u32,
+    /// The FSInfo sector is out of date
+    fsinfo_dirty: bool,
+}
+
+pub struct FatFs {
+    cache: BlockCache,
+    bpb: Bpb,
+    state: Mutex<State>,
+}
+
+impl FatFs {
+    /// Mount the FAT volume on `dev`
+    pub fn mount(dev: Arc<dyn BlockDevice>) -> FatResult<Self> {
+        let mut sector = [0u8; BLOCK_SIZE];
+        dev.read_blocks(0, &mut sector)?;
+        let bpb = Bpb::parse(&sector)?;
+
+        if bpb.total_sectors as u64 > dev.block_count() {
+            warn!("{}: FAT volume is larger than the device.", dev.name());
+            return Err(FatError::InvalidFs);
+        }
+
+        let fs = Self {
+            cache: BlockCache::new(dev, CACHE_BLOCKS),
+            state: Mutex::new(State {
+                next_free: 2,
+                fsinfo_dirty: false,
+            }),
+            bpb,
+        };
+
+        if let Some(next_free) = fs.read_fsinfo()? {
+            fs.state.lock().next_free = next_free;
+        }
+
+        Ok(fs)
+    }
+
+    /// Free cluster hint of the FSInfo sector
+    fn read_fsinfo(&self) -> FatResult<Option<u32>> {
+        let sector = match self.bpb.fsinfo_sector {
+            Some(sector) => sector as u64,
+            None => return Ok(None),
+        };
+
+        let mut buf = [0u8; BLOCK_SIZE];
+        self.read_at(sector * BLOCK_SIZE as u64, &mut buf)?;
+        if u32_at(&buf, 0) != FSINFO_LEAD_SIG || u32_at(&buf, 484) != FSINFO_STRUCT_SIG {
+            return Ok(None);
+        }
+
+        let next_free = u32_at(&buf, 492);
+        Ok(Some(next_free).filter(|&c| self.bpb.is_valid_cluster(c)))
+    }
+
+    #[inline]
+    pub fn bpb(&self) -> &Bpb {
+        &self.bpb
+    }
+
+    #[inline]
+    pub fn fat_type(&self) -> FatType {
+        self.bpb.fat_type
+    }
+
+    /// Volume label, empty if there is none
+    pub fn label(&self) -> String {
+        let label: String = self.bpb.label.iter().map(|&b| b as char).collect();
+        match label.trim_end() {
+            "NO NAME" => String::new(),
+            label => label.into(),
+        }
+    }
+
+    #[inline]
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        self.cache.device()
+    }
+
+    #[inline]
+    pub fn is_read_only(&self) -> bool {
+        self.cache.is_read_only()
+    }
+
+    /// Read `buf.len()` bytes at byte `offset` of the volume
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FatResult {
+        let mut pos = 0;
+        while pos < buf.len() {
+            let at = offset + pos as u64;
+            let lba = at / BLOCK_SIZE as u64;
+            let skip = (at % BLOCK_SIZE as u64) as usize;
+            let rest = buf.len() - pos;
+
+            if skip == 0 && rest >= BLOCK_SIZE {
+                let len = rest / BLOCK_SIZE * BLOCK_SIZE;
+                self.cache.read_blocks(lba, &mut buf[pos..pos + len])?;
+                pos += len;
+            } else {
+                let mut block = [0u8; BLOCK_SIZE];
+                self.cache.read_blocks(lba, &mut block)?;
+                let len = rest.min(BLOCK_SIZE - skip);
+                buf[pos..pos + len].copy_from_slice(&block[skip..skip + len]);
+                pos += len;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write `buf` at byte `offset` of the volume
+    fn write_at(&self, offset: u64, buf: &[u8]) -> FatResult {
+        let mut pos = 0;
+        while pos < buf.len() {
+            let at = offset + pos as u64;
+            let lba = at / BLOCK_SIZE as u64;
+            let skip = (at % BLOCK_SIZE as u64) as usize;
+            let rest = buf.len() - pos;
+
+            if skip == 0 && rest >= BLOCK_SIZE {
+                let len = rest / BLOCK_SIZE * BLOCK_SIZE;
+                self.cache.write_blocks(lba, &buf[pos..pos + len])?;
+                pos += len;
+            } else {
+                let mut block = [0u8; BLOCK_SIZE];
+                self.cache.read_blocks(lba, &mut block)?;
+                let len = rest.min(BLOCK_SIZE - skip);
+                block[skip..skip + len].copy_from_slice(&buf[pos..pos + len]);
+                self.cache.write_blocks(lba, &block)?;
+                pos += len;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lock the volume for a modification
+    fn modify(&self) -> FatResult<spin::MutexGuard<'_, State>> {
+        if self.is_read_only() {
+            return Err(FatError::ReadOnly);
+        }
+        Ok(self.state.lock())
+    }
+
+    /// Directory id of the root: its first cluster, or 0 for a fixed root
+    #[inline]
+    fn root_dir(&self) -> u32 {
+        self.bpb.root_cluster
+    }
+
+    /// Directory id of `entry`
+    fn dir_of(&self, entry: &FatEntry) -> FatResult<u32> {
+        if !entry.is_dir() {
+            return Err(FatError::NotADirectory);
+        }
+        // `..` of a subdirectory of the root points to cluster 0
+        Ok(if entry.cluster == 0 { self.root_dir() } else { entry.cluster })
+    }
+
+    pub fn root(&self) -> FatEntry {
+        FatEntry::root(self.root_dir())
+    }
+
+    /// Files of directory `dir`, without `.` and `..`
+    pub fn read_dir(&self, dir: &FatEntry) -> FatResult<Vec<FatEntry>> {
+        self.list(self.dir_of(dir)?)
+    }
+
+    /// Find `name` in directory `dir`, case insensitive
+    pub fn find(&self, dir: &FatEntry, name: &str) -> FatResult<FatEntry> {
+        self.read_dir(dir)?
+            .into_iter()
+            .find(|e| e.matches(name))
+            .ok_or(FatError::NotFound)
+    }
+
+    /// Find the file at `path`, relative to the root of the volume
+    pub fn lookup(&self, path: &str) -> FatResult<FatEntry> {
+        let mut stack = Vec::new();
+        let mut current = self.root();
+
+        for name in path.split('/') {
+            match name {
+                "" | "." => continue,
+                ".." => {
+                    if let Some(parent) = stack.pop() {
+                        current = parent;
+                    }
+                }
+                name => {
+                    let next = self.find(&current, name)?;
+                    stack.push(core::mem::replace(&mut current, next));
+                }
+            }
+        }
+
+        Ok(current)
+    }
+
+    /// Read up to `buf.len()` bytes at `offset` of `file`, returns the bytes read
+    pub fn read(&self, file: &FatEntry, offset: u64, buf: &mut [u8]) -> FatResult<usize> {
+        if file.is_dir() {
+            return Err(FatError::IsADirectory);
+        }
+        if offset >= file.size() {
+            return Ok(0);
+        }
+
+        let len = buf.len().min((file.size() - offset) as usize);
+        self.for_each_extent(file.cluster, offset, len, |at, pos, count| {
+            self.read_at(at, &mut buf[pos..pos + count])
+        })?;
+        Ok(len)
+    }
+
+    /// Make room for `size` bytes in `file`, zeroing the bytes past its end
+    fn grow(&self, state: &mut State, file: &mut FatEntry, size: u64) -> FatResult {
+        if size > u32::MAX as u64 {
+            return Err(FatError::NoSpace);
+        }
+
+        let clusters = size.div_ceil(self.bpb.cluster_size() as u64);
+        self.extend_chain(state, &mut file.cluster, clusters)?;
+
+        if size > file.size() {
+            // new clusters are zeroed, but the last old one may hold stale data
+            let old = file.size();
+            self.zero_range(file.cluster, old, (size - old) as usize)?;
+        }
+        Ok(())
+    }
+
+    /// Write `buf` at `offset` of `file`, extending it as needed
+    pub fn write(&self, file: &mut FatEntry, offset: u64, buf: &[u8]) -> FatResult<usize> {
+        if file.is_dir() {
+            return Err(FatError::IsADirectory);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut state = self.modify()?;
+        let end = offset + buf.len() as u64;
+        if end > file.size() {
+            // only the gap before `offset` needs zeroing
+            self.grow(&mut state, file, offset.max(file.size()))?;
+            self.extend_chain(&mut state, &mut file.cluster, end.div_ceil(self.bpb.cluster_size() as u64))?;
+        }
+
+        self.for_each_extent(file.cluster, offset, buf.len(), |at, pos, count| {
+            self.write_at(at, &buf[pos..pos + count])
+        })?;
+
+        if end > file.size() {
+            file.size = end as u32;
+        }
+        self.update_entry(file)?;
+        Ok(buf.len())
+    }
+
+    /// Set the size of `file`, zero-filling when it grows
+    pub fn truncate(&self, file: &mut FatEntry, size: u64) -> FatResult {
+        if file.is_dir() {
+            return Err(FatError::IsADirectory);
+        }
+
+        let mut state = self.modify()?;
+        if size < file.size() {
+            let clusters = size.div_ceil(self.bpb.cluster_size() as u64);
+            file.cluster = self.truncate_chain(&mut state, file.cluster, clusters)?;
+        } else {
+            self.grow(&mut state, file, size)?;
+        }
+
+        file.size = size as u32;
+        self.update_entry(file)
+    }
+
+    /// Create an empty file `name` in directory `dir`
+    pub fn create(&self, dir: &FatEntry, name: &str) -> FatResult<FatEntry> {
+        let dir = self.dir_of(dir)?;
+        let mut state = self.modify()?;
+        self.insert_entry(&mut state, dir, name, Attributes::ARCHIVE, 0)
+    }
+
+    /// Create a directory `name` in directory `dir`
+    pub fn create_dir(&self, parent: &FatEntry, name: &str) -> FatResult<FatEntry> {
+        let dir = self.dir_of(parent)?;
+        let mut state = self.modify()?;
+
+        let cluster = self.alloc_cluster(&mut state, None)?;
+        let parent_cluster = if parent.is_root() { 0 } else { parent.cluster };
+
+        let result = self
+            .init_dir(cluster, parent_cluster)
+            .and_then(|_| self.insert_entry(&mut state, dir, name, Attributes::DIRECTORY, cluster));
+        if result.is_err() {
+            self.free_chain(&mut state, cluster)?;
+        }
+        result
+    }
+
+    /// Delete `name` from directory `dir`, directories must be empty
+    pub fn remove(&self, dir: &FatEntry, name: &str) -> FatResult {
+        let entry = self.find(dir, name)?;
+        let mut state = self.modify()?;
+
+        if entry.is_dir() && !self.list(self.dir_of(&entry)?)?.is_empty() {
+            return Err(FatError::DirectoryNotEmpty);
+        }
+
+        self.remove_entry(&entry)?;
+        if entry.cluster != 0 {
+            self.free_chain(&mut state, entry.cluster)?;
+        }
+        Ok(())
+    }
+
+    /// Write all changes to the device
+    pub fn sync(&self) -> FatResult {
+        let mut state = self.state.lock();
+
+        if let (true, Some(sector)) = (state.fsinfo_dirty, self.bpb.fsinfo_sector) {
+            // the free count is not tracked, let it be recomputed
+            let offset = sector as u64 * BLOCK_SIZE as u64;
+            let mut buf = [0u8; BLOCK_SIZE];
+            self.read_at(offset, &mut buf)?;
+            if u32_at(&buf, 0) == FSINFO_LEAD_SIG && u32_at(&buf, 484) == FSINFO_STRUCT_SIG {
+                buf[488..492].copy_from_slice(&FSINFO_UNKNOWN.to_le_bytes());
+                buf[492..496].copy_from_slice(&state.next_free.to_le_bytes());
+                self.write_at(offset, &buf)?;
+            }
+        }
+        state.fsinfo_dirty = false;
+
+        self.cache.flush()?;
+        Ok(())
+    }
+}
+
+static VOLUMES: RwLock<Vec<Arc<FatFs>>> = RwLock::new(Vec::new());
+
+/// Mount the FAT volumes found on the block devices
+pub fn init() {
+    for dev in block::devices() {
+        let name = String::from(dev.name());
+        match FatFs::mount(dev) {
+            Ok(fs) => {
+                info!("{}: {} volume \"{}\", {} clusters of {} bytes",
+                    name, fs.fat_type(), fs.label(), fs.bpb.cluster_count, fs.bpb.cluster_size());
+                VOLUMES.write().push(Arc::new(fs));
+            }
+            Err(FatError::InvalidFs) => {}
+            Err(err) => warn!("{}: failed to mount FAT volume: {:?}", name, err),
+        }
+    }
+}
+
+/// Mounted FAT volumes
+pub fn volumes() -> Vec<Arc<FatFs>> {
+    VOLUMES.read().clone()
+}
+
+/// The mounted volume on the block device `name`
+pub fn volume(name: &str) -> Option<Arc<FatFs>> {
+    VOLUMES.read().iter().find(|fs| fs.device().name() == name).cloned()
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::drivers::block::RamDisk;
+    use alloc::vec;
+
+    /// Geometry of a test volume, FAT32 if it has no fixed root directory
+    #[derive(Clone, Copy)]
+    pub(super) struct Layout {
+        pub total: u32,
+        pub cluster: u8,
+        pub reserved: u16,
+        pub fat_sectors: u32,
+        pub root_entries: u16,
+    }
+
+    pub(super) const FAT12: Layout = Layout {
+        total: 2048,
+        cluster: 1,
+        reserved: 1,
+        fat_sectors: 6,
+        root_entries: 224,
+    };
+
+    pub(super) const FAT16: Layout = Layout {
+        total: 8192,
+        cluster: 1,
+        reserved: 1,
+        fat_sectors: 32,
+        root_entries: 512,
+    };
+
+    /// The smallest FAT32 volume with 512 bytes clusters
+    pub(super) const FAT32: Layout = Layout {
+        total: 66600,
+        cluster: 1,
+        reserved: 32,
+        fat_sectors: 521,
+        root_entries: 0,
+    };
+
+    /// Boot sector of a volume with two FATs, FAT32 ones have the root
+    /// directory in cluster 2 and the FSInfo sector in sector 1
+    pub(super) fn boot_sector(layout: Layout) -> [u8; BLOCK_SIZE] {
+        let fat32 = layout.root_entries == 0;
+        let mut sector = [0u8; BLOCK_SIZE];
+        sector[0] = 0xEB;
+        sector[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+        sector[13] = layout.cluster;
+        sector[14..16].copy_from_slice(&layout.reserved.to_le_bytes());
+        sector[16] = 2;
+        sector[17..19].copy_from_slice(&layout.root_entries.to_le_bytes());
+        match u16::try_from(layout.total) {
+            Ok(total) => sector[19..21].copy_from_slice(&total.to_le_bytes()),
+            Err(_) => sector[32..36].copy_from_slice(&layout.total.to_le_bytes()),
+        }
+        if fat32 {
+            sector[36..40].copy_from_slice(&layout.fat_sectors.to_le_bytes());
+            sector[44..48].copy_from_slice(&2u32.to_le_bytes());
+            sector[48..50].copy_from_slice(&1u16.to_le_bytes());
+            sector[71..82].copy_from_slice(b"NO NAME    ");
+        } else {
+            sector[22..24].copy_from_slice(&(layout.fat_sectors as u16).to_le_bytes());
+            sector[43..54].copy_from_slice(b"NO NAME    ");
+        }
+        sector[510..512].copy_from_slice(&[0x55, 0xAA]);
+        sector
+    }
+
+    /// A RAM disk holding an empty volume
+    pub(super) fn format(layout: Layout) -> Arc<dyn BlockDevice> {
+        let disk = RamDisk::new("fat", layout.total as usize);
+        disk.write_blocks(0, &boot_sector(layout)).unwrap();
+
+        if layout.root_entries == 0 {
+            // the root directory takes cluster 2
+            let mut fat = [0u8; BLOCK_SIZE];
+            fat[8..12].copy_from_slice(&0x0FFF_FFFFu32.to_le_bytes());
+            for copy in 0..2 {
+                let sector = layout.reserved as u32 + copy * layout.fat_sectors;
+                disk.write_blocks(sector as u64, &fat).unwrap();
+            }
+        }
+        Arc::new(disk)
+    }
+
+    /// Clusters of the chain starting at `first`
+    pub(super) fn chain(fs: &FatFs, first: u32) -> Vec<u32> {
+        let mut clusters = Vec::new();
+        let mut cluster = Some(first).filter(|&c| c != 0);
+        while let Some(current) = cluster {
+            clusters.push(current);
+            cluster = fs.next_cluster(current).unwrap();
+        }
+        clusters
+    }
+
+    #[test]
+    fn file_lifecycle() {
+        for layout in [FAT12, FAT16, FAT32] {
+            let dev = format(layout);
+            let fs = FatFs::mount(dev.clone()).unwrap();
+            let root = fs.root();
+
+            let mut file = fs.create(&root, "hello.txt").unwrap();
+            assert_eq!(fs.create(&root, "HELLO.TXT").unwrap_err(), FatError::AlreadyExists);
+            assert_eq!(file.cluster(), 0);
+
+            let data: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+            assert_eq!(fs.write(&mut file, 0, &data), Ok(3000));
+            assert_eq!(chain(&fs, file.cluster()).len(), 6);
+
+            // a write past the end leaves zeros in the gap
+            fs.write(&mut file, 4000, b"tail").unwrap();
+            assert_eq!(file.size(), 4004);
+            assert_eq!(chain(&fs, file.cluster()).len(), 8);
+            let mut buf = vec![0xffu8; 5000];
+            assert_eq!(fs.read(&file, 0, &mut buf), Ok(4004));
+            assert_eq!(buf[..3000], data[..]);
+            assert!(buf[3000..4000].iter().all(|&b| b == 0));
+            assert_eq!(&buf[4000..4004], b"tail");
+
+            // shrinking frees the clusters past the new end
+            let before = chain(&fs, file.cluster());
+            fs.truncate(&mut file, 600).unwrap();
+            assert_eq!(chain(&fs, file.cluster()), before[..2]);
+            assert!(before[2..].iter().all(|&c| fs.fat_entry(c) == Ok(0)));
+
+            // growing again zero-fills, even where the old data was
+            fs.truncate(&mut file, 1500).unwrap();
+            buf.fill(0xff);
+            assert_eq!(fs.read(&file, 0, &mut buf), Ok(1500));
+            assert_eq!(buf[..600], data[..600]);
+            assert!(buf[600..1500].iter().all(|&b| b == 0));
+
+            // everything reaches the device on sync
+            fs.sync().unwrap();
+            let fs = FatFs::mount(dev).unwrap();
+            let file = fs.lookup("/HELLO.TXT").unwrap();
+            assert_eq!(file.size(), 1500);
+
+            let clusters = chain(&fs, file.cluster());
+            assert_eq!(clusters.len(), 3);
+            fs.remove(&fs.root(), "hello.txt").unwrap();
+            assert_eq!(fs.find(&fs.root(), "hello.txt").unwrap_err(), FatError::NotFound);
+            assert!(clusters.iter().all(|&c| fs.fat_entry(c) == Ok(0)));
+        }
+    }
+
+    #[test]
+    fn directories() {
+        let fs = FatFs::mount(format(FAT16)).unwrap();
+        let dir = fs.create_dir(&fs.root(), "docs").unwrap();
+        assert_eq!(chain(&fs, dir.cluster()).len(), 1);
+
+        let mut file = fs.create(&dir, "A rather long file name.md").unwrap();
+        fs.write(&mut file, 0, b"hi").unwrap();
+        let found = fs.lookup("docs/../DOCS/a rather long FILE name.md").unwrap();
+        assert_eq!(found.size(), 2);
+        assert_eq!(found.short_name(), "ARATHE~1.MD");
+
+        // `.`, `..` and 16 files take more than the first cluster of 16 entries
+        for i in 0..16 {
+            fs.create(&dir, &format!("f{}", i)).unwrap();
+        }
+        assert_eq!(chain(&fs, dir.cluster()).len(), 2);
+        assert_eq!(fs.read_dir(&dir).unwrap().len(), 17);
+
+        assert_eq!(fs.remove(&fs.root(), "docs").unwrap_err(), FatError::DirectoryNotEmpty);
+        for entry in fs.read_dir(&dir).unwrap() {
+            fs.remove(&dir, entry.name()).unwrap();
+        }
+        let clusters = chain(&fs, dir.cluster());
+        fs.remove(&fs.root(), "docs").unwrap();
+        assert!(fs.read_dir(&fs.root()).unwrap().is_empty());
+        assert!(clusters.iter().all(|&c| fs.fat_entry(c) == Ok(0)));
+    }
+
+    #[test]
+    fn chains() {
+        for layout in [FAT12, FAT16, FAT32] {
+            let fs = FatFs::mount(format(layout)).unwrap();
+            let mut state = fs.state.lock();
+
+            // FAT12 entries of odd and even clusters share a byte
+            let mut first = 0;
+            fs.extend_chain(&mut state, &mut first, 5).unwrap();
+            let clusters = chain(&fs, first);
+            assert_eq!(clusters.len(), 5);
+            fs.extend_chain(&mut state, &mut first, 3).unwrap();
+            assert_eq!(chain(&fs, first), clusters);
+
+            let mut other = 0;
+            fs.extend_chain(&mut state, &mut other, 2).unwrap();
+            assert!(chain(&fs, other).iter().all(|c| !clusters.contains(c)));
+            assert_eq!(chain(&fs, first), clusters);
+
+            assert_eq!(fs.truncate_chain(&mut state, first, 2), Ok(first));
+            assert_eq!(chain(&fs, first), clusters[..2]);
+            assert!(clusters[2..].iter().all(|&c| fs.fat_entry(c) == Ok(0)));
+
+            // freed clusters are reused first
+            fs.extend_chain(&mut state, &mut first, 3).unwrap();
+            assert_eq!(chain(&fs, first)[2], clusters[2]);
+
+            assert_eq!(fs.truncate_chain(&mut state, first, 0), Ok(0));
+            assert!(clusters[..3].iter().all(|&c| fs.fat_entry(c) == Ok(0)));
+            assert_eq!(chain(&fs, other).len(), 2);
+        }
+    }
+}
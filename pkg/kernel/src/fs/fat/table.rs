@@ -0,0 +1,248 @@
+//! File allocation table and cluster chains
+
+use super::*;
+
+/// Sectors of zeros, used to clear new clusters
+static ZEROS: [u8; BLOCK_SIZE] = [0; BLOCK_SIZE];
+
+impl FatFs {
+    /// Byte offset of the entry of `cluster` in the first FAT, and its width
+    fn fat_location(&self, cluster: u32) -> (u64, usize) {
+        let n = cluster as u64;
+        let (offset, width) = match self.bpb.fat_type {
+            FatType::Fat12 => (n + n / 2, 2),
+            FatType::Fat16 => (n * 2, 2),
+            FatType::Fat32 => (n * 4, 4),
+        };
+        (self.bpb.reserved_sectors as u64 * BLOCK_SIZE as u64 + offset, width)
+    }
+
+    pub(super) fn fat_entry(&self, cluster: u32) -> FatResult<u32> {
+        let (offset, width) = self.fat_location(cluster);
+        let mut buf = [0u8; 4];
+        self.read_at(offset, &mut buf[..width])?;
+        let raw = u32::from_le_bytes(buf);
+
+        Ok(match self.bpb.fat_type {
+            // 12 bit entries share a byte, odd ones take the high nibble
+            FatType::Fat12 if cluster & 1 == 1 => raw >> 4,
+            FatType::Fat12 => raw & 0xFFF,
+            FatType::Fat16 => raw,
+            FatType::Fat32 => raw & 0x0FFF_FFFF,
+        })
+    }
+
+    /// Set the entry of `cluster` in every copy of the FAT
+    fn set_fat_entry(&self, cluster: u32, value: u32) -> FatResult {
+        let (offset, width) = self.fat_location(cluster);
+        let mut buf = [0u8; 4];
+        self.read_at(offset, &mut buf[..width])?;
+        let raw = u32::from_le_bytes(buf);
+
+        let raw = match self.bpb.fat_type {
+            FatType::Fat12 if cluster & 1 == 1 => (raw & 0x000F) | (value << 4),
+            FatType::Fat12 => (raw & 0xF000) | (value & 0xFFF),
+            FatType::Fat16 => value & 0xFFFF,
+            // the high 4 bits are reserved
+            FatType::Fat32 => (raw & 0xF000_0000) | (value & 0x0FFF_FFFF),
+        };
+
+        let fat_size = self.bpb.fat_sectors as u64 * BLOCK_SIZE as u64;
+        for copy in 0..self.bpb.fat_count as u64 {
+            self.write_at(offset + copy * fat_size, &raw.to_le_bytes()[..width])?;
+        }
+        Ok(())
+    }
+
+    /// End of chain marker
+    #[inline]
+    fn eoc(&self) -> u32 {
+        match self.bpb.fat_type {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        }
+    }
+
+    /// Cluster after `cluster` in its chain, `None` at the end
+    pub(super) fn next_cluster(&self, cluster: u32) -> FatResult<Option<u32>> {
+        let next = self.fat_entry(cluster)?;
+        if next >= self.eoc() - 7 {
+            Ok(None)
+        } else if self.bpb.is_valid_cluster(next) {
+            Ok(Some(next))
+        } else {
+            // free or bad cluster in a chain
+            warn!("FAT: broken chain at cluster {}: {:#x}.", cluster, next);
+            Err(FatError::InvalidFs)
+        }
+    }
+
+    /// The `n`th cluster of the chain starting at `first`, if the chain is long enough
+    pub(super) fn cluster_at(&self, first: u32, n: u64) -> FatResult<Option<u32>> {
+        if !self.bpb.is_valid_cluster(first) {
+            return Err(FatError::InvalidFs);
+        }
+
+        let mut cluster = first;
+        for _ in 0..n {
+            cluster = match self.next_cluster(cluster)? {
+                Some(next) => next,
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(cluster))
+    }
+
+    /// Number of clusters in the chain starting at `first`
+    pub(super) fn chain_length(&self, first: u32) -> FatResult<u64> {
+        if first == 0 {
+            return Ok(0);
+        }
+
+        let mut count = 1;
+        let mut cluster = first;
+        while let Some(next) = self.next_cluster(cluster)? {
+            count += 1;
+            if count > self.bpb.cluster_count as u64 {
+                warn!("FAT: loop in the chain of cluster {}.", first);
+                return Err(FatError::InvalidFs);
+            }
+            cluster = next;
+        }
+        Ok(count)
+    }
+
+    /// Allocate a zeroed cluster and append it to the chain ending at `prev`
+    pub(super) fn alloc_cluster(&self, state: &mut State, prev: Option<u32>) -> FatResult<u32> {
+        let count = self.bpb.cluster_count;
+        let hint = state.next_free.saturating_sub(2) % count;
+
+        let cluster = (0..count)
+            .map(|i| 2 + (hint + i) % count)
+            .find(|&c| matches!(self.fat_entry(c), Ok(0)))
+            .ok_or(FatError::NoSpace)?;
+
+        self.set_fat_entry(cluster, self.eoc())?;
+        if let Some(prev) = prev {
+            self.set_fat_entry(prev, cluster)?;
+        }
+
+        let offset = self.bpb.cluster_offset(cluster);
+        for sector in 0..self.bpb.sectors_per_cluster as u64 {
+            self.write_at(offset + sector * BLOCK_SIZE as u64, &ZEROS)?;
+        }
+
+        state.next_free = cluster + 1;
+        state.fsinfo_dirty = true;
+        Ok(cluster)
+    }
+
+    /// Make the chain at `first` at least `count` clusters long,
+    /// `first` is set if the chain was empty.
+    pub(super) fn extend_chain(&self, state: &mut State, first: &mut u32, count: u64) -> FatResult {
+        if count == 0 {
+            return Ok(());
+        }
+
+        if *first == 0 {
+            *first = self.alloc_cluster(state, None)?;
+        }
+
+        let mut cluster = *first;
+        for _ in 1..count {
+            cluster = match self.next_cluster(cluster)? {
+                Some(next) => next,
+                None => self.alloc_cluster(state, Some(cluster))?,
+            };
+        }
+        Ok(())
+    }
+
+    /// Free every cluster of the chain starting at `first`
+    pub(super) fn free_chain(&self, state: &mut State, first: u32) -> FatResult {
+        let mut cluster = Some(first);
+        let mut freed = 0;
+
+        while let Some(current) = cluster {
+            if !self.bpb.is_valid_cluster(current) || freed > self.bpb.cluster_count {
+                return Err(FatError::InvalidFs);
+            }
+            cluster = self.next_cluster(current)?;
+            self.set_fat_entry(current, 0)?;
+            freed += 1;
+        }
+
+        state.next_free = state.next_free.min(first);
+        state.fsinfo_dirty = true;
+        Ok(())
+    }
+
+    /// Keep the first `count` clusters of the chain at `first`,
+    /// returns the new first cluster, which is 0 if nothing is kept.
+    pub(super) fn truncate_chain(&self, state: &mut State, first: u32, count: u64) -> FatResult<u32> {
+        if first == 0 {
+            return Ok(0);
+        }
+        if count == 0 {
+            self.free_chain(state, first)?;
+            return Ok(0);
+        }
+
+        let last = match self.cluster_at(first, count - 1)? {
+            Some(last) => last,
+            None => return Ok(first),
+        };
+        if let Some(rest) = self.next_cluster(last)? {
+            self.set_fat_entry(last, self.eoc())?;
+            self.free_chain(state, rest)?;
+        }
+        Ok(first)
+    }
+
+    /// Call `f` with the volume offset, buffer position and length of each
+    /// piece of `len` bytes at `offset` in the chain starting at `first`.
+    pub(super) fn for_each_extent(
+        &self,
+        first: u32,
+        offset: u64,
+        len: usize,
+        mut f: impl FnMut(u64, usize, usize) -> FatResult,
+    ) -> FatResult {
+        if len == 0 {
+            return Ok(());
+        }
+
+        let size = self.bpb.cluster_size() as u64;
+        let mut cluster = self
+            .cluster_at(first, offset / size)?
+            .ok_or(FatError::InvalidFs)?;
+        let mut in_cluster = offset % size;
+        let mut done = 0;
+
+        loop {
+            let count = (len - done).min((size - in_cluster) as usize);
+            f(self.bpb.cluster_offset(cluster) + in_cluster, done, count)?;
+            done += count;
+            in_cluster = 0;
+
+            if done == len {
+                return Ok(());
+            }
+            cluster = self.next_cluster(cluster)?.ok_or(FatError::InvalidFs)?;
+        }
+    }
+
+    /// Write zeros to `len` bytes at `offset` in the chain starting at `first`
+    pub(super) fn zero_range(&self, first: u32, offset: u64, len: usize) -> FatResult {
+        self.for_each_extent(first, offset, len, |at, _, count| {
+            let mut written = 0;
+            while written < count {
+                let n = (count - written).min(BLOCK_SIZE);
+                self.write_at(at + written as u64, &ZEROS[..n])?;
+                written += n;
+            }
+            Ok(())
+        })
+    }
+}
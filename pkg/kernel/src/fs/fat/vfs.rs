@@ -0,0 +1,154 @@
+//! FAT volumes in the VFS
+//!
+//! An inode is shared by every path walk reaching the same directory
+//! entry, so the size and first cluster of an open file stay coherent.
+
+use super::*;
+use crate::fs::{DirEntry, FileSystem, FileType, FsError, FsResult, Inode, Metadata};
+use alloc::collections::BTreeMap;
+use alloc::sync::Weak;
+
+struct Volume {
+    fs: Arc<FatFs>,
+    /// Live inodes by inode number
+    inodes: Mutex<BTreeMap<u64, Weak<FatInode>>>,
+}
+
+impl Volume {
+    fn inode(self: &Arc<Self>, entry: FatEntry) -> Arc<FatInode> {
+        let mut inodes = self.inodes.lock();
+        let id = entry.id();
+
+        if let Some(inode) = inodes.get(&id).and_then(Weak::upgrade) {
+            return inode;
+        }
+
+        inodes.retain(|_, inode| inode.strong_count() > 0);
+        let inode = Arc::new(FatInode {
+            volume: self.clone(),
+            entry: RwLock::new(entry),
+        });
+        inodes.insert(id, Arc::downgrade(&inode));
+        inode
+    }
+}
+
+pub struct FatFileSystem {
+    volume: Arc<Volume>,
+}
+
+impl FatFileSystem {
+    pub fn new(fs: Arc<FatFs>) -> Arc<Self> {
+        Arc::new(Self {
+            volume: Arc::new(Volume {
+                fs,
+                inodes: Mutex::new(BTreeMap::new()),
+            }),
+        })
+    }
+}
+
+impl FileSystem for FatFileSystem {
+    fn name(&self) -> &str {
+        "fat"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.volume.inode(self.volume.fs.root())
+    }
+
+    fn sync(&self) -> FsResult {
+        Ok(self.volume.fs.sync()?)
+    }
+}
+
+struct FatInode {
+    volume: Arc<Volume>,
+    entry: RwLock<FatEntry>,
+}
+
+impl FatInode {
+    #[inline]
+    fn fs(&self) -> &FatFs {
+        &self.volume.fs
+    }
+}
+
+#[inline]
+fn file_type(entry: &FatEntry) -> FileType {
+    if entry.is_dir() {
+        FileType::Directory
+    } else {
+        FileType::File
+    }
+}
+
+impl Inode for FatInode {
+    fn metadata(&self) -> FsResult<Metadata> {
+        let entry = self.entry.read();
+        Ok(Metadata {
+            ty: file_type(&entry),
+            size: entry.size(),
+            inode: entry.id(),
+            links: 1,
+            read_only: self.fs().is_read_only() || entry.attributes().contains(Attributes::READ_ONLY),
+        })
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        Ok(self.fs().read(&self.entry.read(), offset, buf)?)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        Ok(self.fs().write(&mut self.entry.write(), offset, buf)?)
+    }
+
+    fn truncate(&self, size: u64) -> FsResult {
+        Ok(self.fs().truncate(&mut self.entry.write(), size)?)
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        let entry = self.fs().find(&self.entry.read(), name)?;
+        Ok(self.volume.inode(entry))
+    }
+
+    fn read_dir(&self) -> FsResult<Vec<DirEntry>> {
+        let entries = self.fs().read_dir(&self.entry.read())?;
+        Ok(entries
+            .iter()
+            .map(|e| DirEntry {
+                name: e.name().into(),
+                ty: file_type(e),
+                inode: e.id(),
+            })
+            .collect())
+    }
+
+    fn create(&self, name: &str, ty: FileType) -> FsResult<Arc<dyn Inode>> {
+        let dir = self.entry.read();
+        let entry = match ty {
+            FileType::File => self.fs().create(&dir, name)?,
+            FileType::Directory => self.fs().create_dir(&dir, name)?,
+        };
+        Ok(self.volume.inode(entry))
+    }
+
+    fn unlink(&self, name: &str) -> FsResult {
+        let dir = self.entry.read();
+        let entry = self.fs().find(&dir, name)?;
+
+        // the clusters of an open file would be reused under it
+        let id = entry.id();
+        if self.volume.inodes.lock().get(&id).is_some_and(|i| i.strong_count() > 0) {
+            return Err(FsError::Busy);
+        }
+
+        self.fs().remove(&dir, name)?;
+        self.volume.inodes.lock().remove(&id);
+        Ok(())
+    }
+
+    fn sync(&self) -> FsResult {
+        Ok(self.fs().sync()?)
+    }
+}
@@ -0,0 +1,136 @@
+//! Open files
+
+use super::*;
+use spin::Mutex;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct OpenFlags: u32 {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        /// Create the file if it does not exist
+        const CREATE = 1 << 2;
+        /// With `CREATE`, fail if the file exists
+        const EXCL = 1 << 3;
+        /// Empty the file when opening it for writing
+        const TRUNCATE = 1 << 4;
+        /// Every write goes to the end of the file
+        const APPEND = 1 << 5;
+        /// Fail if the file is not a directory
+        const DIRECTORY = 1 << 6;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// An opened file, with its own offset
+pub struct File {
+    dentry: Arc<Dentry>,
+    flags: OpenFlags,
+    offset: Mutex<u64>,
+}
+
+impl File {
+    pub(super) fn new(dentry: Arc<Dentry>, flags: OpenFlags) -> Self {
+        Self {
+            dentry,
+            flags,
+            offset: Mutex::new(0),
+        }
+    }
+
+    #[inline]
+    pub fn dentry(&self) -> &Arc<Dentry> {
+        &self.dentry
+    }
+
+    #[inline]
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        self.dentry.inode()
+    }
+
+    #[inline]
+    pub fn flags(&self) -> OpenFlags {
+        self.flags
+    }
+
+    #[inline]
+    pub fn path(&self) -> String {
+        self.dentry.path()
+    }
+
+    /// Read from the current offset, returns the bytes read, 0 at the end
+    pub fn read(&self, buf: &mut [u8]) -> FsResult<usize> {
+        if !self.flags.contains(OpenFlags::READ) {
+            return Err(FsError::PermissionDenied);
+        }
+
+        let mut offset = self.offset.lock();
+        let len = self.inode().read_at(*offset, buf)?;
+        *offset += len as u64;
+        Ok(len)
+    }
+
+    /// Write at the current offset, or at the end with `APPEND`
+    pub fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        if !self.flags.contains(OpenFlags::WRITE) {
+            return Err(FsError::PermissionDenied);
+        }
+
+        let mut offset = self.offset.lock();
+        if self.flags.contains(OpenFlags::APPEND) {
+            *offset = self.inode().metadata()?.size;
+        }
+        let len = self.inode().write_at(*offset, buf)?;
+        *offset += len as u64;
+        Ok(len)
+    }
+
+    /// Move the offset, returns the new offset
+    pub fn seek(&self, pos: SeekFrom) -> FsResult<u64> {
+        let mut offset = self.offset.lock();
+        let new = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(delta) => offset.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.inode().metadata()?.size.checked_add_signed(delta),
+        };
+
+        *offset = new.ok_or(FsError::InvalidArgument)?;
+        Ok(*offset)
+    }
+
+    pub fn stat(&self) -> FsResult<Metadata> {
+        self.inode().metadata()
+    }
+
+    pub fn read_dir(&self) -> FsResult<Vec<DirEntry>> {
+        self.dentry.read_dir()
+    }
+
+    pub fn truncate(&self, size: u64) -> FsResult {
+        if !self.flags.contains(OpenFlags::WRITE) {
+            return Err(FsError::PermissionDenied);
+        }
+        self.inode().truncate(size)
+    }
+
+    /// Write the changes to the file to its storage
+    pub fn sync(&self) -> FsResult {
+        self.inode().sync()
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        if self.flags.contains(OpenFlags::WRITE) {
+            if let Err(err) = self.sync() {
+                warn!("Failed to sync {}: {:?}", self.path(), err);
+            }
+        }
+    }
+}
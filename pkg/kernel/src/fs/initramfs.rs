@@ -0,0 +1,349 @@
+//! Initramfs
+//!
+//! The bootloader loads the archive named by `initramfs` in `boot.conf`,
+//! it is unpacked into a [`RamFs`] mounted on `/`, so its files are there
+//! before any storage driver is up. Both cpio (newc) and tar (ustar)
+//! archives are accepted, only regular files and directories are kept.
+
+use super::*;
+use crate::memory::physical_to_virtual;
+use boot::BootInfo;
+
+const CPIO_MAGIC: &[u8] = b"070701";
+const CPIO_MAGIC_CRC: &[u8] = b"070702";
+const CPIO_HEADER_SIZE: usize = 110;
+const CPIO_TRAILER: &str = "TRAILER!!!";
+
+const TAR_BLOCK_SIZE: usize = 512;
+const TAR_MAGIC: &[u8] = b"ustar";
+
+/// A file or directory of an archive
+struct Entry<'a> {
+    /// Path relative to the root
+    path: String,
+    ty: FileType,
+    data: &'a [u8],
+}
+
+/// Parse a cpio archive in newc format
+fn parse_cpio(archive: &[u8]) -> FsResult<Vec<Entry<'_>>> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    loop {
+        let header = archive
+            .get(offset..offset + CPIO_HEADER_SIZE)
+            .ok_or(FsError::Io)?;
+        if &header[..6] != CPIO_MAGIC && &header[..6] != CPIO_MAGIC_CRC {
+            return Err(FsError::Io);
+        }
+
+        // fields are 8 hex digits, after the magic
+        let field = |idx: usize| -> FsResult<usize> {
+            let digits = core::str::from_utf8(&header[6 + idx * 8..14 + idx * 8])
+                .map_err(|_| FsError::Io)?;
+            usize::from_str_radix(digits, 16).map_err(|_| FsError::Io)
+        };
+        let mode = field(1)?;
+        let file_size = field(6)?;
+        let name_size = field(11)?;
+
+        // the name ends with NUL, then the header and name are padded to 4 bytes
+        let name_start = offset + CPIO_HEADER_SIZE;
+        let name = archive
+            .get(name_start..name_start + name_size.saturating_sub(1))
+            .and_then(|name| core::str::from_utf8(name).ok())
+            .ok_or(FsError::Io)?;
+        let data_start = (name_start + name_size + 3) & !3;
+        let data = archive
+            .get(data_start..data_start + file_size)
+            .ok_or(FsError::Io)?;
+        offset = (data_start + file_size + 3) & !3;
+
+        if name == CPIO_TRAILER {
+            break;
+        }
+
+        match mode & 0o170000 {
+            0o040000 => entries.push(Entry { path: name.into(), ty: FileType::Directory, data }),
+            0o100000 => entries.push(Entry { path: name.into(), ty: FileType::File, data }),
+            _ => warn!("Initramfs: skipped {}, not a file or directory", name),
+        }
+    }
+
+    Ok(entries)
+}
+
+/// A NUL terminated string field of a tar header
+fn tar_str(field: &[u8]) -> FsResult<&str> {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).map_err(|_| FsError::Io)
+}
+
+/// Parse a tar archive in ustar format
+fn parse_tar(archive: &[u8]) -> FsResult<Vec<Entry<'_>>> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    while offset < archive.len() {
+        let header = archive
+            .get(offset..offset + TAR_BLOCK_SIZE)
+            .ok_or(FsError::Io)?;
+        // the archive ends with zero blocks
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+
+        let size = tar_str(&header[124..136])?.trim_matches(' ');
+        let size = usize::from_str_radix(size, 8).map_err(|_| FsError::Io)?;
+        let data_start = offset + TAR_BLOCK_SIZE;
+        let data = archive
+            .get(data_start..data_start + size)
+            .ok_or(FsError::Io)?;
+        offset = data_start + size.div_ceil(TAR_BLOCK_SIZE) * TAR_BLOCK_SIZE;
+
+        // long names are split into a prefix and a name
+        let name = tar_str(&header[0..100])?;
+        let prefix = tar_str(&header[345..500])?;
+        let path = if prefix.is_empty() {
+            String::from(name)
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+
+        match header[156] {
+            b'5' => entries.push(Entry { path, ty: FileType::Directory, data }),
+            b'0' | 0 => entries.push(Entry { path, ty: FileType::File, data }),
+            _ => warn!("Initramfs: skipped {}, not a file or directory", path),
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Look up `name` in `dir`, creating it as `ty` if it does not exist
+fn find_or_create(dir: &Arc<dyn Inode>, name: &str, ty: FileType) -> FsResult<Arc<dyn Inode>> {
+    match dir.lookup(name) {
+        Ok(inode) if inode.metadata()?.ty == ty => Ok(inode),
+        Ok(_) => Err(FsError::AlreadyExists),
+        Err(FsError::NotFound) => dir.create(name, ty),
+        Err(err) => Err(err),
+    }
+}
+
+/// Unpack `archive` into the directory `root`, returns the number of entries
+pub fn unpack(archive: &[u8], root: &Arc<dyn Inode>) -> FsResult<usize> {
+    let entries = if archive.starts_with(CPIO_MAGIC) || archive.starts_with(CPIO_MAGIC_CRC) {
+        parse_cpio(archive)?
+    } else if archive.get(257..262) == Some(TAR_MAGIC) {
+        parse_tar(archive)?
+    } else {
+        return Err(FsError::NotSupported);
+    };
+
+    for entry in entries.iter() {
+        let mut names = entry
+            .path
+            .split('/')
+            .filter(|name| !name.is_empty() && *name != ".")
+            .peekable();
+
+        // the directories on the path may come later in the archive, or not at all
+        let mut dir = root.clone();
+        while let Some(name) = names.next() {
+            if name == ".." {
+                return Err(FsError::InvalidPath);
+            }
+            if names.peek().is_some() {
+                dir = find_or_create(&dir, name, FileType::Directory)?;
+                continue;
+            }
+
+            let inode = find_or_create(&dir, name, entry.ty)?;
+            if entry.ty == FileType::File {
+                inode.truncate(0)?;
+                inode.write_at(0, entry.data)?;
+            }
+        }
+    }
+
+    Ok(entries.len())
+}
+
+/// Unpack the initramfs loaded by the bootloader into a RAM filesystem on `/`
+pub fn init(boot_info: &'static BootInfo) {
+    let (addr, size) = match boot_info.initramfs {
+        Some(initramfs) => initramfs,
+        None => return,
+    };
+
+    // the pages stay reserved as loader data: the frame allocator only hands
+    // out conventional memory and can not take frames back yet, so there is
+    // nothing to free them to, and they are only read here
+    let archive =
+        unsafe { core::slice::from_raw_parts(physical_to_virtual(addr) as *const u8, size as usize) };
+
+    let fs = RamFs::new();
+    match unpack(archive, &fs.root()) {
+        Ok(count) => {
+            let (size, unit) = crate::humanized_size(size);
+            info!("Initramfs: unpacked {} entries from {:.3} {}", count, size, unit);
+        }
+        Err(err) => {
+            warn!("Failed to unpack initramfs: {:?}", err);
+            return;
+        }
+    }
+
+    if let Err(err) = mount("/", "initramfs", fs) {
+        warn!("Failed to mount initramfs on /: {:?}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIR: u32 = 0o040755;
+    const FILE: u32 = 0o100644;
+    const LINK: u32 = 0o120777;
+
+    /// A newc header, with the name and padding
+    fn cpio_entry(archive: &mut Vec<u8>, name: &str, mode: u32, data: &[u8]) {
+        let fields = [0, mode, 0, 0, 1, 0, data.len() as u32, 0, 0, 0, 0, name.len() as u32 + 1, 0];
+        archive.extend_from_slice(CPIO_MAGIC);
+        for field in fields {
+            archive.extend_from_slice(format!("{:08x}", field).as_bytes());
+        }
+        archive.extend_from_slice(name.as_bytes());
+        archive.push(0);
+        archive.resize(archive.len().next_multiple_of(4), 0);
+        archive.extend_from_slice(data);
+        archive.resize(archive.len().next_multiple_of(4), 0);
+    }
+
+    fn cpio(entries: &[(&str, u32, &[u8])]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        for &(name, mode, data) in entries {
+            cpio_entry(&mut archive, name, mode, data);
+        }
+        cpio_entry(&mut archive, CPIO_TRAILER, 0, &[]);
+        archive
+    }
+
+    /// A ustar header and the padded data, the checksum is not checked
+    fn tar_entry(archive: &mut Vec<u8>, prefix: &str, name: &str, ty: u8, data: &[u8]) {
+        let mut header = [0u8; TAR_BLOCK_SIZE];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+        header[156] = ty;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(data);
+        archive.resize(archive.len().next_multiple_of(TAR_BLOCK_SIZE), 0);
+    }
+
+    /// Content of the file at `path` in `root`
+    fn read(root: &Arc<dyn Inode>, path: &str) -> FsResult<Vec<u8>> {
+        let mut inode = root.clone();
+        for name in path.split('/') {
+            inode = inode.lookup(name)?;
+        }
+        let mut data = alloc::vec![0; inode.metadata()?.size as usize];
+        inode.read_at(0, &mut data)?;
+        Ok(data)
+    }
+
+    fn unpack_new(archive: &[u8]) -> FsResult<(usize, Arc<dyn Inode>)> {
+        let root = RamFs::new().root();
+        unpack(archive, &root).map(|count| (count, root))
+    }
+
+    #[test]
+    fn cpio_padding() {
+        // names and data of every length modulo 4
+        let archive = cpio(&[
+            ("d", DIR, b""),
+            ("d/ab", FILE, b"1"),
+            ("d/abc", FILE, b"12"),
+            ("./d/abcd", FILE, b"123"),
+            ("d/abcde", FILE, b"1234"),
+            ("d/link", LINK, b"ab"),
+            ("empty", FILE, b""),
+        ]);
+        let (count, root) = unpack_new(&archive).unwrap();
+        assert_eq!(count, 6);
+        assert_eq!(read(&root, "d/ab").unwrap(), b"1");
+        assert_eq!(read(&root, "d/abc").unwrap(), b"12");
+        assert_eq!(read(&root, "d/abcd").unwrap(), b"123");
+        assert_eq!(read(&root, "d/abcde").unwrap(), b"1234");
+        assert_eq!(read(&root, "empty").unwrap(), b"");
+        assert_eq!(read(&root, "d/link").err(), Some(FsError::NotFound));
+        assert_eq!(root.lookup("d").unwrap().read_dir().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn cpio_trailer() {
+        // what follows the trailer is not part of the archive
+        let mut archive = cpio(&[("a/b/file", FILE, b"data")]);
+        archive.extend_from_slice(b"garbage");
+        let (count, root) = unpack_new(&archive).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(read(&root, "a/b/file").unwrap(), b"data");
+        assert_eq!(root.read_dir().unwrap().len(), 1);
+
+        let mut archive = Vec::new();
+        cpio_entry(&mut archive, "file", FILE, b"data");
+        assert_eq!(unpack_new(&archive).err(), Some(FsError::Io));
+    }
+
+    #[test]
+    fn tar_prefix() {
+        let mut archive = Vec::new();
+        let long = "n".repeat(100);
+        tar_entry(&mut archive, "", "dir/", b'5', b"");
+        tar_entry(&mut archive, "dir/sub", "file", b'0', b"hello");
+        tar_entry(&mut archive, "dir", &long, 0, &[7; 513]);
+        tar_entry(&mut archive, "", "dir/link", b'2', b"");
+        archive.resize(archive.len() + 2 * TAR_BLOCK_SIZE, 0);
+
+        let (count, root) = unpack_new(&archive).unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(read(&root, "dir/sub/file").unwrap(), b"hello");
+        assert_eq!(read(&root, &format!("dir/{}", long)).unwrap(), [7; 513]);
+        assert_eq!(root.lookup("dir").unwrap().read_dir().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn parent_components() {
+        for path in ["../escape", "a/../../escape", "a/.."] {
+            let archive = cpio(&[(path, FILE, b"x")]);
+            assert_eq!(unpack_new(&archive).err(), Some(FsError::InvalidPath));
+
+            let mut archive = Vec::new();
+            tar_entry(&mut archive, "", path, b'0', b"x");
+            assert_eq!(unpack_new(&archive).err(), Some(FsError::InvalidPath));
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let archive = cpio(&[("file", FILE, b"data")]);
+        // cut in the first header, in the data and in the trailer header
+        for len in [60, CPIO_HEADER_SIZE + 6, archive.len() - 20] {
+            assert_eq!(unpack_new(&archive[..len]).err(), Some(FsError::Io));
+        }
+        let mut bad = archive.clone();
+        bad[6 + 6 * 8] = b'g';
+        assert_eq!(unpack_new(&bad).err(), Some(FsError::Io));
+
+        let mut archive = Vec::new();
+        tar_entry(&mut archive, "", "file", b'0', &[1; 600]);
+        tar_entry(&mut archive, "", "next", b'0', b"");
+        for len in [TAR_BLOCK_SIZE + 100, 3 * TAR_BLOCK_SIZE + 300] {
+            assert_eq!(unpack_new(&archive[..len]).err(), Some(FsError::Io));
+        }
+        assert_eq!(unpack_new(&archive[..100]).err(), Some(FsError::NotSupported));
+    }
+}
@@ -0,0 +1,100 @@
+//! Interfaces of concrete filesystems
+
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+}
+
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub ty: FileType,
+    /// Size in bytes, 0 for directories
+    pub size: u64,
+    /// Inode number, unique within the filesystem
+    pub inode: u64,
+    /// Number of names of the file
+    pub links: u32,
+    pub read_only: bool,
+}
+
+impl Metadata {
+    #[inline]
+    pub fn is_dir(&self) -> bool {
+        self.ty == FileType::Directory
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub ty: FileType,
+    pub inode: u64,
+}
+
+/// A file or directory of a filesystem
+///
+/// Operations that don't apply to the type of the inode return
+/// `NotADirectory` or `IsADirectory`, unsupported ones `NotSupported`.
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> FsResult<Metadata>;
+
+    /// Read up to `buf.len()` bytes at `offset`, returns the bytes read
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> FsResult<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Write `buf` at `offset`, extending the file as needed
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> FsResult<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    fn truncate(&self, _size: u64) -> FsResult {
+        Err(FsError::NotSupported)
+    }
+
+    /// Find `name` in this directory, `.` and `..` are handled by the VFS
+    fn lookup(&self, _name: &str) -> FsResult<Arc<dyn Inode>> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Entries of this directory, without `.` and `..`
+    fn read_dir(&self) -> FsResult<Vec<DirEntry>> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Create `name` of type `ty` in this directory
+    fn create(&self, _name: &str, _ty: FileType) -> FsResult<Arc<dyn Inode>> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Add `name` in this directory as another name of `target`,
+    /// which must be a file of the same filesystem
+    fn link(&self, _name: &str, _target: &Arc<dyn Inode>) -> FsResult {
+        Err(FsError::NotSupported)
+    }
+
+    /// Remove `name` from this directory, directories must be empty
+    fn unlink(&self, _name: &str) -> FsResult {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Write the changes to the file to its storage
+    fn sync(&self) -> FsResult {
+        Ok(())
+    }
+}
+
+pub trait FileSystem: Send + Sync {
+    /// Type of the filesystem, like `fat`
+    fn name(&self) -> &str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Write all changes to the storage
+    fn sync(&self) -> FsResult {
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+//! Filesystems
+//!
+//! Concrete filesystems implement [`FileSystem`] and [`Inode`], and are
+//! mounted into a single tree. Files are then opened by path, and used
+//! through the returned [`File`].
+
+pub mod fat;
+pub mod initramfs;
+pub mod procfs;
+pub mod ramfs;
+
+mod dentry;
+mod file;
+mod inode;
+pub mod mount;
+
+pub use dentry::{resolve, resolve_parent, Dentry};
+pub use file::{File, OpenFlags, SeekFrom};
+pub use inode::{DirEntry, FileSystem, FileType, Inode, Metadata};
+pub use mount::{mount, umount};
+pub use procfs::ProcFs;
+pub use ramfs::RamFs;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    /// Empty path component, or `.` and `..` where a name is expected
+    InvalidPath,
+    InvalidArgument,
+    /// The file was not opened for the operation
+    PermissionDenied,
+    ReadOnly,
+    NoSpace,
+    NotSupported,
+    /// A mount point is in the way
+    Busy,
+    /// The file descriptor is not open
+    BadDescriptor,
+    TooManyFiles,
+    /// Writing to a pipe without reader
+    BrokenPipe,
+    /// The storage failed, or holds a corrupted filesystem
+    Io,
+}
+
+pub type FsResult<T = ()> = Result<T, FsError>;
+
+impl From<fat::FatError> for FsError {
+    fn from(err: fat::FatError) -> Self {
+        use fat::FatError::*;
+        match err {
+            NotFound => FsError::NotFound,
+            NotADirectory => FsError::NotADirectory,
+            IsADirectory => FsError::IsADirectory,
+            AlreadyExists => FsError::AlreadyExists,
+            DirectoryNotEmpty => FsError::DirectoryNotEmpty,
+            InvalidName => FsError::InvalidPath,
+            NoSpace => FsError::NoSpace,
+            ReadOnly => FsError::ReadOnly,
+            Unsupported => FsError::NotSupported,
+            Device(_) | InvalidFs => FsError::Io,
+        }
+    }
+}
+
+/// Open the file at `path`
+pub fn open(path: &str, flags: OpenFlags) -> FsResult<Arc<File>> {
+    let dentry = match resolve(path) {
+        Ok(_) if flags.contains(OpenFlags::CREATE | OpenFlags::EXCL) => {
+            return Err(FsError::AlreadyExists);
+        }
+        Ok(dentry) => dentry,
+        Err(FsError::NotFound) if flags.contains(OpenFlags::CREATE) => {
+            let (parent, name) = resolve_parent(path)?;
+            parent.inode().create(name, FileType::File)?;
+            parent.inode().sync()?;
+            parent.child(name)?
+        }
+        Err(err) => return Err(err),
+    };
+
+    let meta = dentry.inode().metadata()?;
+    if meta.is_dir() && flags.contains(OpenFlags::WRITE) {
+        return Err(FsError::IsADirectory);
+    }
+    if !meta.is_dir() && flags.contains(OpenFlags::DIRECTORY) {
+        return Err(FsError::NotADirectory);
+    }
+    if meta.read_only && flags.intersects(OpenFlags::WRITE | OpenFlags::TRUNCATE) {
+        return Err(FsError::ReadOnly);
+    }
+
+    if flags.contains(OpenFlags::TRUNCATE | OpenFlags::WRITE) && meta.size > 0 {
+        dentry.inode().truncate(0)?;
+    }
+
+    Ok(Arc::new(File::new(dentry, flags)))
+}
+
+pub fn stat(path: &str) -> FsResult<Metadata> {
+    resolve(path)?.inode().metadata()
+}
+
+pub fn read_dir(path: &str) -> FsResult<Vec<DirEntry>> {
+    resolve(path)?.read_dir()
+}
+
+pub fn create_dir(path: &str) -> FsResult {
+    let (parent, name) = resolve_parent(path)?;
+    if mount::mounted_at(&parent.child_path(name)).is_some() {
+        return Err(FsError::AlreadyExists);
+    }
+    parent.inode().create(name, FileType::Directory)?;
+    parent.inode().sync()
+}
+
+/// Remove the file or empty directory at `path`,
+/// which must not have filesystems mounted on or below it
+pub fn remove(path: &str) -> FsResult {
+    let (parent, name) = resolve_parent(path)?;
+    // the dentry is dropped before unlinking, so its inode is not in use
+    let path = parent.child(name)?.path();
+    if mount::is_busy(&path) {
+        return Err(FsError::Busy);
+    }
+    parent.inode().unlink(name)?;
+    parent.inode().sync()
+}
+
+/// Make `new` another name of the file at `old`
+pub fn link(old: &str, new: &str) -> FsResult {
+    let target = resolve(old)?;
+    let (parent, name) = resolve_parent(new)?;
+    parent.inode().link(name, target.inode())?;
+    parent.inode().sync()
+}
+
+/// Resize the file at `path`, filling with zeros when it grows
+pub fn truncate(path: &str, size: u64) -> FsResult {
+    open(path, OpenFlags::WRITE)?.truncate(size)
+}
+
+/// Replace the content of the file at `path` with `data`, creating it if needed
+pub fn write(path: &str, data: &[u8]) -> FsResult {
+    let file = open(path, OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE)?;
+    let mut len = 0;
+    while len < data.len() {
+        len += file.write(&data[len..])?;
+    }
+    Ok(())
+}
+
+/// Read the whole file at `path`
+pub fn read_to_vec(path: &str) -> FsResult<Vec<u8>> {
+    let file = open(path, OpenFlags::READ)?;
+    let mut buf = alloc::vec![0u8; file.stat()?.size as usize];
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    buf.truncate(len);
+    Ok(buf)
+}
+
+/// Directory listing for the kernel shell
+pub fn list_dir(path: &str) -> FsResult<String> {
+    let mut entries = read_dir(path)?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut output = String::new();
+    for entry in entries {
+        let child = format!("{}/{}", path.trim_end_matches('/'), entry.name);
+        let size = stat(&child).map(|m| m.size).unwrap_or(0);
+        let (size, unit) = crate::humanized_size_short(size);
+        match entry.ty {
+            FileType::Directory => output += &format!("{:>10} {}/\n", "<DIR>", entry.name),
+            FileType::File => output += &format!("{:>8.1} {} {}\n", size, unit, entry.name),
+        }
+    }
+    Ok(output)
+}
+
+/// Mount the first FAT volume as the root, or on `/boot` if the
+/// initramfs is the root, then a tmpfs on `/tmp` and the procfs on `/proc`.
+/// The mount points are not created as directories on the volume.
+pub fn init() {
+    fat::init();
+
+    if let Some(volume) = fat::volumes().first() {
+        let path = if mount::mounted_at("/").is_some() { "/boot" } else { "/" };
+        let source = String::from(volume.device().name());
+        if let Err(err) = mount(path, &source, fat::FatFileSystem::new(volume.clone())) {
+            warn!("Failed to mount {} on {}: {:?}", source, path, err);
+        }
+    }
+
+    // without a disk nor an initramfs, files still have somewhere to go
+    if mount::mounted_at("/").is_none() {
+        warn!("No filesystem to mount on /, using a ramfs.");
+        mount("/", "none", RamFs::new()).expect("Failed to mount ramfs on /");
+    }
+
+    if let Err(err) = mount("/tmp", "tmpfs", RamFs::with_limit(TMP_SIZE)) {
+        warn!("Failed to mount tmpfs on /tmp: {:?}", err);
+    }
+    if let Err(err) = mount("/proc", "proc", ProcFs::new()) {
+        warn!("Failed to mount proc on /proc: {:?}", err);
+    }
+}
+
+/// Most bytes held by files in `/tmp`
+const TMP_SIZE: usize = 1024 * 1024;
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use spin::{Mutex, MutexGuard};
+
+    /// The mount table is global, tests using it run one at a time
+    static TREE: Mutex<()> = Mutex::new(());
+
+    /// A tree made of a ramfs on `/` with `/mnt`, and a ramfs
+    /// on `/mnt/data` holding `file`, held until the guard is dropped
+    pub(super) fn tree() -> MutexGuard<'static, ()> {
+        let guard = TREE.lock();
+        mount::clear();
+        mount("/", "none", RamFs::new()).unwrap();
+        create_dir("/mnt").unwrap();
+        mount("/mnt/data", "data", RamFs::new()).unwrap();
+        write("/mnt/data/file", b"data").unwrap();
+        guard
+    }
+
+    #[test]
+    fn open_flags() {
+        let _tree = tree();
+        let create = OpenFlags::WRITE | OpenFlags::CREATE;
+
+        let file = open("/new", create | OpenFlags::EXCL).unwrap();
+        assert_eq!(file.write(b"hello"), Ok(5));
+        assert_eq!(file.read(&mut [0; 4]).err(), Some(FsError::PermissionDenied));
+        drop(file);
+        assert_eq!(open("/new", create | OpenFlags::EXCL).err(), Some(FsError::AlreadyExists));
+        assert_eq!(open("/missing", OpenFlags::READ).err(), Some(FsError::NotFound));
+        assert_eq!(open("/missing/new", create).err(), Some(FsError::NotFound));
+
+        // without TRUNCATE the content is overwritten in place
+        open("/new", create).unwrap().write(b"J").unwrap();
+        assert_eq!(read_to_vec("/new").unwrap(), b"Jello");
+
+        // TRUNCATE only applies to files opened for writing
+        open("/new", OpenFlags::READ | OpenFlags::TRUNCATE).unwrap();
+        assert_eq!(stat("/new").unwrap().size, 5);
+        let file = open("/new", OpenFlags::WRITE | OpenFlags::TRUNCATE).unwrap();
+        assert_eq!(file.stat().unwrap().size, 0);
+
+        assert_eq!(open("/mnt", OpenFlags::WRITE).err(), Some(FsError::IsADirectory));
+        assert_eq!(open("/new", OpenFlags::DIRECTORY).err(), Some(FsError::NotADirectory));
+        assert!(open("/mnt/data", OpenFlags::READ | OpenFlags::DIRECTORY).is_ok());
+    }
+
+    #[test]
+    fn remove_mount_point() {
+        let _tree = tree();
+
+        assert_eq!(remove("/mnt/data").err(), Some(FsError::Busy));
+        assert_eq!(remove("/mnt/data/../data/").err(), Some(FsError::Busy));
+        assert_eq!(create_dir("/mnt/data").err(), Some(FsError::AlreadyExists));
+        assert_eq!(umount("/mnt").err(), Some(FsError::Busy));
+        assert_eq!(umount("/").err(), Some(FsError::Busy));
+
+        // `/mnt` is empty on the filesystem below, but holds a mount point
+        assert_eq!(remove("/mnt").err(), Some(FsError::Busy));
+        umount("/mnt/data").unwrap();
+        assert_eq!(umount("/mnt").err(), Some(FsError::InvalidPath));
+        assert_eq!(stat("/mnt/data").err(), Some(FsError::NotFound));
+        remove("/mnt").unwrap();
+    }
+}
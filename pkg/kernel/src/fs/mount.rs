@@ -0,0 +1,106 @@
+//! Mount table
+//!
+//! Filesystems are mounted on directories given by their absolute path,
+//! the one mounted on `/` is the root of the tree. A mount point missing
+//! from the filesystem below only exists in the mount table.
+
+use super::*;
+use alloc::collections::BTreeMap;
+use spin::RwLock;
+
+pub struct Mount {
+    pub path: String,
+    /// Device or description of the mounted data
+    pub source: String,
+    pub fs: Arc<dyn FileSystem>,
+}
+
+static MOUNTS: RwLock<BTreeMap<String, Arc<Mount>>> = RwLock::new(BTreeMap::new());
+
+/// Dentry of the root of the tree
+pub fn root() -> FsResult<Arc<Dentry>> {
+    let fs = mounted_at("/").ok_or(FsError::NotFound)?;
+    Ok(Dentry::root(fs.root()))
+}
+
+/// The filesystem mounted on `path`, which must be canonical
+pub fn mounted_at(path: &str) -> Option<Arc<dyn FileSystem>> {
+    MOUNTS.read().get(path).map(|m| m.fs.clone())
+}
+
+/// If a filesystem is mounted on `path` or below it, `path` must be canonical
+pub fn is_busy(path: &str) -> bool {
+    let prefix = if path == "/" { String::from(path) } else { format!("{}/", path) };
+    MOUNTS.read().keys().any(|p| p == path || p.starts_with(&prefix))
+}
+
+/// Mount `fs` on the directory `path`, the root may be mounted first.
+/// The directory may also not exist, then nothing is created for it.
+pub fn mount(path: &str, source: &str, fs: Arc<dyn FileSystem>) -> FsResult {
+    let path = if MOUNTS.read().is_empty() && path.trim_matches('/').is_empty() {
+        String::from("/")
+    } else {
+        let (parent, name) = resolve_parent(path)?;
+        match parent.child(name) {
+            Ok(dentry) if !dentry.inode().metadata()?.is_dir() => {
+                return Err(FsError::NotADirectory);
+            }
+            Ok(dentry) => dentry.path(),
+            // like `/proc` on a boot partition, which should not get one more directory
+            Err(FsError::NotFound) => parent.child_path(name),
+            Err(err) => return Err(err),
+        }
+    };
+
+    let mut mounts = MOUNTS.write();
+    if mounts.contains_key(&path) {
+        return Err(FsError::Busy);
+    }
+
+    info!("Mounted {} ({}) on {}", source, fs.name(), path);
+    mounts.insert(
+        path.clone(),
+        Arc::new(Mount {
+            path,
+            source: source.into(),
+            fs,
+        }),
+    );
+    Ok(())
+}
+
+/// Sync and unmount the filesystem on `path`,
+/// which must not have other filesystems mounted below it.
+pub fn umount(path: &str) -> FsResult {
+    let path = resolve(path)?.path();
+
+    let mut mounts = MOUNTS.write();
+    let prefix = if path == "/" { path.clone() } else { format!("{}/", path) };
+    if mounts.keys().any(|p| *p != path && p.starts_with(&prefix)) {
+        return Err(FsError::Busy);
+    }
+
+    let mount = mounts.remove(&path).ok_or(FsError::InvalidPath)?;
+    mount.fs.sync()?;
+    info!("Unmounted {} from {}", mount.source, path);
+    Ok(())
+}
+
+/// All mounts, sorted by path
+pub fn mounts() -> Vec<Arc<Mount>> {
+    MOUNTS.read().values().cloned().collect()
+}
+
+/// Forget every mount, for tests that build their own tree
+#[cfg(test)]
+pub(super) fn clear() {
+    MOUNTS.write().clear();
+}
+
+/// Sync every mounted filesystem
+pub fn sync_all() -> FsResult {
+    for mount in mounts() {
+        mount.fs.sync()?;
+    }
+    Ok(())
+}
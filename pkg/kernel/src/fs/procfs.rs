@@ -0,0 +1,338 @@
+//! Process filesystem
+//!
+//! Files of `/proc` are generated when they are looked up, so an opened
+//! file reads a consistent snapshot. Each process that is not dead has a
+//! directory named by its pid, with `status`, `maps`, `env` and `stat`.
+
+use super::*;
+use crate::interrupt::consts::{Interrupts, Irq};
+use crate::interrupt::stats;
+use crate::memory::allocator::{ALLOCATOR, HEAP_SIZE};
+use crate::memory::{FRAME_ALLOCATOR, PAGE_SIZE};
+use crate::proc::manager::get_process_manager;
+use crate::proc::processor::{self, MAX_CPU_COUNT};
+use crate::proc::{ProcessId, ProgramStatus};
+use core::fmt::Write;
+use x86::cpuid::CpuId;
+use x86_64::instructions::interrupts;
+
+const ROOT_INODE: u64 = 1;
+
+type Generator = fn() -> String;
+type ProcessGenerator = fn(ProcessId) -> Option<String>;
+
+/// Files of `/proc`, and the function generating each
+const ROOT_FILES: [(&str, Generator); 5] = [
+    ("cmdline", cmdline),
+    ("cpuinfo", cpuinfo),
+    ("interrupts", stats::print_interrupts),
+    ("meminfo", meminfo),
+    ("uptime", uptime),
+];
+
+/// Files of `/proc/<pid>`
+const PROCESS_FILES: [(&str, ProcessGenerator); 4] = [
+    ("env", process_env),
+    ("maps", process_maps),
+    ("stat", process_stat),
+    ("status", process_status),
+];
+
+/// Inode numbers of `/proc/<pid>` and its files,
+/// the files of `/proc` come right after the root
+#[inline]
+fn process_inode(pid: ProcessId) -> u64 {
+    (pid.0 as u64 + 1) << 4
+}
+
+pub struct ProcFs;
+
+impl ProcFs {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self)
+    }
+}
+
+impl FileSystem for ProcFs {
+    fn name(&self) -> &str {
+        "proc"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(RootDir)
+    }
+}
+
+fn dir_metadata(inode: u64) -> Metadata {
+    Metadata {
+        ty: FileType::Directory,
+        size: 0,
+        inode,
+        links: 1,
+        read_only: true,
+    }
+}
+
+/// `/proc`
+struct RootDir;
+
+impl Inode for RootDir {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(dir_metadata(ROOT_INODE))
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        if let Some(idx) = ROOT_FILES.iter().position(|(n, _)| *n == name) {
+            let data = interrupts::without_interrupts(ROOT_FILES[idx].1);
+            return Ok(Arc::new(ProcFile::new(ROOT_INODE + 1 + idx as u64, data)));
+        }
+
+        let pid = name.parse().map(ProcessId).map_err(|_| FsError::NotFound)?;
+        let alive = interrupts::without_interrupts(|| {
+            get_process_manager()
+                .get_proc(&pid)
+                .is_some_and(|p| p.read().status() != ProgramStatus::Dead)
+        });
+        if !alive {
+            return Err(FsError::NotFound);
+        }
+        Ok(Arc::new(ProcessDir(pid)))
+    }
+
+    fn read_dir(&self) -> FsResult<Vec<DirEntry>> {
+        let mut entries: Vec<_> = ROOT_FILES
+            .iter()
+            .enumerate()
+            .map(|(idx, (name, _))| DirEntry {
+                name: String::from(*name),
+                ty: FileType::File,
+                inode: ROOT_INODE + 1 + idx as u64,
+            })
+            .collect();
+
+        let processes = interrupts::without_interrupts(|| get_process_manager().processes());
+        entries.extend(processes.iter().map(|p| DirEntry {
+            name: format!("{}", p.pid()),
+            ty: FileType::Directory,
+            inode: process_inode(p.pid()),
+        }));
+        Ok(entries)
+    }
+}
+
+/// `/proc/<pid>`
+struct ProcessDir(ProcessId);
+
+impl Inode for ProcessDir {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(dir_metadata(process_inode(self.0)))
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        let idx = PROCESS_FILES
+            .iter()
+            .position(|(n, _)| *n == name)
+            .ok_or(FsError::NotFound)?;
+
+        // the process may have exited since its directory was looked up
+        let data = interrupts::without_interrupts(|| PROCESS_FILES[idx].1(self.0))
+            .ok_or(FsError::NotFound)?;
+        let inode = process_inode(self.0) + 1 + idx as u64;
+        Ok(Arc::new(ProcFile::new(inode, data)))
+    }
+
+    fn read_dir(&self) -> FsResult<Vec<DirEntry>> {
+        Ok(PROCESS_FILES
+            .iter()
+            .enumerate()
+            .map(|(idx, (name, _))| DirEntry {
+                name: String::from(*name),
+                ty: FileType::File,
+                inode: process_inode(self.0) + 1 + idx as u64,
+            })
+            .collect())
+    }
+}
+
+/// A generated file
+struct ProcFile {
+    inode: u64,
+    data: String,
+}
+
+impl ProcFile {
+    fn new(inode: u64, data: String) -> Self {
+        Self { inode, data }
+    }
+}
+
+impl Inode for ProcFile {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata {
+            ty: FileType::File,
+            size: self.data.len() as u64,
+            inode: self.inode,
+            links: 1,
+            read_only: true,
+        })
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let data = self.data.as_bytes();
+        let start = (offset as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+}
+
+fn process_status(pid: ProcessId) -> Option<String> {
+    let proc = get_process_manager().get_proc(&pid)?;
+    let inner = proc.read();
+
+    let mut output = String::new();
+    writeln!(output, "Name:     {}", inner.name()).unwrap();
+    writeln!(output, "State:    {:?}", inner.status()).unwrap();
+    writeln!(output, "Pid:      {}", pid).unwrap();
+    writeln!(output, "PPid:     {}", inner.parent().map(|p| p.pid().0).unwrap_or(0)).unwrap();
+    let children: Vec<_> = inner.children().iter().map(|c| format!("{}", c.pid())).collect();
+    writeln!(output, "Children: {}", children.join(" ")).unwrap();
+    writeln!(output, "Ticks:    {}", inner.ticks()).unwrap();
+    writeln!(output, "Cpu:      {}", inner.last_cpu()).unwrap();
+    writeln!(output, "Affinity: {:#x}", inner.affinity()).unwrap();
+    if let Some(data) = inner.data() {
+        writeln!(output, "Fds:      {}", data.fd_table().iter().count()).unwrap();
+    }
+    if let Some(code) = inner.exit_code() {
+        writeln!(output, "ExitCode: {}", code).unwrap();
+    }
+    Some(output)
+}
+
+/// One line: pid, name, state, ppid, ticks, cpu and affinity
+///
+/// the state is `R` for running or ready, `S` for blocked, `Z` for dead
+fn process_stat(pid: ProcessId) -> Option<String> {
+    let proc = get_process_manager().get_proc(&pid)?;
+    let inner = proc.read();
+
+    let state = match inner.status() {
+        ProgramStatus::Running | ProgramStatus::Ready => 'R',
+        ProgramStatus::Blocked => 'S',
+        ProgramStatus::Dead => 'Z',
+    };
+    Some(format!(
+        "{} ({}) {} {} {} {} {:#x}\n",
+        pid,
+        inner.name(),
+        state,
+        inner.parent().map(|p| p.pid().0).unwrap_or(0),
+        inner.ticks(),
+        inner.last_cpu(),
+        inner.affinity()
+    ))
+}
+
+/// Mapped segments of the process, only its stack is tracked
+fn process_maps(pid: ProcessId) -> Option<String> {
+    let proc = get_process_manager().get_proc(&pid)?;
+    let inner = proc.read();
+
+    let mut output = String::new();
+    if let Some(stack) = inner.data().and_then(|d| d.stack()) {
+        writeln!(
+            output,
+            "{:016x}-{:016x} rw-p [stack]",
+            stack.start.start_address().as_u64(),
+            stack.end.start_address().as_u64()
+        )
+        .unwrap();
+    }
+    Some(output)
+}
+
+fn process_env(pid: ProcessId) -> Option<String> {
+    let proc = get_process_manager().get_proc(&pid)?;
+    let inner = proc.read();
+
+    let mut output = String::new();
+    for (key, value) in inner.data().map(|d| d.envs()).unwrap_or_default() {
+        writeln!(output, "{}={}", key, value).unwrap();
+    }
+    Some(output)
+}
+
+/// The command line of the boot entry
+fn cmdline() -> String {
+    format!("{}\n", crate::cmdline())
+}
+
+/// Usable frames and the kernel heap, in KiB
+fn meminfo() -> String {
+    let mut output = String::new();
+    let kib = |bytes: u64| bytes / 1024;
+
+    // copied out, formatting allocates from the heap
+    let frames = FRAME_ALLOCATOR
+        .get()
+        .map(|m| m.lock())
+        .map(|frames| (frames.frames_total() as u64, frames.frames_used() as u64));
+    if let Some((total, used)) = frames {
+        let (total, used) = (total * PAGE_SIZE, used * PAGE_SIZE);
+        writeln!(output, "MemTotal:  {:>10} kB", kib(total)).unwrap();
+        writeln!(output, "MemUsed:   {:>10} kB", kib(used)).unwrap();
+        writeln!(output, "MemFree:   {:>10} kB", kib(total.saturating_sub(used))).unwrap();
+    }
+
+    let (used, free) = {
+        let heap = ALLOCATOR.lock();
+        (heap.used() as u64, heap.free() as u64)
+    };
+    writeln!(output, "HeapTotal: {:>10} kB", kib(HEAP_SIZE as u64)).unwrap();
+    writeln!(output, "HeapUsed:  {:>10} kB", kib(used)).unwrap();
+    writeln!(output, "HeapFree:  {:>10} kB", kib(free)).unwrap();
+    output
+}
+
+/// Timer ticks of the bootstrap processor since boot, and the ticks the
+/// CPUs spent in their idle process, as there is no calibrated clock
+fn uptime() -> String {
+    let timer = Interrupts::IrqBase as u8 + Irq::Timer as u8;
+    let idle: usize = (0..MAX_CPU_COUNT)
+        .filter_map(|cpuid| processor::get(cpuid).get_idle())
+        .filter_map(|pid| get_process_manager().get_proc(&pid))
+        .map(|p| p.read().ticks())
+        .sum();
+    format!("{} {}\n", stats::count(timer, 0), idle)
+}
+
+/// What CPUID reports about the CPU reading the file, then every
+/// online CPU, numbered by CPU id
+fn cpuinfo() -> String {
+    let cpuid = CpuId::new();
+    let mut output = String::new();
+
+    if let Some(vendor) = cpuid.get_vendor_info() {
+        writeln!(output, "vendor_id  : {}", vendor.as_str()).unwrap();
+    }
+    if let Some(brand) = cpuid.get_processor_brand_string() {
+        writeln!(output, "model name : {}", brand.as_str().trim()).unwrap();
+    }
+    if let Some(features) = cpuid.get_feature_info() {
+        writeln!(output, "cpu family : {}", features.family_id()).unwrap();
+        writeln!(output, "model      : {}", features.model_id()).unwrap();
+        writeln!(output, "stepping   : {}", features.stepping_id()).unwrap();
+    }
+    writeln!(output, "read on    : {}\n", processor::current_id()).unwrap();
+
+    let online = processor::online_mask();
+    for id in (0..MAX_CPU_COUNT).filter(|id| online & (1 << id) != 0) {
+        let cpu = processor::get(id);
+        writeln!(output, "processor  : {}", id).unwrap();
+        writeln!(output, "apicid     : {}", processor::apic_id(id)).unwrap();
+        let pid = cpu.get_pid().map(|p| p.0).unwrap_or(0);
+        writeln!(output, "pid        : {}", pid).unwrap();
+        writeln!(output, "idle       : {}\n", cpu.is_idle()).unwrap();
+    }
+    output
+}
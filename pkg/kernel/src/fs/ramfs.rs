@@ -0,0 +1,405 @@
+//! In-memory filesystem
+//!
+//! Files and directories live on the kernel heap, and are lost at shutdown.
+//! An inode is freed once its last name is unlinked and it is no longer
+//! open. A tmpfs is a ramfs with a limit on the bytes held by its files.
+
+use super::*;
+use alloc::collections::BTreeMap;
+use alloc::sync::Weak;
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use spin::{Mutex, RwLock};
+
+/// State shared by the inodes of a filesystem
+struct Superblock {
+    next_inode: AtomicU64,
+    /// Live inodes by inode number, to find the target of a hard link
+    inodes: Mutex<BTreeMap<u64, Weak<RamInode>>>,
+    /// Bytes held by files
+    used: AtomicUsize,
+    /// Most bytes files may hold, None means no limit but the heap
+    limit: Option<usize>,
+}
+
+impl Superblock {
+    /// Account for `size` more bytes of file data
+    fn reserve(&self, size: usize) -> FsResult {
+        let limit = self.limit.unwrap_or(usize::MAX);
+        let mut used = self.used.load(Ordering::Relaxed);
+        loop {
+            let new = used
+                .checked_add(size)
+                .filter(|&new| new <= limit)
+                .ok_or(FsError::NoSpace)?;
+            match self
+                .used
+                .compare_exchange_weak(used, new, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => return Ok(()),
+                Err(current) => used = current,
+            }
+        }
+    }
+
+    fn release(&self, size: usize) {
+        self.used.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+pub struct RamFs {
+    name: &'static str,
+    root: Arc<RamInode>,
+}
+
+impl RamFs {
+    pub fn new() -> Arc<Self> {
+        Self::create("ramfs", None)
+    }
+
+    /// A tmpfs, whose files may hold at most `limit` bytes
+    pub fn with_limit(limit: usize) -> Arc<Self> {
+        Self::create("tmpfs", Some(limit))
+    }
+
+    fn create(name: &'static str, limit: Option<usize>) -> Arc<Self> {
+        let sb = Arc::new(Superblock {
+            next_inode: AtomicU64::new(1),
+            inodes: Mutex::new(BTreeMap::new()),
+            used: AtomicUsize::new(0),
+            limit,
+        });
+        Arc::new(Self {
+            name,
+            root: RamInode::new(sb, FileType::Directory),
+        })
+    }
+
+    /// Bytes held by files, and the limit
+    pub fn usage(&self) -> (usize, Option<usize>) {
+        let sb = &self.root.sb;
+        (sb.used.load(Ordering::Relaxed), sb.limit)
+    }
+}
+
+impl FileSystem for RamFs {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+enum Content {
+    File(Vec<u8>),
+    Dir(BTreeMap<String, Arc<RamInode>>),
+}
+
+struct RamInode {
+    sb: Arc<Superblock>,
+    id: u64,
+    /// Number of names in directories
+    links: AtomicU32,
+    content: RwLock<Content>,
+}
+
+impl RamInode {
+    fn new(sb: Arc<Superblock>, ty: FileType) -> Arc<Self> {
+        let id = sb.next_inode.fetch_add(1, Ordering::Relaxed);
+        let content = match ty {
+            FileType::File => Content::File(Vec::new()),
+            FileType::Directory => Content::Dir(BTreeMap::new()),
+        };
+        let inode = Arc::new(Self {
+            sb: sb.clone(),
+            id,
+            links: AtomicU32::new(1),
+            content: RwLock::new(content),
+        });
+
+        sb.inodes.lock().insert(id, Arc::downgrade(&inode));
+        inode
+    }
+
+    fn file_type(&self) -> FileType {
+        match *self.content.read() {
+            Content::File(_) => FileType::File,
+            Content::Dir(_) => FileType::Directory,
+        }
+    }
+
+    /// Resize the data of a file, within the limit of the filesystem
+    fn resize(&self, data: &mut Vec<u8>, size: usize) -> FsResult {
+        if size > data.len() {
+            self.sb.reserve(size - data.len())?;
+            data.try_reserve_exact(size - data.len()).map_err(|_| {
+                self.sb.release(size - data.len());
+                FsError::NoSpace
+            })?;
+        } else {
+            self.sb.release(data.len() - size);
+        }
+        data.resize(size, 0);
+        Ok(())
+    }
+}
+
+impl Drop for RamInode {
+    fn drop(&mut self) {
+        if let Content::File(data) = self.content.get_mut() {
+            self.sb.release(data.len());
+        }
+        self.sb.inodes.lock().remove(&self.id);
+    }
+}
+
+impl Inode for RamInode {
+    fn metadata(&self) -> FsResult<Metadata> {
+        let (ty, size) = match &*self.content.read() {
+            Content::File(data) => (FileType::File, data.len() as u64),
+            Content::Dir(_) => (FileType::Directory, 0),
+        };
+        Ok(Metadata {
+            ty,
+            size,
+            inode: self.id,
+            links: self.links.load(Ordering::Relaxed),
+            read_only: false,
+        })
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        match &*self.content.read() {
+            Content::File(data) => {
+                let start = (offset as usize).min(data.len());
+                let len = buf.len().min(data.len() - start);
+                buf[..len].copy_from_slice(&data[start..start + len]);
+                Ok(len)
+            }
+            Content::Dir(_) => Err(FsError::IsADirectory),
+        }
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        match &mut *self.content.write() {
+            Content::File(data) => {
+                let start = offset as usize;
+                let end = start.checked_add(buf.len()).ok_or(FsError::InvalidArgument)?;
+                if end > data.len() {
+                    self.resize(data, end)?;
+                }
+                data[start..end].copy_from_slice(buf);
+                Ok(buf.len())
+            }
+            Content::Dir(_) => Err(FsError::IsADirectory),
+        }
+    }
+
+    fn truncate(&self, size: u64) -> FsResult {
+        match &mut *self.content.write() {
+            Content::File(data) => {
+                self.resize(data, size as usize)?;
+                data.shrink_to_fit();
+                Ok(())
+            }
+            Content::Dir(_) => Err(FsError::IsADirectory),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        match &*self.content.read() {
+            Content::Dir(entries) => match entries.get(name) {
+                Some(inode) => Ok(inode.clone()),
+                None => Err(FsError::NotFound),
+            },
+            Content::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn read_dir(&self) -> FsResult<Vec<DirEntry>> {
+        match &*self.content.read() {
+            Content::Dir(entries) => Ok(entries
+                .iter()
+                .map(|(name, inode)| DirEntry {
+                    name: name.clone(),
+                    ty: inode.file_type(),
+                    inode: inode.id,
+                })
+                .collect()),
+            Content::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn create(&self, name: &str, ty: FileType) -> FsResult<Arc<dyn Inode>> {
+        match &mut *self.content.write() {
+            Content::Dir(entries) => {
+                if entries.contains_key(name) {
+                    return Err(FsError::AlreadyExists);
+                }
+                let inode = RamInode::new(self.sb.clone(), ty);
+                entries.insert(name.into(), inode.clone());
+                Ok(inode)
+            }
+            Content::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn link(&self, name: &str, target: &Arc<dyn Inode>) -> FsResult {
+        // the target must be an inode of this filesystem
+        let id = target.metadata()?.inode;
+        // upgraded without the lock, dropping the inode takes it
+        let inode = self.sb.inodes.lock().get(&id).cloned();
+        let inode = inode
+            .and_then(|inode| inode.upgrade())
+            .filter(|inode| Arc::as_ptr(inode) as *const u8 == Arc::as_ptr(target) as *const u8)
+            .ok_or(FsError::InvalidArgument)?;
+
+        if inode.file_type() == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+
+        match &mut *self.content.write() {
+            Content::Dir(entries) => {
+                if entries.contains_key(name) {
+                    return Err(FsError::AlreadyExists);
+                }
+                inode.links.fetch_add(1, Ordering::Relaxed);
+                entries.insert(name.into(), inode);
+                Ok(())
+            }
+            Content::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn unlink(&self, name: &str) -> FsResult {
+        match &mut *self.content.write() {
+            Content::Dir(entries) => {
+                let inode = entries.get(name).ok_or(FsError::NotFound)?;
+                if let Content::Dir(children) = &*inode.content.read() {
+                    if !children.is_empty() {
+                        return Err(FsError::DirectoryNotEmpty);
+                    }
+                }
+                if let Some(inode) = entries.remove(name) {
+                    inode.links.fetch_sub(1, Ordering::Relaxed);
+                }
+                Ok(())
+            }
+            Content::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(dir: &Arc<dyn Inode>, name: &str, data: &[u8]) -> FsResult<Arc<dyn Inode>> {
+        let inode = dir.create(name, FileType::File)?;
+        inode.write_at(0, data)?;
+        Ok(inode)
+    }
+
+    fn live_inodes(fs: &RamFs) -> usize {
+        fs.root.sb.inodes.lock().len()
+    }
+
+    #[test]
+    fn hard_links() {
+        let fs = RamFs::new();
+        let root = fs.root();
+        let dir = root.create("dir", FileType::Directory).unwrap();
+        let inode = file(&root, "a", b"hello").unwrap();
+
+        dir.link("b", &inode).unwrap();
+        let other = dir.lookup("b").unwrap();
+        assert_eq!(other.metadata().unwrap().inode, inode.metadata().unwrap().inode);
+        assert_eq!(inode.metadata().unwrap().links, 2);
+        other.write_at(0, b"J").unwrap();
+        let mut buf = [0; 5];
+        inode.read_at(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"Jello");
+
+        assert_eq!(dir.link("b", &inode).err(), Some(FsError::AlreadyExists));
+        assert_eq!(root.link("d", &dir).err(), Some(FsError::IsADirectory));
+        assert_eq!(inode.link("c", &inode).err(), Some(FsError::NotADirectory));
+        let foreign = file(&RamFs::new().root(), "f", b"").unwrap();
+        assert_eq!(root.link("f", &foreign).err(), Some(FsError::InvalidArgument));
+
+        // the data stays until the last name is gone
+        root.unlink("a").unwrap();
+        assert_eq!(other.metadata().unwrap().links, 1);
+        drop((inode, other));
+        assert_eq!(live_inodes(&fs), 3);
+        assert_eq!(fs.usage().0, 5);
+
+        dir.unlink("b").unwrap();
+        assert_eq!(live_inodes(&fs), 2);
+        assert_eq!(fs.usage().0, 0);
+        assert_eq!(dir.link("b", &foreign).err(), Some(FsError::InvalidArgument));
+    }
+
+    #[test]
+    fn limits() {
+        let fs = RamFs::with_limit(100);
+        let sb = &fs.root.sb;
+        sb.reserve(60).unwrap();
+        assert_eq!(sb.reserve(41), Err(FsError::NoSpace));
+        assert_eq!(sb.reserve(usize::MAX), Err(FsError::NoSpace));
+        sb.reserve(40).unwrap();
+        sb.release(100);
+        assert_eq!(fs.usage(), (0, Some(100)));
+
+        let root = fs.root();
+        let a = file(&root, "a", &[1; 60]).unwrap();
+        // a failed write holds nothing
+        assert_eq!(file(&root, "b", &[2; 50]).err(), Some(FsError::NoSpace));
+        let b = root.lookup("b").unwrap();
+        assert_eq!((b.metadata().unwrap().size, fs.usage().0), (0, 60));
+        b.write_at(0, &[2; 40]).unwrap();
+        assert_eq!(a.truncate(61), Err(FsError::NoSpace));
+        assert_eq!(a.write_at(60, &[1]), Err(FsError::NoSpace));
+
+        // shrinking gives the space back, growing zero-fills
+        a.truncate(10).unwrap();
+        assert_eq!(fs.usage().0, 50);
+        a.truncate(60).unwrap();
+        let mut buf = [0xff; 60];
+        assert_eq!(a.read_at(0, &mut buf), Ok(60));
+        assert!(buf[..10].iter().all(|&b| b == 1) && buf[10..].iter().all(|&b| b == 0));
+        assert_eq!(fs.usage().0, 100);
+
+        assert_eq!(RamFs::new().usage(), (0, None));
+    }
+
+    #[test]
+    fn unlink() {
+        let fs = RamFs::with_limit(1000);
+        let root = fs.root();
+        let dir = root.create("dir", FileType::Directory).unwrap();
+        let open = file(&dir, "open", &[0; 300]).unwrap();
+        file(&dir, "closed", &[0; 200]).unwrap();
+        assert_eq!(fs.usage().0, 500);
+
+        assert_eq!(root.unlink("dir"), Err(FsError::DirectoryNotEmpty));
+        assert_eq!(root.unlink("missing"), Err(FsError::NotFound));
+        assert_eq!(open.unlink("x"), Err(FsError::NotADirectory));
+
+        dir.unlink("closed").unwrap();
+        assert_eq!(fs.usage().0, 300);
+        // an open file keeps its data, though it can not be found anymore
+        dir.unlink("open").unwrap();
+        assert_eq!(dir.lookup("open").err(), Some(FsError::NotFound));
+        assert_eq!(open.metadata().unwrap().links, 0);
+        assert_eq!(fs.usage().0, 300);
+        open.write_at(300, &[0; 100]).unwrap();
+        drop(open);
+        assert_eq!(fs.usage().0, 0);
+
+        root.unlink("dir").unwrap();
+        drop(dir);
+        assert!(root.read_dir().unwrap().is_empty());
+        assert_eq!(live_inodes(&fs), 1);
+    }
+}
@@ -4,7 +4,7 @@
 
 use bit_field::BitField;
 
-/// Default physical address of IO APIC
+/// Default physical address of IO APIC, used when ACPI MADT is absent
 pub const IOAPIC_ADDR: u64 = 0xFEC00000;
 
 bitflags! {
@@ -74,6 +74,19 @@ impl IoApic {
         trace!("Enable IOApic: IRQ={}, CPU={}", irq, cpuid);
     }
 
+    /// Route input `pin` to `vector` on the given cpuid,
+    /// with the trigger mode and polarity reported by ACPI.
+    pub fn route(&mut self, pin: u8, vector: u8, level: bool, active_low: bool, cpuid: u8) {
+        let mut flags = RedirectionEntry::NONE;
+        flags.set(RedirectionEntry::LEVEL, level);
+        flags.set(RedirectionEntry::ACTIVELOW, active_low);
+        unsafe {
+            self.write(0x10 + 2 * pin, vector as u32 | flags.bits());
+            self.write(0x10 + 2 * pin + 1, (cpuid as u32) << 24);
+        }
+        trace!("Route IOApic: PIN={}, VEC={}, CPU={}", pin, vector, cpuid);
+    }
+
     pub fn disable(&mut self, irq: u8, cpuid: u8) {
         self.write_irq(irq, RedirectionEntry::DISABLED, cpuid);
     }
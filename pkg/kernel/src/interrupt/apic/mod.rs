@@ -9,14 +9,20 @@
 //! Reference: [OSDev Wiki](https://wiki.osdev.org/APIC)
 
 pub use ioapic::{IoApic, IOAPIC_ADDR};
+pub use x2apic::X2Apic;
 pub use xapic::{XApic, LAPIC_ADDR};
 
 mod ioapic;
+mod x2apic;
 mod xapic;
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
 pub trait LocalApic {
     /// If this type APIC is supported
-    fn support() -> bool;
+    fn support() -> bool
+    where
+        Self: Sized;
 
     /// Initialize the LAPIC for the current CPU
     fn cpu_init(&mut self);
@@ -31,6 +37,99 @@ pub trait LocalApic {
 
     fn set_icr(&mut self, value: u64);
 
+    /// Send an IPI with the low 32 bits of ICR to the APIC `dest`
+    fn send_ipi(&mut self, dest: u32, command: u32);
+
     /// Acknowledge interrupt on the current CPU
     fn eoi(&mut self);
+
+    /// Read and clear the Error Status Register
+    fn error_status(&mut self) -> u32;
+}
+
+/// If the local APICs are in x2APIC mode, decided on the BSP
+static X2APIC_MODE: AtomicBool = AtomicBool::new(false);
+
+/// The local APIC of the current CPU, in the mode chosen by `select`
+pub enum Lapic {
+    XApic(XApic),
+    X2Apic(X2Apic),
+}
+
+impl Lapic {
+    /// Choose x2APIC if supported, xAPIC otherwise
+    pub fn select() -> Option<&'static str> {
+        if X2Apic::support() {
+            X2APIC_MODE.store(true, Ordering::Release);
+            Some("x2APIC")
+        } else if XApic::support() {
+            Some("xAPIC")
+        } else {
+            None
+        }
+    }
+
+    /// Get the local APIC, `addr` is the virtual address of xAPIC registers
+    #[inline]
+    pub unsafe fn new(addr: u64) -> Self {
+        if X2APIC_MODE.load(Ordering::Acquire) {
+            Lapic::X2Apic(X2Apic::new())
+        } else {
+            Lapic::XApic(XApic::new(addr))
+        }
+    }
+
+    #[inline]
+    fn inner(&self) -> &dyn LocalApic {
+        match self {
+            Lapic::XApic(apic) => apic,
+            Lapic::X2Apic(apic) => apic,
+        }
+    }
+
+    #[inline]
+    fn inner_mut(&mut self) -> &mut dyn LocalApic {
+        match self {
+            Lapic::XApic(apic) => apic,
+            Lapic::X2Apic(apic) => apic,
+        }
+    }
+}
+
+impl LocalApic for Lapic {
+    fn support() -> bool {
+        X2Apic::support() || XApic::support()
+    }
+
+    fn cpu_init(&mut self) {
+        self.inner_mut().cpu_init()
+    }
+
+    fn id(&self) -> u32 {
+        self.inner().id()
+    }
+
+    fn version(&self) -> u32 {
+        self.inner().version()
+    }
+
+    fn icr(&self) -> u64 {
+        self.inner().icr()
+    }
+
+    fn set_icr(&mut self, value: u64) {
+        self.inner_mut().set_icr(value)
+    }
+
+    fn send_ipi(&mut self, dest: u32, command: u32) {
+        self.inner_mut().send_ipi(dest, command)
+    }
+
+    fn eoi(&mut self) {
+        self.inner_mut().eoi()
+    }
+
+    fn error_status(&mut self) -> u32 {
+        self.inner_mut().error_status()
+    }
 }
@@ -0,0 +1,151 @@
+use super::LocalApic;
+use crate::interrupt::consts::{ Interrupts, Irq };
+use core::fmt::{ Debug, Error, Formatter };
+use x86::cpuid::CpuId;
+use x86_64::registers::model_specific::Msr;
+
+/// IA32_APIC_BASE MSR
+const APIC_BASE_MSR: u32 = 0x1b;
+/// xAPIC global enable
+const APIC_BASE_EN: u64 = 1 << 11;
+/// x2APIC mode enable
+const APIC_BASE_EXTD: u64 = 1 << 10;
+
+/// x2APIC registers are MSRs at `0x800 + (xAPIC offset >> 4)`
+const fn msr(offset: u32) -> u32 {
+  0x800 + (offset >> 4)
+}
+
+/// Local APIC in x2APIC mode, accessed with MSRs instead of MMIO
+///
+/// Reference: Intel SDM Vol. 3A, 10.12 Extended XAPIC (x2APIC)
+pub struct X2Apic;
+
+impl X2Apic {
+  pub fn new() -> Self {
+    X2Apic
+  }
+
+  unsafe fn read(&self, offset: u32) -> u64 {
+    Msr::new(msr(offset)).read()
+  }
+
+  unsafe fn write(&mut self, offset: u32, value: u64) {
+    Msr::new(msr(offset)).write(value);
+  }
+
+  /// Switch the local APIC of the current CPU into x2APIC mode
+  fn enable(&mut self) {
+    unsafe {
+      let mut base = Msr::new(APIC_BASE_MSR);
+      // EXTD can only be set when the xAPIC is enabled
+      let value = base.read() | APIC_BASE_EN;
+      base.write(value);
+      base.write(value | APIC_BASE_EXTD);
+    }
+  }
+}
+
+impl Default for X2Apic {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl LocalApic for X2Apic {
+  /// If x2APIC is supported
+  fn support() -> bool {
+    CpuId::new()
+      .get_feature_info()
+      .map(|f| f.has_x2apic())
+      .unwrap_or(false)
+  }
+
+  /// Switch to x2APIC mode and initialize it for the current CPU
+  ///
+  /// same as `XApic::cpu_init`, but there is no arbitration ID to synchronise.
+  fn cpu_init(&mut self) {
+    self.enable();
+    unsafe {
+      // Enable local APIC; set spurious interrupt vector.
+      let mut spiv = self.read(0xf0);
+      spiv |= 1 << 8;
+      spiv &= !0xff;
+      spiv |= Interrupts::Spurious as u64;
+      self.write(0xf0, spiv);
+      // The timer repeatedly counts down at bus frequency
+      let mut lvt_timer = self.read(0x320);
+      lvt_timer &= !0xff;
+      lvt_timer |= (Interrupts::IrqBase as u64) + (Irq::Timer as u64);
+      lvt_timer &= !(1 << 16); // clear Mask
+      lvt_timer |= 1 << 17; // set Timer Periodic Mode
+      self.write(0x320, lvt_timer);
+      self.write(0x3E0, 0b1011); // set Timer Divide to 1
+      self.write(0x380, 0x20000); // set Timer Initial Count
+      // Disable logical interrupt lines (LINT0, LINT1)
+      self.write(0x350, 1 << 16);
+      self.write(0x360, 1 << 16);
+      // Disable performance counter overflow interrupts (PCINT)
+      self.write(0x340, 1 << 16);
+      // Map error interrupt to IRQ_ERROR.
+      let mut error_vector = self.read(0x370);
+      error_vector &= !((1 << 16) | 0xff);
+      error_vector |= Interrupts::ApicError as u64;
+      self.write(0x370, error_vector);
+      // Clear error status register, the write must be zero.
+      self.write(0x280, 0);
+      // Ack any outstanding interrupts.
+      self.eoi();
+      // Enable interrupts on the APIC (but not on the processor).
+      self.write(0x80, 0);
+    }
+  }
+
+  /// The 32-bit x2APIC ID
+  fn id(&self) -> u32 {
+    unsafe { self.read(0x20) as u32 }
+  }
+
+  fn version(&self) -> u32 {
+    unsafe { self.read(0x30) as u32 }
+  }
+
+  /// The ICR is a single 64-bit MSR in x2APIC mode
+  fn icr(&self) -> u64 {
+    unsafe { self.read(0x300) }
+  }
+
+  fn set_icr(&mut self, value: u64) {
+    unsafe {
+      self.write(0x300, value);
+    }
+  }
+
+  fn send_ipi(&mut self, dest: u32, command: u32) {
+    self.set_icr(((dest as u64) << 32) | command as u64);
+  }
+
+  fn eoi(&mut self) {
+    unsafe {
+      self.write(0xb0, 0);
+    }
+  }
+
+  /// The ESR must be written before reading to latch the errors
+  fn error_status(&mut self) -> u32 {
+    unsafe {
+      self.write(0x280, 0);
+      self.read(0x280) as u32
+    }
+  }
+}
+
+impl Debug for X2Apic {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+    f.debug_struct("X2apic")
+      .field("id", &self.id())
+      .field("version", &self.version())
+      .field("icr", &self.icr())
+      .finish()
+  }
+}
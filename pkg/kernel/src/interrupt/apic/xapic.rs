@@ -5,7 +5,7 @@ use core::fmt::{ Debug, Error, Formatter };
 use core::ptr::{ read_volatile, write_volatile };
 use x86::cpuid::CpuId; //crate为离当前路径最近的.toml文件所在的路径
 use crate::memory::address;
-/// Default physical address of xAPIC
+/// Default physical address of xAPIC, used when ACPI MADT is absent
 pub const LAPIC_ADDR: u64 = 0xfee00000;
 
 pub struct XApic {
@@ -47,7 +47,7 @@ impl LocalApic for XApic {
       spiv |= 1 << 8; // set EN bit
       // clear and set Vector
       spiv &= !0xff; //清空第八位
-      spiv |= (Interrupts::IrqBase as u32) + (Irq::Spurious as u32);
+      spiv |= Interrupts::Spurious as u32;
       self.write(0xf0, spiv);
       // FIXME: The timer repeatedly counts down at bus frequency
       let mut lvt_timer = self.read(0x320);
@@ -57,6 +57,8 @@ impl LocalApic for XApic {
       lvt_timer &= !(1 << 16); // clear Mask
       lvt_timer |= 1 << 17; // set Timer Periodic Mode
       self.write(0x320, lvt_timer);
+      self.write(0x3E0, 0b1011); // set Timer Divide to 1
+      self.write(0x380, 0x20000); // set Timer Initial Count
       // FIXME: Disable logical interrupt lines (LINT0, LINT1)
       self.write(0x350, 1 << 16); // set Mask
       self.write(0x360, 1 << 16);
@@ -64,8 +66,8 @@ impl LocalApic for XApic {
       self.write(0x340, 1 << 16);
       // FIXME: Map error interrupt to IRQ_ERROR.
       let mut error_vector = self.read(0x370);
-      error_vector &= !0xff; // Clear existing vector
-      error_vector |= Irq::Error as u32; // Assuming Irq::Error is the error interrupt vector
+      error_vector &= !((1 << 16) | 0xff); // Clear existing vector and unmask
+      error_vector |= Interrupts::ApicError as u32;
       self.write(0x370, error_vector);
       // FIXME: Clear error status register (requires back-to-back writes).
       self.write(0x280, 0);
@@ -111,11 +113,23 @@ impl LocalApic for XApic {
     }
   }
 
+  fn send_ipi(&mut self, dest: u32, command: u32) {
+    self.set_icr(((dest as u64) << 56) | command as u64);
+  }
+
   fn eoi(&mut self) {
     unsafe {
       self.write(0x00b0, 0);
     }
   }
+
+  /// The ESR must be written before reading to latch the errors
+  fn error_status(&mut self) -> u32 {
+    unsafe {
+      self.write(0x280, 0);
+      self.read(0x280)
+    }
+  }
 }
 
 impl Debug for XApic {
@@ -1,19 +1,52 @@
 use super::consts::*;
+use crate::acpi::get_acpi;
+use crate::memory::physical_to_virtual;
 use core::sync::atomic::{AtomicU64, Ordering};
 use x86_64::structures::idt::{InterruptDescriptorTable,InterruptStackFrame};
-use crate::{memory::gdt, proc::switch};
+use crate::{memory::gdt, proc::{switch, ProcessContext}};
 pub unsafe fn register_idt(idt: &mut InterruptDescriptorTable) {
     idt[Interrupts::IrqBase as usize + Irq::Timer as usize]
         .set_handler_fn(process_scheduler_handler)
         .set_stack_index(gdt::TIMER_IST_INDEX);
+    idt[Interrupts::Reschedule as usize]
+        .set_handler_fn(reschedule_handler)
+        .set_stack_index(gdt::TIMER_IST_INDEX);
+    idt[Interrupts::Yield as usize]
+        .set_handler_fn(yield_cpu_handler)
+        .set_stack_index(gdt::TIMER_IST_INDEX);
 }
 as_handler!(process_scheduler);
 
-pub extern "x86-interrupt" fn process_scheduler(_sf: InterruptStackFrame) {//idt负责传递这个参数
-    //FIXME
-    x86_64::instructions::interrupts::without_interrupts(|| {//在中断关闭的状态下继续执行，保证操作的原子性，防止被其他中断打断
-    // do something
-})
+/// Timer interrupt of each CPU's local APIC, switch to the next process
+pub extern "C" fn process_scheduler(context: &mut ProcessContext) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        super::stats::record(Interrupts::IrqBase as u8 + Irq::Timer as u8);
+        inc_counter();
+        switch(context);
+        super::ack();
+    })
+}
+
+as_handler!(reschedule);
+
+/// Sent by another CPU when work is queued for this idle CPU
+pub extern "C" fn reschedule(context: &mut ProcessContext) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        super::stats::record(Interrupts::Reschedule as u8);
+        switch(context);
+        super::ack();
+    })
+}
+
+as_handler!(yield_cpu);
+
+/// Raised by `int` in a process giving up the CPU, the local APIC
+/// did not deliver it so there is no interrupt to acknowledge
+pub extern "C" fn yield_cpu(context: &mut ProcessContext) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        super::stats::record(Interrupts::Yield as u8);
+        switch(context);
+    })
 }
 
 static COUNTER: AtomicU64 = AtomicU64::new(0);
@@ -28,4 +61,107 @@ pub fn read_counter() -> u64 {
 pub fn inc_counter() -> u64 {
     // FIXME: read counter value and increase it
     COUNTER.fetch_add(1, Ordering::Relaxed)//Adds to the current value, returning the previous value.
-}
\ No newline at end of file
+}
+
+// HPET registers, offsets from its base
+const HPET_CAPABILITIES: u64 = 0x000;
+const HPET_CONFIG: u64 = 0x010;
+const HPET_MAIN_COUNTER: u64 = 0x0F0;
+/// Config bit to start the main counter
+const HPET_ENABLE: u64 = 1;
+/// Longest counter period allowed by the specification, in femtoseconds
+const HPET_MAX_PERIOD: u64 = 100_000_000;
+
+/// Polls counted as a millisecond by [`Timeout`] without a HPET
+const POLLS_PER_MS: u64 = 1000;
+
+/// The main counter of the HPET, the only clock with a known frequency
+struct Hpet {
+    /// Virtual address of the registers
+    base: u64,
+    /// Counter ticks per millisecond
+    ticks_per_ms: u64,
+    /// Mask of the counter bits, the counter may only have 32 bits
+    mask: u64,
+}
+
+impl Hpet {
+    #[inline]
+    fn read(&self) -> u64 {
+        unsafe { ((self.base + HPET_MAIN_COUNTER) as *const u64).read_volatile() & self.mask }
+    }
+}
+
+static HPET: spin::Once<Hpet> = spin::Once::new();
+
+/// Start the HPET main counter if ACPI reports one
+///
+/// Without it, [`Timeout`] falls back to counting polls.
+pub fn init() {
+    let info = match get_acpi().hpet {
+        Some(info) => info,
+        None => {
+            warn!("No HPET, timeouts are counted in polls.");
+            return;
+        }
+    };
+
+    let base = physical_to_virtual(info.base_address);
+    let period = unsafe { ((base + HPET_CAPABILITIES) as *const u64).read_volatile() } >> 32;
+    if period == 0 || period > HPET_MAX_PERIOD {
+        warn!("HPET reports an invalid period of {} fs, ignored.", period);
+        return;
+    }
+
+    unsafe {
+        let config = (base + HPET_CONFIG) as *mut u64;
+        config.write_volatile(config.read_volatile() | HPET_ENABLE);
+    }
+
+    let hpet = HPET.call_once(|| Hpet {
+        base,
+        ticks_per_ms: 1_000_000_000_000 / period,
+        mask: if info.counter_64bit { u64::MAX } else { u32::MAX as u64 },
+    });
+    info!("HPET counter enabled at {} kHz.", hpet.ticks_per_ms);
+}
+
+/// A time limit for waiting on a device
+///
+/// It is measured by the HPET, which must be checked at least once in each
+/// wrap around of a 32-bit counter (minutes). Without a HPET, each check
+/// counts as a poll instead.
+pub struct Timeout {
+    start: u64,
+    ticks: u64,
+    polls: u64,
+}
+
+impl Timeout {
+    pub fn new(ms: u64) -> Self {
+        match HPET.get() {
+            Some(hpet) => Self {
+                start: hpet.read(),
+                // a longer wait would be cut by the wrap around
+                ticks: ms.saturating_mul(hpet.ticks_per_ms).min(hpet.mask / 2),
+                polls: 0,
+            },
+            None => Self {
+                start: 0,
+                ticks: ms.saturating_mul(POLLS_PER_MS),
+                polls: 0,
+            },
+        }
+    }
+
+    /// If the time limit has passed
+    pub fn expired(&mut self) -> bool {
+        match HPET.get() {
+            Some(hpet) => (hpet.read().wrapping_sub(self.start) & hpet.mask) > self.ticks,
+            None => {
+                self.polls += 1;
+                self.polls > self.ticks
+            }
+        }
+    }
+}
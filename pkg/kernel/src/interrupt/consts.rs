@@ -27,6 +27,14 @@ pub enum Interrupts {
 
     IrqBase = 0x20,//32
     Syscall = 0x80,
+    /// IPI to run the scheduler on another CPU
+    Reschedule = 0xf0,
+    /// Raised by a process giving up the CPU, not by the local APIC
+    Yield = 0xf1,
+    /// Local APIC vectors, above every IO APIC line and MSI vector
+    ApicError = 0xfe,
+    /// the low 4 bits must be set on older CPUs
+    Spurious = 0xff,
 }
 
 /// https://www.computerhope.com/jargon/i/irq.htm
@@ -43,6 +51,4 @@ pub enum Irq {
     RealTimeClock = 8,
     Ide0 = 14,
     Ide1 = 15,
-    Error = 19,
-    Spurious = 31,
 }
@@ -1,3 +1,4 @@
+use super::consts::Interrupts;
 use crate::memory::*;
 use x86_64::registers::control::Cr2;
 use x86_64::structures::idt::{ InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode };
@@ -16,6 +17,7 @@ pub unsafe fn register_idt(idt: &mut InterruptDescriptorTable) {
 }
 //异常处理函数，作为参数传递
 pub extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+  super::stats::record(Interrupts::DivideError as u8);
   panic!("EXCEPTION: DIVIDE ERROR\n\n{:#?}", stack_frame);
 }
 
@@ -23,6 +25,7 @@ pub extern "x86-interrupt" fn double_fault_handler(
   stack_frame: InterruptStackFrame,
   error_code: u64
 ) -> ! {
+  super::stats::record(Interrupts::DoubleFault as u8);
   panic!("EXCEPTION: DOUBLE FAULT, ERROR_CODE: 0x{:016x}\n\n{:#?}", error_code, stack_frame);
 }
 
@@ -30,6 +33,7 @@ pub extern "x86-interrupt" fn page_fault_handler(
     stack_frame: InterruptStackFrame,//保存cpu在中断发生时的寄存器状态
     err_code: PageFaultErrorCode,
 ) {
+    super::stats::record(Interrupts::PageFault as u8);
     if !crate::proc::handle_page_fault(Cr2::read(), err_code) {//中断处理函数err
         warn!(
             "EXCEPTION: PAGE FAULT, ERROR_CODE: {:?}\n\nTrying to access: {:#x}\n{:#?}",
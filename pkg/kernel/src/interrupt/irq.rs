@@ -0,0 +1,218 @@
+//! Dynamic IRQ registration
+//!
+//! Every IO APIC line `irq` is delivered at vector `IrqBase + irq`, whose IDT
+//! entry dispatches to the handlers registered with [`request_irq`].
+//! A line can be shared by several handlers, which are called in order,
+//! and the EOI is sent after all of them return.
+//!
+//! Vectors from [`MSI_VECTOR_BASE`] are handed out by [`request_msi`]
+//! to devices that signal interrupts with messages (MSI/MSI-X).
+
+use super::consts::*;
+use crate::proc::processor;
+use alloc::vec::Vec;
+use spin::RwLock;
+use x86_64::instructions::interrupts;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+/// Number of lines on a standard IO APIC
+pub const IRQ_COUNT: usize = 24;
+
+/// First vector for message signaled interrupts, after the local APIC vectors
+pub const MSI_VECTOR_BASE: u8 = 0x40;
+/// Number of vectors for message signaled interrupts, up to `Syscall`
+pub const MSI_VECTOR_COUNT: usize = 64;
+
+/// Handler of an IRQ, called with interrupts disabled
+pub type IrqHandler = fn();
+
+struct IrqAction {
+  name: &'static str,
+  handler: IrqHandler,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_LINE: RwLock<Vec<IrqAction>> = RwLock::new(Vec::new());
+
+static ACTIONS: [RwLock<Vec<IrqAction>>; IRQ_COUNT] = [EMPTY_LINE; IRQ_COUNT];
+static MSI_ACTIONS: [RwLock<Vec<IrqAction>>; MSI_VECTOR_COUNT] = [EMPTY_LINE; MSI_VECTOR_COUNT];
+
+extern "x86-interrupt" fn stub<const VECTOR: u8>(_sf: InterruptStackFrame) {
+  dispatch(VECTOR);
+}
+
+macro_rules! register_stubs {
+  ($idt:expr, $base:expr, [$($offset:literal),* $(,)?]) => {
+    $(
+      $idt[$base as usize + $offset].set_handler_fn(stub::<{ $base as u8 + $offset }>);
+    )*
+  };
+}
+
+pub unsafe fn register_idt(idt: &mut InterruptDescriptorTable) {
+  // IRQ 0 is taken by the local APIC timer
+  register_stubs!(idt, Interrupts::IrqBase, [
+    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23,
+  ]);
+  register_stubs!(idt, MSI_VECTOR_BASE, [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+    16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31,
+    32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47,
+    48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63,
+  ]);
+}
+
+/// Whether `irq` can be requested by drivers
+#[inline]
+pub fn is_available(irq: u8) -> bool {
+  (irq as usize) < IRQ_COUNT && irq != Irq::Timer as u8
+}
+
+/// If `vector` is one of the message signaled interrupt vectors
+#[inline]
+pub fn is_msi_vector(vector: u8) -> bool {
+  (MSI_VECTOR_BASE..MSI_VECTOR_BASE + MSI_VECTOR_COUNT as u8).contains(&vector)
+}
+
+/// Handlers of the interrupt at `vector`
+fn actions_of(vector: u8) -> Option<&'static RwLock<Vec<IrqAction>>> {
+  if is_msi_vector(vector) {
+    return Some(&MSI_ACTIONS[(vector - MSI_VECTOR_BASE) as usize]);
+  }
+
+  vector
+    .checked_sub(Interrupts::IrqBase as u8)
+    .filter(|&irq| is_available(irq))
+    .map(|irq| &ACTIONS[irq as usize])
+}
+
+fn dispatch(vector: u8) {
+  super::stats::record(vector);
+
+  if let Some(actions) = actions_of(vector) {
+    let actions = actions.read();
+    if actions.is_empty() {
+      trace!("Unhandled interrupt {:#x}", vector);
+    }
+    for action in actions.iter() {
+      (action.handler)();
+    }
+  }
+
+  super::ack();
+}
+
+/// Register `handler` for `irq`, routing and unmasking the line
+/// on the IO APIC if it is the first handler.
+///
+/// The line is routed to the calling CPU. Lines below 16 are ISA interrupts,
+/// configured as the MADT overrides say, the others are PCI INTx interrupts,
+/// which are level-triggered and active low.
+pub fn request_irq(irq: u8, handler: IrqHandler, name: &'static str) -> Result<(), ()> {
+  if !is_available(irq) {
+    warn!("IRQ {} requested by {} is not available.", irq, name);
+    return Err(());
+  }
+
+  interrupts::without_interrupts(|| {
+    let mut actions = ACTIONS[irq as usize].write();
+    if actions.iter().any(|action| action.name == name) {
+      warn!("IRQ {} is already requested by {}.", irq, name);
+      return Err(());
+    }
+
+    actions.push(IrqAction { name, handler });
+    if actions.len() == 1 {
+      let apic_id = processor::apic_id(processor::current_id());
+      super::enable_irq(irq, apic_id as u8);
+    }
+
+    debug!("IRQ {} requested by {}.", irq, name);
+    Ok(())
+  })
+}
+
+/// Remove the handler registered by `name` from `irq`,
+/// the line is masked when no handler is left.
+pub fn free_irq(irq: u8, name: &'static str) {
+  if !is_available(irq) {
+    return;
+  }
+
+  interrupts::without_interrupts(|| {
+    let mut actions = ACTIONS[irq as usize].write();
+    let len = actions.len();
+    actions.retain(|action| action.name != name);
+
+    if actions.len() == len {
+      warn!("IRQ {} is not requested by {}.", irq, name);
+    } else if actions.is_empty() {
+      super::disable_irq(irq);
+    }
+  })
+}
+
+/// Deliver `irq` to the calling CPU from now on, so a driver that halts
+/// until it arrives is woken by it, not by the next timer tick.
+/// Nothing changes if the line has no handler.
+pub fn route_to_current(irq: u8) {
+  if !is_available(irq) {
+    return;
+  }
+
+  interrupts::without_interrupts(|| {
+    // a concurrent `free_irq` can not mask the line in between
+    let actions = ACTIONS[irq as usize].read();
+    if !actions.is_empty() {
+      let apic_id = processor::apic_id(processor::current_id());
+      super::retarget_irq(irq, apic_id as u8);
+    }
+  })
+}
+
+/// Allocate a free vector for a message signaled interrupt
+/// and register `handler` on it, returns the vector.
+///
+/// The device must be programmed to send the vector by the caller.
+pub fn request_msi(handler: IrqHandler, name: &'static str) -> Option<u8> {
+  interrupts::without_interrupts(|| {
+    for (idx, line) in MSI_ACTIONS.iter().enumerate() {
+      let mut actions = line.write();
+      if actions.is_empty() {
+        actions.push(IrqAction { name, handler });
+        let vector = MSI_VECTOR_BASE + idx as u8;
+        debug!("MSI vector {:#x} requested by {}.", vector, name);
+        return Some(vector);
+      }
+    }
+
+    warn!("No free MSI vector for {}.", name);
+    None
+  })
+}
+
+/// Release a vector allocated by [`request_msi`]
+///
+/// The device should stop sending it before the vector is freed.
+pub fn free_msi(vector: u8) {
+  if !is_msi_vector(vector) {
+    return;
+  }
+
+  interrupts::without_interrupts(|| {
+    MSI_ACTIONS[(vector - MSI_VECTOR_BASE) as usize].write().clear();
+  })
+}
+
+/// Number of interrupts received on `irq`
+#[inline]
+pub fn irq_count(irq: u8) -> u64 {
+  super::stats::total(Interrupts::IrqBase as u8 + irq)
+}
+
+/// Names of the handlers registered at `vector`
+pub fn vector_names(vector: u8) -> Vec<&'static str> {
+  actions_of(vector)
+    .map(|actions| actions.read().iter().map(|action| action.name).collect())
+    .unwrap_or_default()
+}
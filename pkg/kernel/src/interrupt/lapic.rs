@@ -0,0 +1,62 @@
+//! Local APIC error and spurious interrupts
+//!
+//! Reference: Intel SDM Vol. 3A, 11.5.3 Error Handling and 11.9 Spurious Interrupt
+
+use super::apic::LocalApic;
+use super::consts::*;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+bitflags! {
+  /// Bits of the Error Status Register
+  #[derive(Debug, Clone, Copy)]
+  pub struct ApicError: u32 {
+    const SEND_CHECKSUM = 1 << 0;
+    const RECEIVE_CHECKSUM = 1 << 1;
+    const SEND_ACCEPT = 1 << 2;
+    const RECEIVE_ACCEPT = 1 << 3;
+    const REDIRECTABLE_IPI = 1 << 4;
+    const SEND_ILLEGAL_VECTOR = 1 << 5;
+    const RECEIVE_ILLEGAL_VECTOR = 1 << 6;
+    const ILLEGAL_REGISTER = 1 << 7;
+  }
+}
+
+const ERROR_KINDS: usize = 8;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+/// Occurrences of each error bit, on all CPUs
+static ERROR_COUNTS: [AtomicU64; ERROR_KINDS] = [ZERO; ERROR_KINDS];
+
+pub unsafe fn register_idt(idt: &mut InterruptDescriptorTable) {
+  idt[Interrupts::ApicError as usize].set_handler_fn(error_handler);
+  idt[Interrupts::Spurious as usize].set_handler_fn(spurious_handler);
+}
+
+pub extern "x86-interrupt" fn error_handler(_sf: InterruptStackFrame) {
+  super::stats::record(Interrupts::ApicError as u8);
+
+  let mut lapic = super::local_apic();
+  let esr = lapic.error_status();
+  for (bit, count) in ERROR_COUNTS.iter().enumerate() {
+    if esr & (1 << bit) != 0 {
+      count.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+
+  warn!("APIC error on CPU {}: {:?} ({:#x})", lapic.id(), ApicError::from_bits_truncate(esr), esr);
+  lapic.eoi();
+}
+
+/// A spurious interrupt is not in service, so it must not be acknowledged
+pub extern "x86-interrupt" fn spurious_handler(_sf: InterruptStackFrame) {
+  super::stats::record(Interrupts::Spurious as u8);
+}
+
+/// Occurrences of the APIC `error`, which should be a single bit
+pub fn error_count(error: ApicError) -> u64 {
+  let bit = error.bits().trailing_zeros() as usize;
+  ERROR_COUNTS.get(bit).map(|count| count.load(Ordering::Relaxed)).unwrap_or(0)
+}
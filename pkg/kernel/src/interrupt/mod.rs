@@ -1,12 +1,23 @@
-mod apic;
-mod consts;
+pub mod apic;
+pub mod consts;
 pub mod clock;
-mod serial;
+pub mod irq;
+pub mod lapic;
+pub mod stats;
+pub mod syscall;
 mod exceptions;
 
 use apic::*;
+pub use irq::{free_irq, free_msi, request_irq, request_msi, IrqHandler};
+pub use apic::{IOAPIC_ADDR, LAPIC_ADDR};
 use x86_64::structures::idt::InterruptDescriptorTable;
+use crate::acpi::{get_acpi, InterruptOverride, Polarity, TriggerMode};
 use crate::memory::physical_to_virtual;
+use spin::Mutex;
+
+/// APIC ID each enabled IO APIC line is routed to, the lock also
+/// keeps CPUs from programming the IO APICs at the same time
+static IRQ_TARGETS: Mutex<[Option<u8>; irq::IRQ_COUNT]> = Mutex::new([None; irq::IRQ_COUNT]);
 
 
 lazy_static! {
@@ -16,7 +27,9 @@ lazy_static! {
     unsafe {
       exceptions::register_idt(&mut idt); //注册中断描述符表
       clock::register_idt(&mut idt);
-      serial::register_idt(&mut idt);
+      irq::register_idt(&mut idt);
+      lapic::register_idt(&mut idt);
+      syscall::register_idt(&mut idt);
     }
     idt
   };
@@ -27,29 +40,81 @@ pub fn init() {
   IDT.load();
 
   // FIXME: check and init APIC
-  if XApic::support() {
-    let mut lapic = unsafe { XApic::new(physical_to_virtual(LAPIC_ADDR)) };
-    lapic.cpu_init();
-    info!("APIC Initialized.");
-  } else {
-    panic!("APIC not supported!");
+  match Lapic::select() {
+    Some(mode) => {
+      local_apic().cpu_init();
+      info!("APIC Initialized in {} mode.", mode);
+    }
+    None => panic!("APIC not supported!"),
   }
-  // FIXME: enable serial irq with IO APIC (use enable_irq)
-  //enable_irq(consts::Interrupts::IrqBase as u8 + consts::Irq::Keyboard as u8,0);
-  enable_irq(consts::Irq::Serial0 as u8,0);//这里是irq序号不是中断号
-  info!("Serial IRQ Enabled and Interrupts Initialized.");
-  
   info!("Interrupts Initialized.");
 }
 
-#[inline(always)]
-pub fn enable_irq(irq: u8, cpuid: u8) {
-  let mut ioapic = unsafe { IoApic::new(physical_to_virtual(IOAPIC_ADDR)) };
-  ioapic.enable(irq, cpuid);
+/// init interrupts for an application processor
+///
+/// IO APIC interrupts are still routed to the BSP only.
+pub fn init_ap() {
+  IDT.load();
+
+  local_apic().cpu_init();
+}
+
+/// Send a fixed IPI with `vector` to the CPU with `apic_id`
+pub fn send_ipi(apic_id: u32, vector: u8) {
+  const ASSERT: u32 = 1 << 14;
+  local_apic().send_ipi(apic_id, ASSERT | vector as u32);
+}
+
+/// Get the local APIC of the current CPU
+#[inline]
+pub fn local_apic() -> Lapic {
+  unsafe { Lapic::new(physical_to_virtual(get_acpi().lapic_addr())) }
+}
+
+/// Find the IO APIC and its input pin for `irq`, honoring MADT overrides
+fn io_apic_pin(irq: u8) -> (IoApic, u8, InterruptOverride) {
+  let acpi = get_acpi();
+  let route = acpi.irq_route(irq);
+  let io = acpi.io_apic_for(route.gsi).expect("No IO APIC handles this IRQ");
+
+  let ioapic = unsafe { IoApic::new(physical_to_virtual(io.address)) };
+  (ioapic, (route.gsi - io.gsi_base) as u8, route)
+}
+
+/// Route `irq` to vector `IrqBase + irq` on the CPU with `apic_id`,
+/// with the trigger mode and polarity of the line
+///
+/// Drivers should use [`request_irq`] instead.
+pub(crate) fn enable_irq(irq: u8, apic_id: u8) {
+  let mut targets = IRQ_TARGETS.lock();
+  let (mut ioapic, pin, route) = io_apic_pin(irq);
+  ioapic.route(
+    pin,
+    consts::Interrupts::IrqBase as u8 + irq,
+    route.trigger == TriggerMode::Level,
+    route.polarity == Polarity::ActiveLow,
+    apic_id,
+  );
+  targets[irq as usize] = Some(apic_id);
+}
+
+/// Move `irq` to the CPU with `apic_id` if it is enabled on another one
+pub(crate) fn retarget_irq(irq: u8, apic_id: u8) {
+  let target = IRQ_TARGETS.lock()[irq as usize];
+  if target.is_some_and(|target| target != apic_id) {
+    enable_irq(irq, apic_id);
+  }
+}
+
+/// Mask `irq` on its IO APIC
+pub(crate) fn disable_irq(irq: u8) {
+  let mut targets = IRQ_TARGETS.lock();
+  let (mut ioapic, pin, _) = io_apic_pin(irq);
+  ioapic.disable(pin, 0);
+  targets[irq as usize] = None;
 }
 
 #[inline(always)]
 pub fn ack() {
-  let mut lapic = unsafe { XApic::new(physical_to_virtual(LAPIC_ADDR)) };
-  lapic.eoi();
+  local_apic().eoi();
 }
@@ -0,0 +1,93 @@
+//! Interrupt statistics
+//!
+//! Every handler calls [`record`] with its vector, the counters are kept
+//! per vector and per CPU, and [`print_interrupts`] formats them like
+//! Linux's `/proc/interrupts`, for the kernel shell and [`Syscall::Interrupts`].
+//!
+//! [`Syscall::Interrupts`]: super::syscall::Syscall::Interrupts
+
+use super::consts::*;
+use super::irq;
+use crate::proc::processor::{self, MAX_CPU_COUNT};
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+const VECTOR_COUNT: usize = 256;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const ZEROS: [AtomicU64; MAX_CPU_COUNT] = [ZERO; MAX_CPU_COUNT];
+
+static COUNTS: [[AtomicU64; MAX_CPU_COUNT]; VECTOR_COUNT] = [ZEROS; VECTOR_COUNT];
+
+/// Count an interrupt on `vector` for the current CPU
+#[inline]
+pub fn record(vector: u8) {
+  COUNTS[vector as usize][processor::current_id()].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Interrupts received on `vector` by `cpuid`
+#[inline]
+pub fn count(vector: u8, cpuid: usize) -> u64 {
+  COUNTS[vector as usize][cpuid].load(Ordering::Relaxed)
+}
+
+/// Interrupts received on `vector` by all CPUs
+pub fn total(vector: u8) -> u64 {
+  COUNTS[vector as usize].iter().map(|count| count.load(Ordering::Relaxed)).sum()
+}
+
+/// IRQ line delivered at `vector`, if any
+fn vector_irq(vector: u8) -> Option<u8> {
+  vector
+    .checked_sub(Interrupts::IrqBase as u8)
+    .filter(|&irq| irq::is_available(irq))
+}
+
+/// Name of the handler for `vector`
+fn vector_name(vector: u8) -> String {
+  let name = match vector {
+    0 => "Divide error",
+    8 => "Double fault",
+    14 => "Page fault",
+    v if v == Interrupts::IrqBase as u8 + Irq::Timer as u8 => "LAPIC timer",
+    v if v == Interrupts::ApicError as u8 => "APIC error",
+    v if v == Interrupts::Spurious as u8 => "Spurious interrupt",
+    v if v == Interrupts::Syscall as u8 => "System call",
+    v if v == Interrupts::Reschedule as u8 => "Rescheduling IPI",
+    v if v == Interrupts::Yield as u8 => "Yield",
+    v => return irq::vector_names(v).join(", "),
+  };
+  name.into()
+}
+
+/// Format the counters of every vector that has fired or has a handler
+pub fn print_interrupts() -> String {
+  let cpus = processor::online_mask();
+  let mut output = String::from(" VEC  IRQ");
+  for cpuid in (0..MAX_CPU_COUNT).filter(|cpuid| cpus & (1 << cpuid) != 0) {
+    write!(output, " {:>10}", alloc::format!("CPU{}", cpuid)).unwrap();
+  }
+  output += "  NAME\n";
+
+  for vector in 0..=u8::MAX {
+    if total(vector) == 0 && irq::vector_names(vector).is_empty() {
+      continue;
+    }
+
+    write!(output, "{:#04x}", vector).unwrap();
+    match vector_irq(vector) {
+      Some(irq) => write!(output, " {:>4}", irq).unwrap(),
+      None if irq::is_msi_vector(vector) => output += "  MSI",
+      None => output += "    -",
+    }
+    for cpuid in (0..MAX_CPU_COUNT).filter(|cpuid| cpus & (1 << cpuid) != 0) {
+      write!(output, " {:>10}", count(vector, cpuid)).unwrap();
+    }
+    writeln!(output, "  {}", vector_name(vector)).unwrap();
+  }
+
+  output
+}
@@ -0,0 +1,109 @@
+//! System calls
+//!
+//! A process raises `int 0x80` with the number of the call in `rax` and its
+//! arguments in `rdi`, `rsi` and `rdx`, the result is returned in `rax`.
+//! Every process runs in ring 0 for now, so buffers are used as given.
+
+use super::consts::*;
+use crate::proc::ProcessContext;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::PrivilegeLevel;
+
+/// Returned for an unknown call or invalid arguments
+pub const SYSCALL_ERROR: usize = usize::MAX;
+
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syscall {
+  /// Copy the report of [`super::stats::print_interrupts`] to the buffer
+  /// at `rdi` of `rsi` bytes, returns the length of the whole report
+  Interrupts = 0,
+}
+
+impl Syscall {
+  fn from_number(number: usize) -> Option<Self> {
+    match number {
+      0 => Some(Syscall::Interrupts),
+      _ => None,
+    }
+  }
+}
+
+pub unsafe fn register_idt(idt: &mut InterruptDescriptorTable) {
+  idt[Interrupts::Syscall as usize]
+    .set_handler_fn(syscall_handler)
+    .set_privilege_level(PrivilegeLevel::Ring3);
+}
+
+as_handler!(syscall);
+
+pub extern "C" fn syscall(context: &mut ProcessContext) {
+  super::stats::record(Interrupts::Syscall as u8);
+  let regs = context.regs;
+  context.set_rax(dispatch(regs.rax, regs.rdi, regs.rsi, regs.rdx));
+}
+
+fn dispatch(number: usize, arg0: usize, arg1: usize, _arg2: usize) -> usize {
+  match Syscall::from_number(number) {
+    Some(Syscall::Interrupts) => {
+      let report = super::stats::print_interrupts();
+      match user_buffer(arg0, arg1) {
+        Some(buf) => {
+          let len = buf.len().min(report.len());
+          buf[..len].copy_from_slice(&report.as_bytes()[..len]);
+          report.len()
+        }
+        None => SYSCALL_ERROR,
+      }
+    }
+    None => {
+      warn!("Unknown syscall {}", number);
+      SYSCALL_ERROR
+    }
+  }
+}
+
+/// The buffer of `len` bytes at `addr`, which may be null if it is empty
+fn user_buffer<'a>(addr: usize, len: usize) -> Option<&'a mut [u8]> {
+  if len == 0 {
+    return Some(&mut []);
+  }
+  if addr == 0 || addr.checked_add(len).is_none() {
+    return None;
+  }
+  Some(unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len) })
+}
+
+/// Make the system call `call`, returns what it returns in `rax`
+pub fn syscall3(call: Syscall, arg0: usize, arg1: usize, arg2: usize) -> usize {
+  let ret: usize;
+  unsafe {
+    core::arch::asm!(
+      "int {vector}",
+      vector = const Interrupts::Syscall as u8,
+      inlateout("rax") call as usize => ret,
+      in("rdi") arg0,
+      in("rsi") arg1,
+      in("rdx") arg2,
+    );
+  }
+  ret
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn interrupts_report() {
+    let mut buf = [0u8; 8];
+    let len = dispatch(Syscall::Interrupts as usize, buf.as_mut_ptr() as usize, buf.len(), 0);
+    assert_eq!(len, super::super::stats::print_interrupts().len());
+    assert_eq!(&buf, b" VEC  IR");
+
+    // the length alone, to size the buffer
+    assert_eq!(dispatch(Syscall::Interrupts as usize, 0, 0, 0), len);
+    assert_eq!(dispatch(Syscall::Interrupts as usize, 0, 8, 0), SYSCALL_ERROR);
+    assert_eq!(dispatch(1, 0, 0, 0), SYSCALL_ERROR);
+  }
+}
@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![allow(dead_code)]
 #![feature(naked_functions)]
 #![feature(abi_x86_interrupt)]
@@ -26,10 +26,13 @@ pub use utils::*;
 pub mod drivers;
 pub use drivers::*;
 
+pub mod acpi;
 pub mod memory;
 pub mod interrupt;
 
 pub mod proc;
+pub mod smp;
+pub mod fs;
 
 pub use alloc::format;
 use boot::BootInfo;
@@ -37,12 +40,25 @@ use boot::BootInfo;
 pub fn init(boot_info: &'static BootInfo) {
   serial::init(); // init serial output
   logger::init(); // init logger system
+  utils::init_boot_args(boot_info); // keep the command line
   memory::address::init(boot_info);
+  drivers::console::init(boot_info); // init framebuffer console
   memory::gdt::init(); // init gdt
+  proc::processor::init_bsp(); // number the BSP through its GS base
   memory::allocator::init(); // init kernel heap allocator
-  proc::init(); // init process manager
+  acpi::init(boot_info); // parse ACPI tables
+  proc::init(boot_info); // init process manager
   interrupt::init(); // init interrupts
+  interrupt::clock::init(); // start the HPET counter
+  serial::init_irq(); // receive serial input by interrupt
   memory::init(boot_info); // init memory manager
+  proc::init_idle(); // idle process of the BSP
+  fs::initramfs::init(boot_info); // unpack initramfs
+  pci::init(); // enumerate PCI devices
+  block::ata::init(); // probe IDE disks
+  block::virtio_blk::init(); // probe virtio disks
+  fs::init(); // mount filesystems
+  smp::init(boot_info); // start application processors
   x86_64::instructions::interrupts::enable(); //enable interrupts
   info!("Interrupts Enabled.");
 
@@ -51,6 +67,10 @@ pub fn init(boot_info: &'static BootInfo) {
 
 pub fn shutdown(boot_info: &'static BootInfo) -> ! {
   info!("YatSenOS shutting down.");
+  // FAT volumes keep their changes in a write-back cache
+  if let Err(err) = fs::mount::sync_all() {
+    warn!("Failed to sync filesystems: {:?}", err);
+  }
   unsafe {
     boot_info.system_table
       .runtime_services()
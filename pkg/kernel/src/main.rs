@@ -14,9 +14,19 @@ pub fn kernel_main(boot_info: &'static boot::BootInfo) -> ! {
     loop {
         print!("> ");
         let input = input::get_line();
+        let (cmd, arg) = input.trim().split_once(' ').unwrap_or((input.trim(), ""));
 
-        match input.trim() {
+        match cmd {
             "exit" => break,
+            "interrupts" => print!("{}", interrupt::stats::print_interrupts()),
+            "ls" => match fs::list_dir(arg) {
+                Ok(list) => print!("{}", list),
+                Err(err) => println!("ls: {}: {:?}", arg, err),
+            },
+            "cat" => match fs::read_to_vec(arg) {
+                Ok(data) => println!("{}", alloc::string::String::from_utf8_lossy(&data)),
+                Err(err) => println!("cat: {}: {:?}", arg, err),
+            },
             _ => {
                 println!("You said: {}", input);
                 println!("The counter value is {}", interrupt::clock::read_counter());
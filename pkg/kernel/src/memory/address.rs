@@ -7,6 +7,7 @@ pub fn init(boot_info: &'static boot::BootInfo) {
     PHYSICAL_OFFSET.call_once(|| boot_info.physical_memory_offset);
 
     info!("Physical Offset  : {:#x}", PHYSICAL_OFFSET.get().unwrap());
+    info!("Kernel Offset    : {:#x}", boot_info.kernel_offset);
 }
 
 /// Convert a virtual address to a physical address.
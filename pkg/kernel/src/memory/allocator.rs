@@ -4,7 +4,7 @@ use x86_64::VirtAddr;
 pub const HEAP_SIZE: usize = 8 * 1024 * 1024; // 8 MiB
 
 /// Use linked_list_allocator for kernel heap
-#[global_allocator]
+#[cfg_attr(not(test), global_allocator)]
 pub static ALLOCATOR: LockedHeap = LockedHeap::empty();
 
 pub fn init() {
@@ -31,7 +31,7 @@ pub fn init() {
     info!("Kernel Heap Initialized.");
 }
 
-#[alloc_error_handler]
+#[cfg_attr(not(test), alloc_error_handler)]
 fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
     panic!("Allocation error: {:?}", layout);
 }
@@ -38,6 +38,25 @@ impl BootInfoFrameAllocator {
     pub fn frames_total(&self) -> usize {
         self.size
     }
+
+    /// Allocate `count` physically contiguous frames
+    ///
+    /// Frames skipped at the end of a memory region are lost,
+    /// as they can not be freed yet.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame> {
+        let mut start = self.allocate_frame()?;
+        let mut len = 1;
+        while len < count {
+            let frame = self.allocate_frame()?;
+            if frame == start + len as u64 {
+                len += 1;
+            } else {
+                start = frame;
+                len = 1;
+            }
+        }
+        Some(start)
+    }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
@@ -1,4 +1,6 @@
 //gdt.rs：定义 TSS 和 GDT，为内核提供内存段描述符和任务状态段。
+use alloc::boxed::Box;
+use alloc::vec;
 use lazy_static::lazy_static;
 use x86_64::registers::segmentation::Segment;
 use x86_64::structures::gdt::{ Descriptor, GlobalDescriptorTable, SegmentSelector };
@@ -9,7 +11,8 @@ pub const DOUBLE_FAULT_IST_INDEX: u16 = 0; //interupt stack table
 pub const PAGE_FAULT_IST_INDEX: u16 = 1;
 pub const TIMER_IST_INDEX :u16 =2;
 
-pub const IST_SIZES: [usize; 4] = [0x1000, 0x1000, 0x1000, 0x1000]; //常量数组，表示内存块的大小
+// the page fault handler grows stacks, mapping pages and logging on its stack
+pub const IST_SIZES: [usize; 4] = [0x1000, 0x1000, 0x4000, 0x1000]; //常量数组，表示内存块的大小
 
 /* pub struct TaskStateSegment {
     reserved_1: u32,
@@ -97,20 +100,7 @@ pub struct KernelSelectors {
 }
 
 pub fn init() {
-  use x86_64::instructions::segmentation::{ CS, DS, ES, FS, GS, SS };
-  use x86_64::instructions::tables::load_tss;
-  use x86_64::PrivilegeLevel;
-
-  GDT.0.load();
-  unsafe {
-    CS::set_reg(GDT.1.code_selector);
-    DS::set_reg(GDT.1.data_selector);
-    SS::set_reg(SegmentSelector::new(0, PrivilegeLevel::Ring0));
-    ES::set_reg(SegmentSelector::new(0, PrivilegeLevel::Ring0));
-    FS::set_reg(SegmentSelector::new(0, PrivilegeLevel::Ring0));
-    GS::set_reg(SegmentSelector::new(0, PrivilegeLevel::Ring0));
-    load_tss(GDT.1.tss_selector);
-  }
+  load(&GDT.0, &GDT.1);
 
   let mut size = 0;
 
@@ -124,6 +114,52 @@ pub fn init() {
   info!("GDT Initialized.");
 }
 
+/// Build and load the GDT and TSS for an application processor
+///
+/// Each CPU needs its own TSS, as the IST stacks can not be shared.
+/// The entries are added in the same order as the BSP's,
+/// so the selectors from `get_selector` are valid on all CPUs.
+pub fn init_ap() {
+  let tss = Box::leak(Box::new(TaskStateSegment::new()));
+  tss.privilege_stack_table[0] = alloc_stack(IST_SIZES[0]);
+  tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = alloc_stack(IST_SIZES[1]);
+  tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = alloc_stack(IST_SIZES[2]);
+  tss.interrupt_stack_table[TIMER_IST_INDEX as usize] = alloc_stack(IST_SIZES[3]);
+  let tss: &'static TaskStateSegment = tss;
+
+  let gdt = Box::leak(Box::new(GlobalDescriptorTable::new()));
+  let selectors = KernelSelectors {
+    code_selector: gdt.add_entry(Descriptor::kernel_code_segment()),
+    data_selector: gdt.add_entry(Descriptor::kernel_data_segment()),
+    tss_selector: gdt.add_entry(Descriptor::tss_segment(tss)),
+  };
+
+  load(gdt, &selectors);
+}
+
+fn load(gdt: &'static GlobalDescriptorTable, selectors: &KernelSelectors) {
+  use x86_64::instructions::segmentation::{ CS, DS, ES, FS, GS, SS };
+  use x86_64::instructions::tables::load_tss;
+  use x86_64::PrivilegeLevel;
+
+  gdt.load();
+  unsafe {
+    CS::set_reg(selectors.code_selector);
+    DS::set_reg(selectors.data_selector);
+    SS::set_reg(SegmentSelector::new(0, PrivilegeLevel::Ring0));
+    ES::set_reg(SegmentSelector::new(0, PrivilegeLevel::Ring0));
+    FS::set_reg(SegmentSelector::new(0, PrivilegeLevel::Ring0));
+    GS::set_reg(SegmentSelector::new(0, PrivilegeLevel::Ring0));
+    load_tss(selectors.tss_selector);
+  }
+}
+
+/// Allocate a stack on the kernel heap, return its top
+fn alloc_stack(size: usize) -> VirtAddr {
+  let stack = Box::leak(vec![0u8; size].into_boxed_slice());
+  VirtAddr::from_ptr(stack.as_ptr()) + size
+}
+
 pub fn get_selector() -> &'static KernelSelectors {
   &GDT.1
 }
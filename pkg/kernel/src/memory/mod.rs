@@ -8,6 +8,9 @@ pub use address::*;
 pub use frames::*;
 
 use crate::humanized_size;
+use crate::proc::PageTableContext;
+use x86_64::structures::paging::{Mapper, Page, PageTableFlags, PhysFrame, Size4KiB, Translate};
+use x86_64::{PhysAddr, VirtAddr};
 
 pub fn init(boot_info: &'static boot::BootInfo) {
     let memory_map = &boot_info.memory_map;
@@ -37,3 +40,44 @@ pub fn init(boot_info: &'static boot::BootInfo) {
 
     info!("Frame Allocator initialized.");
 }
+
+/// Map the MMIO range `[addr, addr + size)` uncached at its physical offset
+/// address in the current page table, returns the virtual address.
+///
+/// Only the pages beyond the boot mapping are mapped, and page tables
+/// cloned afterwards share the mapping.
+pub fn map_mmio(addr: u64, size: u64) -> u64 {
+    let offset = physical_to_virtual(0);
+    let start = Page::<Size4KiB>::containing_address(VirtAddr::new(addr + offset));
+    let end = Page::containing_address(VirtAddr::new(addr + size.max(1) - 1 + offset));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+
+    let mut mapper = PageTableContext::new().mapper();
+    let mut frame_alloc = get_frame_alloc_for_sure();
+    for page in Page::range_inclusive(start, end) {
+        if mapper.translate_addr(page.start_address()).is_some() {
+            continue;
+        }
+
+        let frame = PhysFrame::containing_address(PhysAddr::new(page.start_address().as_u64() - offset));
+        unsafe {
+            mapper
+                .map_to(page, frame, flags, &mut *frame_alloc)
+                .expect("Failed to map MMIO range")
+                .flush();
+        }
+    }
+
+    addr + offset
+}
+
+/// Allocate `pages` zeroed, physically contiguous pages for device DMA,
+/// returns the physical address.
+pub fn alloc_dma(pages: usize) -> Option<u64> {
+    let frame = get_frame_alloc_for_sure().allocate_contiguous(pages)?;
+    let addr = frame.start_address().as_u64();
+    unsafe {
+        core::ptr::write_bytes(physical_to_virtual(addr) as *mut u8, 0, pages * PAGE_SIZE as usize);
+    }
+    Some(addr)
+}
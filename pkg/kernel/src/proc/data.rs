@@ -7,6 +7,7 @@ use x86_64::structures::paging::{
 };
 
 use super::*;
+use super::fd::FdTable;
 
 #[derive(Debug, Clone)]
 pub struct ProcessData {
@@ -14,14 +15,18 @@ pub struct ProcessData {
     pub(super) env: Arc<RwLock<BTreeMap<String, String>>>,//线程安全的引用计数和读写保护，键值对映射的方式存储环境变量
 
     // process specific data
-    pub(super) stack_segment: Option<PageRange>//线程栈段的页面
+    pub(super) stack_segment: Option<PageRange>,//线程栈段的页面
+    pub(super) stack_max_pages: u64,//栈最多能增长到的页数，从栈顶算起
+    pub(super) fd_table: FdTable,//打开的文件，0/1/2 默认为控制台
 }
 
 impl Default for ProcessData {
     fn default() -> Self {
         Self {
             env: Arc::new(RwLock::new(BTreeMap::new())),
-            stack_segment: None
+            stack_segment: None,
+            stack_max_pages: STACK_MAX_PAGES,
+            fd_table: FdTable::with_stdio(),
         }
     }
 }
@@ -39,15 +44,52 @@ impl ProcessData {
         self.env.write().insert(key.into(), val.into());
     }
 
+    /// All environment variables, sorted by key
+    pub fn envs(&self) -> BTreeMap<String, String> {
+        self.env.read().clone()
+    }
+
+    #[inline]
+    pub fn stack(&self) -> Option<PageRange> {
+        self.stack_segment
+    }
+
+    /// Data of a process spawned by this one, with the inheritable descriptors
+    pub fn inherit(&self) -> Self {
+        Self {
+            fd_table: self.fd_table.inherit(),
+            ..Self::default()
+        }
+    }
+
+    #[inline]
+    pub fn fd_table(&self) -> &FdTable {
+        &self.fd_table
+    }
+
+    #[inline]
+    pub fn fd_table_mut(&mut self) -> &mut FdTable {
+        &mut self.fd_table
+    }
+
     pub fn set_stack(&mut self, start: VirtAddr, size: u64) {
         let start = Page::containing_address(start);//分配一个页面
         self.stack_segment = Some(Page::range(start, start + size));
     }
 
+    /// Limit how far the stack may grow, in pages counted from its top
+    pub fn set_stack_max(&mut self, pages: u64) {
+        self.stack_max_pages = pages;
+    }
+
     pub fn is_on_stack(&self, addr: VirtAddr) -> bool {//检查地址是否在栈上
-        // FIXME: check if the address is on the stack
-        // 未实现
-        let masked_addr =addr.as_u64() & STACK_START_MASK;
-        false
+        // the stack can grow down to `stack_max_pages` below its top,
+        // wherever it was placed, addresses already mapped never fault here
+        self.stack_segment.is_some_and(|stack| {
+            let stack_start = stack.start.start_address().as_u64();
+            let stack_top = stack.end.start_address().as_u64();
+            let limit = stack_top.saturating_sub(self.stack_max_pages * PAGE_SIZE);
+            (limit..stack_start).contains(&addr.as_u64())
+        })
     }
 }
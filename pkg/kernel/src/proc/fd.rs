@@ -0,0 +1,276 @@
+//! File descriptor table of a process
+//!
+//! A descriptor refers to a shared [`Resource`], `dup` and inheritance make
+//! several descriptors refer to the same one, with the same file offset.
+//! A resource is released when its last reference is dropped, so closing
+//! returns it to be dropped after the process lock is released.
+
+use super::pipe::{PipeReader, PipeWriter};
+use crate::drivers::input;
+use crate::fs::{File, FsError, FsResult, SeekFrom};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+
+pub const STDIN: usize = 0;
+pub const STDOUT: usize = 1;
+pub const STDERR: usize = 2;
+
+/// Most descriptors a process may have open
+pub const MAX_FDS: usize = 256;
+
+/// What a file descriptor refers to
+pub enum Resource {
+    /// Serial input, output to the serial port and the framebuffer
+    Console,
+    File(Arc<File>),
+    PipeRead(PipeReader),
+    PipeWrite(PipeWriter),
+}
+
+impl Resource {
+    /// Read into `buf`, blocking until some data is available
+    pub fn read(&self, buf: &mut [u8]) -> FsResult<usize> {
+        match self {
+            Resource::Console => {
+                if buf.is_empty() {
+                    return Ok(0);
+                }
+
+                // block for the first key, then take what is already there
+                let mut len = 0;
+                let mut key = Some(input::wait_key());
+                while let Some(k) = key {
+                    buf[len] = if k == b'\r' { b'\n' } else { k };
+                    len += 1;
+                    if len == buf.len() {
+                        break;
+                    }
+                    key = input::try_pop_key();
+                }
+                Ok(len)
+            }
+            Resource::File(file) => file.read(buf),
+            Resource::PipeRead(pipe) => pipe.read(buf),
+            Resource::PipeWrite(_) => Err(FsError::PermissionDenied),
+        }
+    }
+
+    pub fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        match self {
+            Resource::Console => {
+                print!("{}", String::from_utf8_lossy(buf));
+                Ok(buf.len())
+            }
+            Resource::File(file) => file.write(buf),
+            Resource::PipeWrite(pipe) => pipe.write(buf),
+            Resource::PipeRead(_) => Err(FsError::PermissionDenied),
+        }
+    }
+
+    pub fn seek(&self, pos: SeekFrom) -> FsResult<u64> {
+        match self {
+            Resource::File(file) => file.seek(pos),
+            _ => Err(FsError::NotSupported),
+        }
+    }
+}
+
+impl core::fmt::Debug for Resource {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Resource::Console => write!(f, "Console"),
+            Resource::File(file) => write!(f, "File({})", file.path()),
+            Resource::PipeRead(_) => write!(f, "PipeRead"),
+            Resource::PipeWrite(_) => write!(f, "PipeWrite"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct FdEntry {
+    resource: Arc<Resource>,
+    /// Not inherited by spawned processes
+    cloexec: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FdTable {
+    fds: BTreeMap<usize, FdEntry>,
+}
+
+impl FdTable {
+    /// Table with stdin, stdout and stderr on the console
+    pub fn with_stdio() -> Self {
+        let console = Arc::new(Resource::Console);
+        let mut table = Self::default();
+        for fd in [STDIN, STDOUT, STDERR] {
+            table.fds.insert(
+                fd,
+                FdEntry {
+                    resource: console.clone(),
+                    cloexec: false,
+                },
+            );
+        }
+        table
+    }
+
+    pub fn get(&self, fd: usize) -> FsResult<Arc<Resource>> {
+        self.fds
+            .get(&fd)
+            .map(|e| e.resource.clone())
+            .ok_or(FsError::BadDescriptor)
+    }
+
+    /// Lowest free descriptor
+    fn free_fd(&self) -> FsResult<usize> {
+        (0..MAX_FDS)
+            .find(|fd| !self.fds.contains_key(fd))
+            .ok_or(FsError::TooManyFiles)
+    }
+
+    /// Add `resource` at the lowest free descriptor
+    pub fn insert(&mut self, resource: Arc<Resource>, cloexec: bool) -> FsResult<usize> {
+        let fd = self.free_fd()?;
+        self.fds.insert(fd, FdEntry { resource, cloexec });
+        Ok(fd)
+    }
+
+    /// Remove `fd`, returns what it referred to
+    pub fn close(&mut self, fd: usize) -> FsResult<Arc<Resource>> {
+        let entry = self.fds.remove(&fd).ok_or(FsError::BadDescriptor)?;
+        Ok(entry.resource)
+    }
+
+    /// Copy `fd` to the lowest free descriptor, without close-on-exec
+    pub fn dup(&mut self, fd: usize) -> FsResult<usize> {
+        let resource = self.get(fd)?;
+        self.insert(resource, false)
+    }
+
+    /// Copy `old` to `new`, returns what `new` referred to before
+    pub fn dup2(&mut self, old: usize, new: usize) -> FsResult<Option<Arc<Resource>>> {
+        let resource = self.get(old)?;
+        if new >= MAX_FDS {
+            return Err(FsError::BadDescriptor);
+        }
+        if old == new {
+            return Ok(None);
+        }
+
+        let replaced = self.fds.insert(new, FdEntry { resource, cloexec: false });
+        Ok(replaced.map(|e| e.resource))
+    }
+
+    pub fn set_cloexec(&mut self, fd: usize, cloexec: bool) -> FsResult {
+        let entry = self.fds.get_mut(&fd).ok_or(FsError::BadDescriptor)?;
+        entry.cloexec = cloexec;
+        Ok(())
+    }
+
+    /// Table of a spawned process, without the close-on-exec descriptors
+    pub fn inherit(&self) -> Self {
+        Self {
+            fds: self
+                .fds
+                .iter()
+                .filter(|(_, e)| !e.cloexec)
+                .map(|(&fd, e)| (fd, e.clone()))
+                .collect(),
+        }
+    }
+
+    /// Close every descriptor, returns what they referred to
+    pub fn take_all(&mut self) -> alloc::vec::Vec<Arc<Resource>> {
+        core::mem::take(&mut self.fds)
+            .into_values()
+            .map(|e| e.resource)
+            .collect()
+    }
+
+    /// Open descriptors and what they refer to
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Arc<Resource>)> {
+        self.fds.iter().map(|(&fd, e)| (fd, &e.resource))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn console() -> Arc<Resource> {
+        Arc::new(Resource::Console)
+    }
+
+    fn same(table: &FdTable, fd: usize, resource: &Arc<Resource>) -> bool {
+        table.get(fd).is_ok_and(|r| Arc::ptr_eq(&r, resource))
+    }
+
+    #[test]
+    fn dup() {
+        let mut table = FdTable::with_stdio();
+        let res = console();
+        assert_eq!(table.insert(res.clone(), false), Ok(3));
+        assert_eq!(table.dup(3), Ok(4));
+        assert!(same(&table, 4, &res));
+
+        // the lowest free descriptor is reused
+        assert!(table.close(STDIN).is_ok());
+        assert_eq!(table.dup(4), Ok(STDIN));
+        assert!(same(&table, STDIN, &res));
+        assert_eq!(table.dup(5).err(), Some(FsError::BadDescriptor));
+        assert_eq!(table.close(5).err(), Some(FsError::BadDescriptor));
+
+        while table.dup(3).is_ok() {}
+        assert_eq!(table.iter().count(), MAX_FDS);
+        assert_eq!(table.dup(3).err(), Some(FsError::TooManyFiles));
+        assert_eq!(table.take_all().len(), MAX_FDS);
+        assert_eq!(table.get(3).err(), Some(FsError::BadDescriptor));
+    }
+
+    #[test]
+    fn dup2() {
+        let mut table = FdTable::with_stdio();
+        let res = console();
+        let fd = table.insert(res.clone(), true).unwrap();
+
+        let replaced = table.dup2(fd, STDOUT).unwrap().unwrap();
+        assert!(!Arc::ptr_eq(&replaced, &res));
+        assert!(same(&table, STDOUT, &res));
+        // not replacing anything, nor when both are the same
+        assert!(table.dup2(fd, 10).unwrap().is_none());
+        assert!(same(&table, 10, &res));
+        assert!(table.dup2(fd, fd).unwrap().is_none());
+        assert!(same(&table, fd, &res));
+
+        assert_eq!(table.dup2(fd, MAX_FDS).err(), Some(FsError::BadDescriptor));
+        assert_eq!(table.dup2(11, STDIN).err(), Some(FsError::BadDescriptor));
+        assert!(!same(&table, STDIN, &res));
+    }
+
+    #[test]
+    fn inherit() {
+        let mut table = FdTable::with_stdio();
+        let res = console();
+        let private = table.insert(res.clone(), true).unwrap();
+        let shared = table.insert(res.clone(), false).unwrap();
+        table.set_cloexec(STDERR, true).unwrap();
+        assert_eq!(table.set_cloexec(100, true).err(), Some(FsError::BadDescriptor));
+
+        // copies do not keep close-on-exec
+        let copy = table.dup(private).unwrap();
+        table.dup2(private, 20).unwrap();
+
+        let child = table.inherit();
+        let fds: alloc::vec::Vec<_> = child.iter().map(|(fd, _)| fd).collect();
+        assert_eq!(fds, [STDIN, STDOUT, shared, copy, 20]);
+        assert!(same(&child, shared, &res));
+        assert_eq!(Arc::strong_count(&res), 1 + 4 + 3);
+
+        table.set_cloexec(private, false).unwrap();
+        assert!(table.inherit().get(private).is_ok());
+        drop((table, child));
+        assert_eq!(Arc::strong_count(&res), 1);
+    }
+}
@@ -1,14 +1,16 @@
 use core::ops::DerefMut;
 
 //进程管理
-use super::processor;
+use super::processor::{self, MAX_CPU_COUNT};
 use super::*;
 use crate::memory::{
     self,
     allocator::{ALLOCATOR, HEAP_SIZE},
     get_frame_alloc_for_sure, PAGE_SIZE,
 };
+use crate::interrupt::consts::Interrupts;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use alloc::{collections::*, format};
 use spin::{Mutex, RwLock};
 pub static PROCESS_MANAGER: spin::Once<ProcessManager> = spin::Once::new();
@@ -20,6 +22,7 @@ pub fn init(init: Arc<Process>) {
     // FIXME: set processor's current pid to init's pid
     // 处理器是单核的，因此同一时刻只有一个pid
     processor::set_pid(init.pid());
+    processor::set_online();
     //let process_ref = Arc::as_ref(&init);
     //let pid = process_ref.pid();
     //processor::set_pid(pid);
@@ -27,22 +30,67 @@ pub fn init(init: Arc<Process>) {
     PROCESS_MANAGER.call_once(|| ProcessManager::new(init));//进程管理器只初始化一次
 }
 
+/// Register the idle process of an application processor as its current process
+///
+/// it runs on the stack the AP was started with, so it has no stack segment.
+/// The idle process is pinned to its CPU and never put into run queues.
+pub fn init_ap(name: String) -> ProcessId {
+    let manager = get_process_manager();
+    let kproc = manager.get_proc(&KERNEL_PID).unwrap();
+    let idle = Process::new(name, Some(Arc::downgrade(&kproc)), PageTableContext::new(), None);
+    let pid = idle.pid();
+    let cpuid = processor::current_id();
+
+    {
+        let mut inner = idle.write();
+        inner.set_affinity(1 << cpuid);
+        inner.resume();
+    }
+    processor::set_pid(pid);
+    processor::get(cpuid).set_idle(pid);
+    manager.add_proc(pid, idle);
+    processor::set_online();
+    pid
+}
+
+/// Create the idle process of the bootstrap processor
+///
+/// unlike on APs, the boot stack of the BSP belongs to the kernel process,
+/// so this idle process is a kernel thread with a stack of its own.
+pub fn init_bsp_idle(entry: VirtAddr) -> ProcessId {
+    let manager = get_process_manager();
+    let kproc = manager.get_proc(&KERNEL_PID).unwrap();
+    let page_table = kproc.read().clone_page_table();
+    let cpuid = processor::current_id();
+    let idle = Process::new(format!("idle{}", cpuid), Some(Arc::downgrade(&kproc)), page_table, None);
+    let pid = idle.pid();
+
+    let stack_top = idle.alloc_init_stack();
+    idle.init_stack_frame(entry, stack_top);
+    idle.write().set_affinity(1 << cpuid);
+    processor::get(cpuid).set_idle(pid);
+    manager.add_proc(pid, idle);
+    pid
+}
+
 pub fn get_process_manager() -> &'static ProcessManager {//获取进程管理器实例
     PROCESS_MANAGER
         .get()
         .expect("Process Manager has not been initialized")
 }
 
+/// Scheduling rounds between two load balancing on each CPU
+const BALANCE_INTERVAL: usize = 64;
+
 pub struct ProcessManager {//进程管理器结构体
     //由RwLock和Mutex提供内部可变性
     processes: RwLock<BTreeMap<ProcessId, Arc<Process>>>, //储存所有进程
-    ready_queue: Mutex<VecDeque<ProcessId>>,              //进程队列，存储pid
+    ready_queues: [Mutex<VecDeque<ProcessId>>; MAX_CPU_COUNT], //每个处理器的进程队列，存储pid
 }
 
 impl ProcessManager {
     pub fn new(init: Arc<Process>) -> Self {
         let mut processes = BTreeMap::new();
-        let ready_queue = VecDeque::new();
         let pid = init.pid();
 
         trace!("Init {:#?}", init);
@@ -50,13 +98,47 @@ impl ProcessManager {
         processes.insert(pid, init);//添加初始化进程到进程集合中
         Self {//返回值
             processes: RwLock::new(processes),//用RwLock返回
-            ready_queue: Mutex::new(ready_queue),//用Mutex返回
+            ready_queues: core::array::from_fn(|_| Mutex::new(VecDeque::new())),
         }
     }
 
-    #[inline]
+    /// Push a process to the run queue of a CPU it may run on,
+    /// and kick that CPU if it is idle
     pub fn push_ready(&self, pid: ProcessId) {//将pid加入队列
-        self.ready_queue.lock().push_back(pid);
+        let cpuid = match self.get_proc(&pid) {
+            Some(proc) => self.select_cpu(&proc.read()),
+            None => return,
+        };
+
+        self.ready_queues[cpuid].lock().push_back(pid);
+
+        if cpuid != processor::current_id() && processor::get(cpuid).is_idle() {
+            crate::interrupt::send_ipi(processor::apic_id(cpuid), Interrupts::Reschedule as u8);
+        }
+    }
+
+    /// Choose the run queue for a process
+    ///
+    /// stay on the last CPU for its cache unless it is clearly busier,
+    /// otherwise use the least loaded CPU allowed by the affinity.
+    fn select_cpu(&self, proc: &ProcessInner) -> usize {
+        let allowed = proc.affinity() & processor::online_mask();
+        if allowed == 0 {
+            return processor::current_id();
+        }
+
+        let load = |cpuid: usize| self.ready_queues[cpuid].lock().len();
+        let least = (0..MAX_CPU_COUNT)
+            .filter(|cpuid| allowed & (1 << cpuid) != 0)
+            .min_by_key(|&cpuid| load(cpuid))
+            .unwrap();
+
+        let last = proc.last_cpu();
+        if allowed & (1 << last) != 0 && load(last) <= load(least) + 1 {
+            last
+        } else {
+            least
+        }
     }
 
     #[inline]
@@ -65,49 +147,165 @@ impl ProcessManager {
     }
 
     #[inline]
-    fn get_proc(&self, pid: &ProcessId) -> Option<Arc<Process>> {//获取指定pid的进程
+    pub fn get_proc(&self, pid: &ProcessId) -> Option<Arc<Process>> {//获取指定pid的进程
         self.processes.read().get(pid).cloned()
     }
 
+    /// Processes that are not dead, sorted by pid
+    pub fn processes(&self) -> Vec<Arc<Process>> {
+        self.processes
+            .read()
+            .values()
+            .filter(|p| p.read().status() != ProgramStatus::Dead)
+            .cloned()
+            .collect()
+    }
+
     pub fn current(&self) -> Arc<Process> {//获取当前进程
         self.get_proc(&processor::get_pid())
             .expect("No current process")
     }
 
-    pub fn save_current(&self, context: &ProcessContext) {//保存当前处理器正在执行的进程，加入队列中
-        let process_manager = get_process_manager();
-        let current_pid = processor::get_pid();
-        let current_process = process_manager.current();
+    pub fn save_current(&self, context: &ProcessContext) {//保存当前处理器正在执行的进程
+        let current_process = self.current();
+        let mut current = current_process.write();
         // FIXME: update current process's tick count
-        current_process.write().tick();//记录进程的调度次数
+        current.tick();//记录进程的调度次数
         // FIXME: update current process's context
-        //疑惑：为什么要传入一个参数修改上下文
-        // FIXME: push current process to ready queue if still alive
-        if current_process.read().status() != ProgramStatus::Dead {
-            current_process.write().save(context);//保存当前进程上下文
+        if current.status() != ProgramStatus::Dead {
+            current.save(context);//保存当前进程上下文
         }
-        process_manager.push_ready(current_pid);//将当前进程加入进程队列
+        // the current process is pushed to ready queue in `switch_next`,
+        // after this CPU has left it, so that no other CPU resumes it too early
     }
 
     pub fn switch_next(&self, context: &mut ProcessContext) -> ProcessId {//从队列中取出一个进程，加载到处理器中
-        // FIXME: fetch the next process from ready queue
-        // FIXME: check if the next process is ready,
-        //        continue to fetch if not ready
-        // FIXME: restore next process's context
-        // FIXME: update processor's current pid
-        let mut ready_queue = self.ready_queue.lock();//锁Mutex
-        while let Some(next_pid) = ready_queue.pop_front(){//循环条件:队列非空
-            if let Some(next_process) = self.get_proc(&next_pid){
-                let mut next_process_inner = next_process.write();//锁RwLock
-                if next_process_inner.status() == ProgramStatus::Ready{//进程状态为Ready
-                    processor::set_pid(next_pid);  // 更新处理器的当前进程
-                    //疑惑：为什么要传入一个参数修改上下文
-                    next_process.write().restore(context);//恢复下一个进程的上下文
-                    return next_pid; 
+        let cpuid = processor::current_id();
+        let current_pid = processor::get_pid();
+        let idle_pid = processor::get(cpuid).get_idle();
+
+        if processor::get(cpuid).round() % BALANCE_INTERVAL == 0 {
+            self.balance(cpuid);
+        }
+
+        let current = self.current();
+        let next = match self.pop_ready(cpuid).or_else(|| self.steal(cpuid)) {
+            Some(next) => next,
+            None if current.read().is_ready() => {
+                // nothing else to run, keep running the current process
+                current.write().restore(context);
+                return current_pid;
+            }
+            // the current process is blocked, wait for interrupts in the idle process
+            None => idle_pid
+                .and_then(|pid| self.get_proc(&pid))
+                .expect("No idle process"),
+        };
+
+        next.write().restore(context);//恢复下一个进程的上下文
+
+        // locked across leaving the CPU, so that `wake` queues it exactly once
+        let inner = current.write();
+        processor::set_pid(next.pid());// 更新处理器的当前进程
+
+        // the idle process never waits in run queues
+        let requeue = inner.is_ready() && Some(current_pid) != idle_pid;
+        drop(inner);
+        if requeue {
+            self.push_ready(current_pid);//将当前进程加入进程队列
+        }
+        next.pid()
+    }
+
+    /// Pop the next ready process from the run queue of `cpuid`
+    fn pop_ready(&self, cpuid: usize) -> Option<Arc<Process>> {
+        let mut migrate = Vec::new();
+        let mut next = None;
+
+        {
+            let mut queue = self.ready_queues[cpuid].lock();
+            while let Some(pid) = queue.pop_front() {
+                let proc = match self.get_proc(&pid) {
+                    Some(proc) => proc,
+                    None => continue,
+                };
+
+                let inner = proc.read();
+                if !inner.is_ready() {
+                    continue;
                 }
-            } 
+                if !inner.can_run_on(cpuid) {
+                    // the affinity has changed since it was queued
+                    migrate.push(pid);
+                    continue;
+                }
+
+                drop(inner);
+                next = Some(proc);
+                break;
+            }
+        }
+
+        for pid in migrate {
+            self.push_ready(pid);
+        }
+        next
+    }
+
+    /// Steal a process from the tail of the busiest run queue
+    fn steal(&self, cpuid: usize) -> Option<Arc<Process>> {
+        let busiest = self.busiest(cpuid)?;
+        let mut queue = self.ready_queues[busiest].lock();
+
+        let idx = queue.iter().rposition(|pid| {
+            self.get_proc(pid).map_or(false, |proc| {
+                let inner = proc.read();
+                inner.is_ready() && inner.can_run_on(cpuid)
+            })
+        })?;
+
+        let pid = queue.remove(idx)?;
+        trace!("CPU {} stole process #{} from CPU {}", cpuid, pid, busiest);
+        self.get_proc(&pid)
+    }
+
+    /// Pull processes from the busiest run queue until both are even
+    fn balance(&self, cpuid: usize) {
+        let busiest = match self.busiest(cpuid) {
+            Some(busiest) => busiest,
+            None => return,
+        };
+
+        let local = self.ready_queues[cpuid].lock().len();
+        let remote = self.ready_queues[busiest].lock().len();
+        for _ in 0..remote.saturating_sub(local) / 2 {
+            match self.steal(cpuid) {
+                Some(proc) => self.ready_queues[cpuid].lock().push_back(proc.pid()),
+                None => break,
+            }
+        }
+    }
+
+    /// The other online CPU with the longest run queue, if it is not empty
+    fn busiest(&self, cpuid: usize) -> Option<usize> {
+        let online = processor::online_mask();
+        (0..MAX_CPU_COUNT)
+            .filter(|&other| other != cpuid && online & (1 << other) != 0)
+            .map(|other| (other, self.ready_queues[other].lock().len()))
+            .filter(|&(_, len)| len > 0)
+            .max_by_key(|&(_, len)| len)
+            .map(|(other, _)| other)
+    }
+
+    /// Set the CPUs a process may run on, it migrates on its next schedule
+    pub fn set_affinity(&self, pid: ProcessId, mask: usize) -> bool {
+        match self.get_proc(&pid) {
+            Some(proc) if mask & processor::ALL_CPUS != 0 => {
+                proc.write().set_affinity(mask);
+                true
+            }
+            _ => false,
         }
-        KERNEL_PID
     }
 
     //创建一个新的内核线程
@@ -119,7 +317,9 @@ impl ProcessManager {
     ) -> ProcessId {
         let kproc = self.get_proc(&KERNEL_PID).unwrap();
         let page_table = kproc.read().clone_page_table();
-        let proc = Process::new(name, Some(Arc::downgrade(&kproc)), page_table, proc_data);
+        // inherit the descriptors of the spawning process
+        let proc_data = proc_data.unwrap_or_else(|| self.current().read().inherit());
+        let proc = Process::new(name, Some(Arc::downgrade(&kproc)), page_table, Some(proc_data));
         let pid = proc.pid();
         // alloc stack for the new process base on pid
         let stack_top = proc.alloc_init_stack();//分配初始栈，返回栈顶地址
@@ -134,6 +334,34 @@ impl ProcessManager {
         pid
         //KERNEL_PID
     }
+    /// Mark the current process as blocked, it is not scheduled again until woken up
+    pub fn block_current(&self) -> ProcessId {
+        let current = self.current();
+        current.write().block();
+        current.pid()
+    }
+
+    /// Make a blocked process ready again
+    pub fn wake(&self, pid: ProcessId) {
+        let proc = match self.get_proc(&pid) {
+            Some(proc) => proc,
+            None => return,
+        };
+
+        let mut inner = proc.write();
+        if inner.status() != ProgramStatus::Blocked {
+            return;
+        }
+        inner.pause();
+
+        // a process still being switched out is queued by its CPU in `switch_next`
+        let on_cpu = (0..MAX_CPU_COUNT).any(|cpuid| processor::get(cpuid).get_pid() == Some(pid));
+        drop(inner);
+        if !on_cpu {
+            self.push_ready(pid);
+        }
+    }
+
     pub fn get_exit_code(&self,pid:ProcessId) -> Option<isize>{//获取进程的返回值
         if let Some(proc) = self.get_proc(&pid){
             //疑惑：进程退出的判断条件是？
@@ -152,12 +380,37 @@ impl ProcessManager {
     }
     //处理页面错误
     pub fn handle_page_fault(&self, addr: VirtAddr, err_code: PageFaultErrorCode) -> bool {
-        // FIXME: handle page fault
         //在 ProcessManager 中，检查缺页异常是否包含越权访问或其他非预期的错误码。
         //如果缺页异常是由于非预期异常导致的，或者缺页异常的地址不在当前进程的栈空间中，直接返回 false。
-        //如果缺页异常的地址在当前进程的栈空间中，把缺页异常的处理委托给当前的进程。你可能需要为 ProcessInner 添加用于分配新的栈、更新进程存储信息的函数。
+        //如果缺页异常的地址在当前进程的栈空间中，把缺页异常的处理委托给当前的进程。
+        if err_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            return false;
+        }
+
+        // the fault may be raised while the process or the frame allocator is
+        // locked, spinning here would never return, so give up instead
+        let Some(proc) = self
+            .processes
+            .try_read()
+            .and_then(|procs| procs.get(&processor::get_pid()).cloned())
+        else {
+            return false;
+        };
+        let Some(mut inner) = proc.try_write() else {
+            return false;
+        };
+        if !inner.is_on_stack(addr) {
+            return false;
+        }
+
         //在进程的缺页异常处理函数中：分配新的页面、更新页表、更新进程数据中的栈信息。
-        false
+        if let Err(e) = inner.grow_stack(addr) {
+            drop(inner);
+            warn!("Failed to grow stack of process #{}: {:?}", proc.pid(), e);
+            return false;
+        }
+
+        true
     }
     //杀死指定pid的进程
     pub fn kill(&self, pid: ProcessId, ret: isize) {
@@ -191,7 +444,11 @@ impl ProcessManager {
 
         // TODO: print memory usage of kernel heap
 
-        output += format!("Queue  : {:?}\n", self.ready_queue.lock()).as_str();
+        for (cpuid, queue) in self.ready_queues.iter().enumerate() {
+            if processor::online_mask() & (1 << cpuid) != 0 {
+                output += format!("Queue {} : {:?}\n", cpuid, queue.lock()).as_str();
+            }
+        }
 
         output += &processor::print_processors();
 
@@ -1,5 +1,7 @@
 mod context;
 mod data;
+pub mod fd;
+mod pipe;
 pub mod manager;
 mod paging;
 mod pid;
@@ -9,12 +11,15 @@ pub mod processor;
 
 use manager::*;
 use process::*;
+use crate::fs::{self, FsResult, OpenFlags, SeekFrom};
 use crate::memory::PAGE_SIZE;
 
 use alloc::string::String;
 pub use context::ProcessContext;
 pub use paging::PageTableContext;
 pub use data::ProcessData;
+pub use fd::{FdTable, Resource};
+pub use pipe::{PipeReader, PipeWriter, PIPE_SIZE};
 pub use pid::ProcessId;
 
 use x86_64::structures::idt::PageFaultErrorCode;
@@ -53,11 +58,19 @@ pub enum ProgramStatus {
 }
 
 /// init process manager
-pub fn init() {
+pub fn init(boot_info: &'static boot::BootInfo) {
     let mut kproc_data = ProcessData::new();//内核栈
 
-    // FIXME: set the kernel stack
-    kproc_data.set_stack(VirtAddr::new(KSTACK_INIT_BOT),KSTACK_DEF_SIZE);
+    // the kernel stack is placed by the bootloader, and may be randomized
+    // with auto grow, only the top pages are mapped, the rest is mapped on page fault
+    let stack_pages = if boot_info.kernel_stack_auto_grow > 0 {
+        boot_info.kernel_stack_auto_grow.min(boot_info.kernel_stack_size)
+    } else {
+        boot_info.kernel_stack_size
+    };
+    let stack_top = boot_info.kernel_stack_address + boot_info.kernel_stack_size * PAGE_SIZE;
+    kproc_data.set_stack(VirtAddr::new(stack_top - stack_pages * PAGE_SIZE), stack_pages);
+    kproc_data.set_stack_max(boot_info.kernel_stack_size);
     // 是否需要kproc_data.set_env()
     //kproc_data.set_env();
     trace!("Init process data: {:#?}", kproc_data);
@@ -76,6 +89,35 @@ pub fn init() {
 
     info!("Process Manager Initialized.");
 }
+/// init the idle process of an application processor
+pub fn init_ap(cpuid: u32) -> ProcessId {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        manager::init_ap(alloc::format!("idle{}", cpuid))
+    })
+}
+
+/// init the idle process of the bootstrap processor, once frames can be allocated
+pub fn init_idle() -> ProcessId {
+    let entry: fn() -> ! = idle;
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        manager::init_bsp_idle(VirtAddr::new(entry as usize as u64))
+    })
+}
+
+/// Body of the idle process of the bootstrap processor
+fn idle() -> ! {
+    loop {
+        x86_64::instructions::interrupts::enable_and_hlt();
+    }
+}
+
+/// Set the CPUs a process may run on, as a mask of CPU ids
+pub fn set_affinity(pid: ProcessId, mask: usize) -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().set_affinity(pid, mask)
+    })
+}
+
 //切换到下一个进程
 pub fn switch(context: &mut ProcessContext) {//参数是当前处理器的上下文
     x86_64::instructions::interrupts::without_interrupts(|| {//确保在关闭中断的状态下继续执行
@@ -105,6 +147,103 @@ pub fn env(key: &str) -> Option<String> {
         get_process_manager().current().read().env(key)
     })
 }
+/// Data for a process spawned by the current one, with its inheritable descriptors
+pub fn inherit_data() -> ProcessData {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().current().read().inherit()
+    })
+}
+
+/// Run `f` on the descriptor table of the current process
+fn with_fd_table<T>(f: impl FnOnce(&mut FdTable) -> T) -> T {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        f(get_process_manager().current().write().fd_table_mut())
+    })
+}
+
+/// The resource behind `fd` of the current process,
+/// used without holding the process lock as I/O may block.
+pub fn fd_resource(fd: usize) -> FsResult<alloc::sync::Arc<Resource>> {
+    with_fd_table(|table| table.get(fd))
+}
+
+/// Open `path` for the current process, returns the new descriptor
+pub fn open(path: &str, flags: OpenFlags) -> FsResult<usize> {
+    let file = fs::open(path, flags)?;
+    with_fd_table(|table| table.insert(alloc::sync::Arc::new(Resource::File(file)), false))
+}
+
+pub fn read(fd: usize, buf: &mut [u8]) -> FsResult<usize> {
+    fd_resource(fd)?.read(buf)
+}
+
+pub fn write(fd: usize, buf: &[u8]) -> FsResult<usize> {
+    fd_resource(fd)?.write(buf)
+}
+
+pub fn seek(fd: usize, pos: SeekFrom) -> FsResult<u64> {
+    fd_resource(fd)?.seek(pos)
+}
+
+pub fn close(fd: usize) -> FsResult {
+    // dropped after the table is unlocked
+    let _resource = with_fd_table(|table| table.close(fd))?;
+    Ok(())
+}
+
+pub fn dup(fd: usize) -> FsResult<usize> {
+    with_fd_table(|table| table.dup(fd))
+}
+
+/// Make `new` refer to what `old` does, closing it first
+pub fn dup2(old: usize, new: usize) -> FsResult<usize> {
+    let _replaced = with_fd_table(|table| table.dup2(old, new))?;
+    Ok(new)
+}
+
+pub fn set_cloexec(fd: usize, cloexec: bool) -> FsResult {
+    with_fd_table(|table| table.set_cloexec(fd, cloexec))
+}
+
+/// Create a pipe in the current process, returns its read and write descriptors,
+/// which are inherited by spawned processes unless set close-on-exec.
+pub fn pipe() -> FsResult<(usize, usize)> {
+    let (reader, writer) = pipe::pipe();
+    with_fd_table(|table| {
+        let read_fd = table.insert(alloc::sync::Arc::new(Resource::PipeRead(reader)), false)?;
+        match table.insert(alloc::sync::Arc::new(Resource::PipeWrite(writer)), false) {
+            Ok(write_fd) => Ok((read_fd, write_fd)),
+            Err(err) => {
+                // the read end is dropped with the table still locked, it only wakes writers
+                table.close(read_fd)?;
+                Err(err)
+            }
+        }
+    })
+}
+
+/// Give up the CPU, a blocked process does not run again until woken up
+pub fn yield_now() {
+    unsafe {
+        core::arch::asm!("int {vector}", vector = const crate::interrupt::consts::Interrupts::Yield as u8);
+    }
+}
+
+/// Mark the current process as blocked, it does not run again
+/// after the next [`yield_now`] until woken up
+pub fn block_current() -> ProcessId {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().block_current()
+    })
+}
+
+/// Make a process blocked on some event ready again
+pub fn wake(pid: ProcessId) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().wake(pid);
+    })
+}
+
 //退出当前进程
 pub fn process_exit(ret: isize) -> ! {
     x86_64::instructions::interrupts::without_interrupts(|| {
@@ -0,0 +1,262 @@
+//! Anonymous pipes
+//!
+//! A pipe is a bounded ring buffer with a read end and a write end. Reading
+//! an empty pipe or writing a full one blocks the process until the other
+//! end makes progress. Once every write end is dropped, reads return 0 at
+//! the end of the data, once every read end is dropped, writes fail.
+
+use super::*;
+use crate::fs::{FsError, FsResult};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+#[cfg(not(test))]
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// Host tests run in user mode, where interrupts can not be disabled
+#[cfg(test)]
+fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    f()
+}
+
+/// Capacity of the buffer of a pipe
+pub const PIPE_SIZE: usize = 4096;
+
+struct PipeInner {
+    buf: Box<[u8; PIPE_SIZE]>,
+    /// Index of the first byte to read
+    head: usize,
+    len: usize,
+    readers: usize,
+    writers: usize,
+    /// Processes waiting for data
+    read_waiters: Vec<ProcessId>,
+    /// Processes waiting for space
+    write_waiters: Vec<ProcessId>,
+}
+
+impl PipeInner {
+    fn pop(&mut self, out: &mut [u8]) -> usize {
+        let count = out.len().min(self.len);
+        for byte in out[..count].iter_mut() {
+            *byte = self.buf[self.head];
+            self.head = (self.head + 1) % PIPE_SIZE;
+        }
+        self.len -= count;
+        count
+    }
+
+    fn push(&mut self, data: &[u8]) -> usize {
+        let count = data.len().min(PIPE_SIZE - self.len);
+        for &byte in &data[..count] {
+            self.buf[(self.head + self.len) % PIPE_SIZE] = byte;
+            self.len += 1;
+        }
+        count
+    }
+}
+
+struct Pipe {
+    inner: Mutex<PipeInner>,
+}
+
+/// Wake up every process in `waiters`
+fn wake_all(waiters: Vec<ProcessId>) {
+    if waiters.is_empty() {
+        return;
+    }
+    without_interrupts(|| {
+        let manager = get_process_manager();
+        for pid in waiters {
+            manager.wake(pid);
+        }
+    })
+}
+
+impl Pipe {
+    /// Run `f` with the pipe locked, until it returns a result,
+    /// `f` returns `None` after registering the process as a waiter.
+    fn wait_for<T>(&self, mut f: impl FnMut(&mut PipeInner) -> Option<T>) -> T {
+        loop {
+            let result = without_interrupts(|| {
+                let mut inner = self.inner.lock();
+                f(&mut inner)
+            });
+
+            match result {
+                Some(result) => return result,
+                None => yield_now(),
+            }
+        }
+    }
+}
+
+pub struct PipeReader(Arc<Pipe>);
+
+pub struct PipeWriter(Arc<Pipe>);
+
+/// Create a pipe, returns its read and write ends
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe {
+        inner: Mutex::new(PipeInner {
+            buf: Box::new([0; PIPE_SIZE]),
+            head: 0,
+            len: 0,
+            readers: 1,
+            writers: 1,
+            read_waiters: Vec::new(),
+            write_waiters: Vec::new(),
+        }),
+    });
+    (PipeReader(pipe.clone()), PipeWriter(pipe))
+}
+
+impl PipeReader {
+    /// Read what is in the pipe, blocking while it is empty,
+    /// returns 0 when it is empty and has no writer left.
+    pub fn read(&self, buf: &mut [u8]) -> FsResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let (count, waiters) = self.0.wait_for(|inner| {
+            if inner.len > 0 {
+                let count = inner.pop(buf);
+                Some((count, core::mem::take(&mut inner.write_waiters)))
+            } else if inner.writers == 0 {
+                Some((0, Vec::new()))
+            } else {
+                // blocked before unlocking, so a wake up in between is not lost
+                let pid = get_process_manager().block_current();
+                if !inner.read_waiters.contains(&pid) {
+                    inner.read_waiters.push(pid);
+                }
+                None
+            }
+        });
+
+        wake_all(waiters);
+        Ok(count)
+    }
+}
+
+impl PipeWriter {
+    /// Write all of `buf`, blocking while the pipe is full,
+    /// fails with `BrokenPipe` if there is no reader left.
+    pub fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        let mut written = 0;
+
+        while written < buf.len() {
+            let (result, waiters) = self.0.wait_for(|inner| {
+                if inner.readers == 0 {
+                    Some((Err(FsError::BrokenPipe), Vec::new()))
+                } else if inner.len < PIPE_SIZE {
+                    let count = inner.push(&buf[written..]);
+                    Some((Ok(count), core::mem::take(&mut inner.read_waiters)))
+                } else {
+                    let pid = get_process_manager().block_current();
+                    if !inner.write_waiters.contains(&pid) {
+                        inner.write_waiters.push(pid);
+                    }
+                    None
+                }
+            });
+
+            wake_all(waiters);
+            match result {
+                Ok(count) => written += count,
+                Err(err) if written == 0 => return Err(err),
+                Err(_) => break,
+            }
+        }
+
+        Ok(written)
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let waiters = without_interrupts(|| {
+            let mut inner = self.0.inner.lock();
+            inner.readers -= 1;
+            core::mem::take(&mut inner.write_waiters)
+        });
+        // writers see the pipe is broken
+        wake_all(waiters);
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        let waiters = without_interrupts(|| {
+            let mut inner = self.0.inner.lock();
+            inner.writers -= 1;
+            core::mem::take(&mut inner.read_waiters)
+        });
+        // readers see the end of the data
+        wake_all(waiters);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(start: usize, len: usize) -> Vec<u8> {
+        (start..start + len).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Read exactly `len` bytes, the pipe must hold them
+    fn read_exact(reader: &PipeReader, len: usize) -> Vec<u8> {
+        let mut buf = alloc::vec![0; len];
+        assert_eq!(reader.read(&mut buf), Ok(len));
+        buf
+    }
+
+    #[test]
+    fn ring_wrap_around() {
+        let (reader, writer) = pipe();
+        assert_eq!(writer.write(&pattern(0, 3000)), Ok(3000));
+        assert_eq!(read_exact(&reader, 2000), pattern(0, 2000));
+
+        // fills the pipe, the data continues at the start of the buffer
+        assert_eq!(writer.write(&pattern(3000, PIPE_SIZE - 1000)), Ok(PIPE_SIZE - 1000));
+        assert_eq!(reader.0.inner.lock().len, PIPE_SIZE);
+        assert_eq!(read_exact(&reader, 1500), pattern(2000, 1500));
+        assert_eq!(writer.write(&pattern(2000 + PIPE_SIZE, 1500)), Ok(1500));
+
+        // a short buffer takes part of the data, a long one what there is
+        assert_eq!(read_exact(&reader, 10), pattern(3500, 10));
+        let mut buf = alloc::vec![0; 2 * PIPE_SIZE];
+        assert_eq!(reader.read(&mut buf), Ok(PIPE_SIZE - 10));
+        assert_eq!(buf[..PIPE_SIZE - 10], pattern(3510, PIPE_SIZE - 10));
+        assert_eq!(reader.read(&mut []), Ok(0));
+    }
+
+    #[test]
+    fn end_of_data() {
+        let (reader, writer) = pipe();
+        writer.write(b"last words").unwrap();
+        drop(writer);
+
+        // what was written before closing is still read
+        let mut buf = [0; 4];
+        assert_eq!(reader.read(&mut buf), Ok(4));
+        assert_eq!(read_exact(&reader, 6), b" words");
+        assert_eq!(reader.read(&mut buf), Ok(0));
+        assert_eq!(reader.read(&mut buf), Ok(0));
+    }
+
+    #[test]
+    fn broken_pipe() {
+        let (reader, writer) = pipe();
+        writer.write(b"unread").unwrap();
+        drop(reader);
+
+        assert_eq!(writer.write(b"more"), Err(FsError::BrokenPipe));
+        assert_eq!(writer.write(&[]), Ok(0));
+        let inner = writer.0.inner.lock();
+        assert_eq!((inner.readers, inner.writers, inner.len), (0, 1, 6));
+    }
+}
@@ -27,6 +27,10 @@ pub struct ProcessInner {
     context: ProcessContext,
     page_table: Option<PageTableContext>,
     proc_data: Option<ProcessData>,
+    /// Mask of the CPUs this process may run on
+    affinity: usize,
+    /// The CPU this process last ran on
+    last_cpu: usize,
 }
 
 impl Process {
@@ -66,6 +70,8 @@ impl Process {
             children: Vec::new(),
             page_table: Some(page_table),
             proc_data: Some(proc_data.unwrap_or_default()),
+            affinity: processor::ALL_CPUS,
+            last_cpu: processor::current_id(),
         };
 
         trace!("New process {}#{} created.", &inner.name, pid);
@@ -87,16 +93,20 @@ impl Process {
             ret
         );
         inner.kill(ret);
+
+        // files are synced when dropped, not while holding the lock
+        let files = inner.fd_table_mut().take_all();
+        drop(inner);
+        drop(files);
     }
     // 跟你爆了^^'（流汗黄豆
     pub fn alloc_init_stack(&self) -> VirtAddr {//分配初始栈空间,返回虚拟地址的栈顶地址
         // FIXME: alloc init stack base on self pid
         // 参考bootloader中为内核分配栈空间的代码
         // 根据内存布局预设和当前进程的 PID，为其分配初始栈空间。
-        let process_inner = self.write();
         let process_pid = self.pid.0;//进程id
         // 克隆内核页表
-        let kernel_page_table = process_inner.clone_page_table();
+        let kernel_page_table = self.read().clone_page_table();
         self.write().page_table = Some(kernel_page_table);
         // 使用elf::map_range()函数来进行页面映射
         let frame_alloctor = &mut *get_frame_alloc_for_sure();
@@ -136,6 +146,22 @@ impl ProcessInner {
         &self.name
     }
 
+    #[inline]
+    pub fn ticks(&self) -> usize {//获取进程调度次数
+        self.ticks_passed
+    }
+
+    #[inline]
+    pub fn children(&self) -> &[Arc<Process>] {
+        &self.children
+    }
+
+    /// Data of the process, None once it is killed
+    #[inline]
+    pub fn data(&self) -> Option<&ProcessData> {
+        self.proc_data.as_ref()
+    }
+
     pub fn tick(&mut self) {//增加进程调度次数
         self.ticks_passed += 1;
     }
@@ -152,6 +178,10 @@ impl ProcessInner {
         self.status = ProgramStatus::Running;
     }
 
+    pub fn block(&mut self) {//将进程设置为Blocked
+        self.status = ProgramStatus::Blocked;
+    }
+
     pub fn exit_code(&self) -> Option<isize> {//获取进程退出的代码
         self.exit_code
     }
@@ -164,11 +194,35 @@ impl ProcessInner {
         self.status == ProgramStatus::Ready
     }
 
+    #[inline]
+    pub fn affinity(&self) -> usize {
+        self.affinity
+    }
+
+    #[inline]
+    pub fn set_affinity(&mut self, mask: usize) {
+        self.affinity = mask & processor::ALL_CPUS;
+    }
+
+    /// If this process may run on the given CPU
+    #[inline]
+    pub fn can_run_on(&self, cpuid: usize) -> bool {
+        self.affinity & (1 << cpuid) != 0
+    }
+
+    #[inline]
+    pub fn last_cpu(&self) -> usize {
+        self.last_cpu
+    }
+
     /// Save the process's context
     /// mark the process as ready
     pub(super) fn save(&mut self, context: &ProcessContext) {//保存进程的上下文
         // FIXME: save the process's context
-        self.pause();
+        // a blocked process stays blocked until it is woken up
+        if self.status != ProgramStatus::Blocked {
+            self.pause();
+        }
         self.context = *context;
     }
 
@@ -177,6 +231,7 @@ impl ProcessInner {
     pub(super) fn restore(&mut self, context: &mut ProcessContext) {//恢复进程的上下文
         // FIXME: restore the process's context
         self.resume();
+        self.last_cpu = processor::current_id();
         *context = self.context;//修改传入的可变参数context
         // FIXME: restore the process's page table
         if let Some(page_table) = &self.page_table{
@@ -184,6 +239,33 @@ impl ProcessInner {
         }
     }
 
+    /// Grow the stack down to the page containing `addr`,
+    /// fails if the frame allocator is locked
+    pub fn grow_stack(&mut self, addr: VirtAddr) -> Result<(), MapToError<Size4KiB>> {
+        let stack = self.stack_segment.expect("Process has no stack");
+        let new_start = Page::containing_address(addr);
+        let count = stack.start - new_start;
+
+        trace!(
+            "Grow stack of {}: {:#x} -> {:#x} ({} pages)",
+            self.name,
+            stack.start.start_address().as_u64(),
+            new_start.start_address().as_u64(),
+            count
+        );
+
+        let mut page_table = self.page_table.as_ref().unwrap().mapper();
+        elf::map_range(
+            new_start.start_address().as_u64(),
+            count,
+            &mut page_table,
+            &mut *get_frame_alloc().ok_or(MapToError::FrameAllocationFailed)?,
+        )?;
+
+        self.stack_segment = Some(Page::range(new_start, stack.end));
+        Ok(())
+    }
+
     pub fn parent(&self) -> Option<Arc<Process>> {//获取进程的父进程
         self.parent.as_ref().and_then(|p| p.upgrade())
     }
@@ -1,28 +1,127 @@
 //cpu核心结构体
-use core::sync::atomic::{AtomicU16, Ordering};
+use core::sync::atomic::{AtomicU16, AtomicU32, AtomicUsize, Ordering};
 
 use crate::proc::ProcessId;
 use alloc::{string::String, vec::Vec};
 use x86::cpuid::CpuId;
+use x86_64::registers::model_specific::GsBase;
+use x86_64::VirtAddr;
 
-const MAX_CPU_COUNT: usize = 4;
+pub const MAX_CPU_COUNT: usize = 16;
 
 #[allow(clippy::declare_interior_mutable_const)]
 const EMPTY: Processor = Processor::new(); // means no process
 
+/// Mask of every CPU the kernel supports
+pub const ALL_CPUS: usize = (1 << MAX_CPU_COUNT) - 1;
+
 static PROCESSORS: [Processor; MAX_CPU_COUNT] = [EMPTY; MAX_CPU_COUNT];
 
-/// Returns the current processor based on the current APIC ID
+/// Mask of the CPUs that are running
+static ONLINE_CPUS: AtomicUsize = AtomicUsize::new(0);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_APIC: AtomicU32 = AtomicU32::new(0);
+
+/// APIC ID of each CPU, indexed by CPU id
+static APIC_IDS: [AtomicU32; MAX_CPU_COUNT] = [NO_APIC; MAX_CPU_COUNT];
+
+/// Number of CPUs given an id
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// The id of each CPU, the GS base of a CPU points to its own
+static CPU_IDS: [usize; MAX_CPU_COUNT] = {
+    let mut ids = [0; MAX_CPU_COUNT];
+    let mut id = 0;
+    while id < MAX_CPU_COUNT {
+        ids[id] = id;
+        id += 1;
+    }
+    ids
+};
+
+/// Give the bootstrap processor id 0, after its GDT is loaded
+///
+/// prefer the 32-bit x2APIC ID, the initial APIC ID only has 8 bits
+pub fn init_bsp() -> usize {
+    let cpuid = CpuId::new();
+    let apic_id = cpuid
+        .get_extended_topology_info()
+        .and_then(|mut levels| levels.next())
+        .map(|level| level.x2apic_id())
+        .unwrap_or_else(|| cpuid.get_feature_info().unwrap().initial_local_apic_id() as u32);
+    register(apic_id)
+}
+
+/// Give the current processor the next CPU id, after its GDT is loaded,
+/// as loading GS clears the GS base
+///
+/// CPU ids are dense, APIC IDs may not be.
+pub fn register(apic_id: u32) -> usize {
+    let id = CPU_COUNT.fetch_add(1, Ordering::AcqRel);
+    assert!(id < MAX_CPU_COUNT, "CPU {} exceeds MAX_CPU_COUNT", apic_id);
+
+    APIC_IDS[id].store(apic_id, Ordering::Release);
+    GsBase::write(VirtAddr::from_ptr(&CPU_IDS[id]));
+    id
+}
+
+/// Number of CPUs given an id, online or starting
+#[inline]
+pub fn count() -> usize {
+    CPU_COUNT.load(Ordering::Acquire)
+}
+
+/// Returns the current processor's id, read through its GS base
+#[inline]
+pub fn current_id() -> usize {
+    let id: usize;
+    unsafe {
+        core::arch::asm!(
+            "mov {}, gs:[0]",
+            out(reg) id,
+            options(nostack, readonly, preserves_flags)
+        );
+    }
+    id
+}
+
+/// Returns the APIC ID of the processor with the given id
+#[inline]
+pub fn apic_id(cpuid: usize) -> u32 {
+    APIC_IDS[cpuid].load(Ordering::Acquire)
+}
+
+/// Returns the current processor based on the current CPU id
 /// 返回当前处理器的引用
 fn current() -> &'static Processor {
-    let cpuid = CpuId::new()
-        .get_feature_info()
-        .unwrap()
-        .initial_local_apic_id() as usize;
+    &PROCESSORS[current_id()]
+}
 
+/// Returns the processor with the given id
+#[inline]
+pub fn get(cpuid: usize) -> &'static Processor {
     &PROCESSORS[cpuid]
 }
 
+/// Mark the current processor as running
+#[inline]
+pub fn set_online() {
+    ONLINE_CPUS.fetch_or(1 << current_id(), Ordering::AcqRel);
+}
+
+/// Mask of the running processors
+#[inline]
+pub fn online_mask() -> usize {
+    ONLINE_CPUS.load(Ordering::Acquire)
+}
+
+/// Number of the running processors
+#[inline]
+pub fn online_count() -> usize {
+    online_mask().count_ones() as usize
+}
+
 pub fn print_processors() -> String {//打印所有处理器及进程id
     alloc::format!(
         "CPUs   : {}\n",
@@ -37,11 +136,21 @@ pub fn print_processors() -> String {//打印所有处理器及进程id
 }
 
 /// Processor holds the current process id
-pub struct Processor(AtomicU16);
+pub struct Processor {
+    pid: AtomicU16,
+    /// The process to run when nothing is ready, 0 if none
+    idle: AtomicU16,
+    /// Scheduling rounds on this processor, for periodic load balancing
+    rounds: AtomicUsize,
+}
 
 impl Processor {
     pub const fn new() -> Self {
-        Self(AtomicU16::new(0))
+        Self {
+            pid: AtomicU16::new(0),
+            idle: AtomicU16::new(0),
+            rounds: AtomicUsize::new(0),
+        }
     }
 }
 
@@ -58,21 +167,49 @@ pub fn get_pid() -> ProcessId {//获取当前处理器运行的pid
 impl Processor {
     #[inline]
     pub fn is_free(&self) -> bool {//检查处理器是否空闲
-        self.0.load(Ordering::Relaxed) == 0
+        self.pid.load(Ordering::Relaxed) == 0
     }
 
     #[inline]
     pub fn set_pid(&self, pid: ProcessId) {//设置处理器上运行的进程id
-        self.0.store(pid.0, Ordering::Relaxed);
+        self.pid.store(pid.0, Ordering::Relaxed);
     }
 
     #[inline]
     pub fn get_pid(&self) -> Option<ProcessId> {//获取正在运行的pid
-        let pid = self.0.load(Ordering::Relaxed);
+        let pid = self.pid.load(Ordering::Relaxed);
+        if pid == 0 {
+            None
+        } else {
+            Some(ProcessId(pid))
+        }
+    }
+
+    #[inline]
+    pub fn set_idle(&self, pid: ProcessId) {
+        self.idle.store(pid.0, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn get_idle(&self) -> Option<ProcessId> {
+        let pid = self.idle.load(Ordering::Relaxed);
         if pid == 0 {
             None
         } else {
             Some(ProcessId(pid))
         }
     }
+
+    /// If the processor is running its idle process
+    #[inline]
+    pub fn is_idle(&self) -> bool {
+        let idle = self.idle.load(Ordering::Relaxed);
+        idle != 0 && idle == self.pid.load(Ordering::Relaxed)
+    }
+
+    /// Count a scheduling round, return the rounds before it
+    #[inline]
+    pub fn round(&self) -> usize {
+        self.rounds.fetch_add(1, Ordering::Relaxed)
+    }
 }
@@ -0,0 +1,144 @@
+//! SMP (Symmetric Multiprocessing)
+//!
+//! Start the application processors (APs) listed in the ACPI MADT
+//! with INIT-SIPI-SIPI. Each AP sets up its own GDT, TSS and local APIC,
+//! then runs an idle process and takes part in scheduling.
+//!
+//! Reference: [OSDev Wiki](https://wiki.osdev.org/SMP)
+
+mod trampoline;
+
+use crate::acpi::get_acpi;
+use crate::interrupt::{self, apic::LocalApic, local_apic};
+use crate::memory::{gdt, PAGE_SIZE};
+use crate::proc::{self, processor::{self, MAX_CPU_COUNT}};
+use alloc::boxed::Box;
+use alloc::vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use trampoline::Trampoline;
+use x86_64::instructions::port::Port;
+
+/// Size of the stack each AP starts with, which is also its idle stack
+const AP_STACK_SIZE: usize = 4 * PAGE_SIZE as usize;
+
+/// Frequency of the ACPI PM timer
+const PM_TIMER_FREQUENCY: u64 = 3_579_545;
+
+// Interrupt Command Register fields
+const ICR_INIT: u32 = 0b101 << 8;
+const ICR_STARTUP: u32 = 0b110 << 8;
+const ICR_ASSERT: u32 = 1 << 14;
+
+/// Set by an AP once it has left the trampoline
+static AP_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Start all application processors
+pub fn init(boot_info: &'static boot::BootInfo) {
+    if boot_info.ap_trampoline == 0 {
+        warn!("No AP trampoline from bootloader, SMP disabled.");
+        return;
+    }
+
+    let trampoline = match unsafe { Trampoline::install(boot_info.ap_trampoline) } {
+        Some(trampoline) => trampoline,
+        None => {
+            warn!("Failed to install AP trampoline, SMP disabled.");
+            return;
+        }
+    };
+
+    let bsp = local_apic().id();
+    for cpu in get_acpi().madt.processors.iter() {
+        if !cpu.enabled || cpu.apic_id == bsp {
+            continue;
+        }
+
+        // ids are given in the order the CPUs start, failed ones take none
+        if processor::count() >= MAX_CPU_COUNT {
+            warn!("CPU {} exceeds MAX_CPU_COUNT, skipped.", cpu.apic_id);
+            continue;
+        }
+
+        start_ap(&trampoline, cpu.apic_id);
+    }
+
+    info!("SMP Initialized, {} CPU(s) online.", processor::online_count());
+}
+
+/// Start an AP with INIT-SIPI-SIPI and wait for it to leave the trampoline
+fn start_ap(trampoline: &Trampoline, apic_id: u32) {
+    let stack = Box::leak(vec![0u8; AP_STACK_SIZE].into_boxed_slice());
+    let stack_top = (stack.as_ptr() as u64 + AP_STACK_SIZE as u64) & !0xf;
+
+    AP_STARTED.store(false, Ordering::Release);
+    trampoline.set_entry(stack_top, ap_main, apic_id as u64);
+
+    let mut lapic = local_apic();
+
+    lapic.send_ipi(apic_id, ICR_INIT | ICR_ASSERT);
+    udelay(10_000);
+
+    for _ in 0..2 {
+        lapic.send_ipi(apic_id, ICR_STARTUP | ICR_ASSERT | trampoline.vector() as u32);
+        udelay(200);
+        if AP_STARTED.load(Ordering::Acquire) {
+            break;
+        }
+    }
+
+    // give the AP up to 100ms to start
+    for _ in 0..1000 {
+        if AP_STARTED.load(Ordering::Acquire) {
+            return;
+        }
+        udelay(100);
+    }
+
+    warn!("CPU {} failed to start.", apic_id);
+}
+
+/// Entry of APs from the trampoline
+extern "C" fn ap_main(apic_id: u64) -> ! {
+    // the trampoline data is no longer needed
+    AP_STARTED.store(true, Ordering::Release);
+
+    gdt::init_ap();
+    let cpuid = processor::register(apic_id as u32);
+    interrupt::init_ap();
+    let pid = proc::init_ap(cpuid as u32);
+
+    info!("CPU {} (APIC {}) online, idle process #{}.", cpuid, apic_id, pid);
+
+    x86_64::instructions::interrupts::enable();
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Busy wait for `us` microseconds, using the ACPI PM timer if present
+fn udelay(us: u64) {
+    let fadt = get_acpi().fadt.filter(|fadt| fadt.pm_timer_port != 0);
+
+    let fadt = match fadt {
+        Some(fadt) => fadt,
+        None => {
+            for _ in 0..us * 1000 {
+                core::hint::spin_loop();
+            }
+            return;
+        }
+    };
+
+    let mask = if fadt.pm_timer_32bit {
+        u32::MAX
+    } else {
+        0xff_ffff
+    };
+    let ticks = us * PM_TIMER_FREQUENCY / 1_000_000;
+
+    let mut port = Port::<u32>::new(fadt.pm_timer_port as u16);
+    let start = unsafe { port.read() };
+    while (unsafe { port.read() }.wrapping_sub(start) & mask) as u64 <= ticks {
+        core::hint::spin_loop();
+    }
+}
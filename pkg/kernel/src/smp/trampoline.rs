@@ -0,0 +1,190 @@
+//! Real mode trampoline for application processors
+//!
+//! The code is copied to a page below 1MiB, and the AP starts executing it
+//! in real mode at `vector << 12`. It switches directly into long mode
+//! with the BSP's page table, then calls the kernel entry on its own stack.
+
+use crate::memory::physical_to_virtual;
+use crate::proc::PageTableContext;
+use core::arch::global_asm;
+use core::mem::size_of;
+use x86_64::registers::control::{Cr0, Cr3, Cr4, Cr4Flags};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::Translate;
+use x86_64::VirtAddr;
+
+global_asm!(
+    r#"
+.pushsection .text.ap_trampoline, "ax"
+.global ap_trampoline_start
+.global ap_trampoline_target
+.global ap_long_mode
+.global ap_gdt
+.global ap_gdt_ptr
+.global ap_trampoline_data
+.global ap_trampoline_end
+
+.code16
+ap_trampoline_start:
+    cli
+    cld
+    mov ax, cs
+    mov ds, ax
+
+    lgdt [AP_GDT_PTR]
+
+    mov eax, dword ptr [AP_DATA]
+    mov cr4, eax
+    mov eax, dword ptr [AP_DATA + 4]
+    mov cr3, eax
+    mov ecx, 0xC0000080
+    mov eax, dword ptr [AP_DATA + 8]
+    xor edx, edx
+    wrmsr
+    mov eax, dword ptr [AP_DATA + 12]
+    mov cr0, eax
+
+    # jmp far dword 0x08:target, the target is filled by the kernel
+    .byte 0x66, 0xea
+ap_trampoline_target:
+    .long 0
+    .word 0x08
+
+.code64
+ap_long_mode:
+    mov ax, 0x10
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+    xor ax, ax
+    mov fs, ax
+    mov gs, ax
+
+    mov rsp, qword ptr [rip + ap_trampoline_data + 16]
+    mov rax, qword ptr [rip + ap_trampoline_data + 24]
+    mov rdi, qword ptr [rip + ap_trampoline_data + 32]
+    xor rbp, rbp
+    call rax
+2:
+    hlt
+    jmp 2b
+
+.balign 8
+ap_gdt:
+    .quad 0
+    .quad 0x00af9a000000ffff
+    .quad 0x00cf92000000ffff
+ap_gdt_ptr:
+    .word ap_gdt_ptr - ap_gdt - 1
+    .long 0
+
+.balign 8
+ap_trampoline_data:
+    .space 40
+ap_trampoline_end:
+
+# offsets from the start, as real mode addresses are relative to cs
+.set AP_GDT_PTR, ap_gdt_ptr - ap_trampoline_start
+.set AP_DATA, ap_trampoline_data - ap_trampoline_start
+.popsection
+"#
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_target: u8;
+    static ap_long_mode: u8;
+    static ap_gdt: u8;
+    static ap_gdt_ptr: u8;
+    static ap_trampoline_data: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// Layout of `ap_trampoline_data`
+#[repr(C)]
+struct TrampolineData {
+    cr4: u32,
+    cr3: u32,
+    efer: u32,
+    cr0: u32,
+    stack_top: u64,
+    entry: u64,
+    arg: u64,
+}
+
+/// Offset of a trampoline symbol from its start
+#[inline]
+fn symbol_offset(symbol: &u8) -> u64 {
+    symbol as *const u8 as u64 - unsafe { &ap_trampoline_start as *const u8 as u64 }
+}
+
+pub struct Trampoline {
+    /// Physical address of the installed trampoline
+    addr: u64,
+}
+
+impl Trampoline {
+    /// Copy the trampoline to the page at physical address `addr`
+    ///
+    /// the page must be below 1MiB and identity mapped,
+    /// and the kernel page table must be below 4GiB.
+    pub unsafe fn install(addr: u64) -> Option<Self> {
+        let (frame, _) = Cr3::read();
+        let cr3 = frame.start_address().as_u64();
+        if cr3 > u32::MAX as u64 {
+            warn!("Kernel page table at {:#x} is not reachable from real mode.", cr3);
+            return None;
+        }
+
+        let mapper = PageTableContext::new().mapper();
+        if mapper.translate_addr(VirtAddr::new(addr)).map(|a| a.as_u64()) != Some(addr) {
+            warn!("AP trampoline at {:#x} is not identity mapped.", addr);
+            return None;
+        }
+
+        let size = symbol_offset(&ap_trampoline_end) as usize;
+        let dst = physical_to_virtual(addr);
+        core::ptr::copy_nonoverlapping(&ap_trampoline_start as *const u8, dst as *mut u8, size);
+
+        // patch the absolute addresses
+        let target = dst + symbol_offset(&ap_trampoline_target);
+        (target as *mut u32).write_unaligned((addr + symbol_offset(&ap_long_mode)) as u32);
+        let gdt_base = dst + symbol_offset(&ap_gdt_ptr) + 2;
+        (gdt_base as *mut u32).write_unaligned((addr + symbol_offset(&ap_gdt)) as u32);
+
+        let data = (dst + symbol_offset(&ap_trampoline_data)) as *mut TrampolineData;
+        debug_assert!(symbol_offset(&ap_trampoline_data) as usize + size_of::<TrampolineData>() <= size);
+        data.write(TrampolineData {
+            // PCID can not be enabled before long mode is active
+            cr4: (Cr4::read() - Cr4Flags::PCID).bits() as u32,
+            cr3: cr3 as u32,
+            efer: (Efer::read() & (EferFlags::SYSTEM_CALL_EXTENSIONS
+                | EferFlags::LONG_MODE_ENABLE
+                | EferFlags::NO_EXECUTE_ENABLE))
+                .bits() as u32,
+            cr0: Cr0::read().bits() as u32,
+            stack_top: 0,
+            entry: 0,
+            arg: 0,
+        });
+
+        Some(Self { addr })
+    }
+
+    /// The startup IPI vector of this trampoline
+    #[inline]
+    pub fn vector(&self) -> u8 {
+        (self.addr >> 12) as u8
+    }
+
+    /// Set the stack and entry for the next AP to start
+    pub fn set_entry(&self, stack_top: u64, entry: extern "C" fn(u64) -> !, arg: u64) {
+        let data = physical_to_virtual(self.addr + symbol_offset(unsafe { &ap_trampoline_data }))
+            as *mut TrampolineData;
+        unsafe {
+            (*data).stack_top = stack_top;
+            (*data).entry = entry as usize as u64;
+            (*data).arg = arg;
+        }
+    }
+}
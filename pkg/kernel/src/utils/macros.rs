@@ -1,6 +1,10 @@
-use crate::drivers::serial::get_serial;
+use crate::drivers::console::{get_console_blocking, get_console_force};
+use crate::drivers::serial::{get_serial_blocking, get_serial_force};
 use core::fmt::*;
-use x86_64::instructions::interrupts;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Set once a panic starts, printing then takes the locks by force
+static PANICKING: AtomicBool = AtomicBool::new(false);
 
 /// Use spin mutex to control variable access
 #[macro_export]
@@ -23,6 +27,34 @@ macro_rules! guard_access_fn {
                     stringify!($mutex has not been initialized or lockable)
                 )
             }
+
+            /// Spin until the lock is free and call `f` with it, interrupts are
+            /// disabled while the lock is held, None if not initialized
+            $(#[$meta])*
+            #[inline(never)]
+            #[allow(non_snake_case, dead_code)]
+            $v fn [< $fn _blocking >]<R>(f: impl FnOnce(&mut $ty) -> R) -> Option<R> {
+                x86_64::instructions::interrupts::without_interrupts(|| {
+                    $mutex.get().map(|mutex| f(&mut mutex.lock()))
+                })
+            }
+
+            /// Call `f` with the lock, unlocking it by force if it is held,
+            /// only for a panic, as the holder may never release it
+            $(#[$meta])*
+            #[inline(never)]
+            #[allow(non_snake_case, dead_code)]
+            $v fn [< $fn _force >]<R>(f: impl FnOnce(&mut $ty) -> R) -> Option<R> {
+                $mutex.get().map(|mutex| {
+                    let mut guard = loop {
+                        if let Some(guard) = mutex.try_lock() {
+                            break guard;
+                        }
+                        unsafe { mutex.force_unlock() };
+                    };
+                    f(&mut guard)
+                })
+            }
         }
     };
 }
@@ -56,16 +88,22 @@ macro_rules! println {
 
 #[doc(hidden)]
 pub fn print_internal(args: Arguments) {
-    interrupts::without_interrupts(|| {
-        if let Some(mut serial) = get_serial() {
-            serial.write_fmt(args).unwrap();
-        }
-    });
+    // the panicking code may hold the locks itself, waiting would never end
+    if PANICKING.load(Ordering::Acquire) {
+        get_serial_force(|serial| serial.write_fmt(args).unwrap());
+        get_console_force(|console| console.write_fmt(args).unwrap());
+        return;
+    }
+
+    // other CPUs may be printing, wait for them instead of dropping the output
+    get_serial_blocking(|serial| serial.write_fmt(args).unwrap());
+    get_console_blocking(|console| console.write_fmt(args).unwrap());
 }
 
 #[allow(dead_code)]
 #[cfg_attr(not(test), panic_handler)]
 fn panic(info: &core::panic::PanicInfo) -> ! {
+    PANICKING.store(true, Ordering::Release);
     error!("ERROR: panic occurred!\n\n{:#?}", info);
     //如果location可用，则输出panic位置
     if let Some(location) = info.location(){
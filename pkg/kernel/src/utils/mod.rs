@@ -12,6 +12,24 @@ pub use regs::*;
 
 use crate::proc::*;
 use alloc:: format;
+
+static CMDLINE: spin::Once<boot::CmdLine> = spin::Once::new();
+
+/// Keep the command line of the boot entry, and list the apps loaded with it
+pub fn init_boot_args(boot_info: &'static boot::BootInfo) {
+    let cmdline = CMDLINE.call_once(|| boot_info.cmdline);
+    info!("Command line: {}", cmdline);
+
+    for app in boot_info.loaded_apps.iter() {
+        info!("App {} loaded at {:#x}, {} bytes", app.name, app.base, app.size);
+    }
+}
+
+/// The command line of the boot entry, empty before `init_boot_args`
+pub fn cmdline() -> &'static str {
+    CMDLINE.get().map_or("", |cmdline| cmdline.as_str())
+}
+
 pub const fn get_ascii_header() -> &'static str {
     concat!(
         r"
@@ -14,6 +14,8 @@ parser.add_argument('-i', '--intdbg', action='store_true',
                     help='Enable interrupt output for qemu')
 parser.add_argument('-m', '--memory', default='96M',
                     help='Set memory size for qemu, default is 96M')
+parser.add_argument('-s', '--smp', default='4',
+                    help='Set number of CPUs for qemu, default is 4')
 parser.add_argument('-o', '--output', default='-nographic',
                     help='Set output for qemu, default is -nographic')
 parser.add_argument('-p', '--profile', type=str, choices=['release', 'debug'],
@@ -29,7 +31,7 @@ parser.add_argument('--bios', type=str,
 parser.add_argument('--boot', type=str, default='esp', help='Set boot path')
 
 parser.add_argument('task', type=str, choices=[
-                    'build', 'clean', 'launch', 'run'
+                    'build', 'clean', 'launch', 'run', 'test'
                     ], default='build', help='Task to execute')
 
 args = parser.parse_args()
@@ -78,7 +80,7 @@ def execute_command(cmd: list, workdir: str = None, shell: bool = False) -> int:
     return prog.returncode
 
 
-def qemu(output: str = '-nographic', memory: str = '96M', debug: bool = False, intdbg: bool = False):
+def qemu(output: str = '-nographic', memory: str = '96M', smp: str = '4', debug: bool = False, intdbg: bool = False):
     qemu_exe = shutil.which('qemu-system-x86_64')
 
     # add optional path C:\Program Files\qemu for Windows
@@ -90,7 +92,7 @@ def qemu(output: str = '-nographic', memory: str = '96M', debug: bool = False, i
         raise Exception('qemu-system-x86_64 not found in PATH')
 
     qemu_args = [qemu_exe, '-bios', args.bios, '-net', 'none', *output.split(),
-                 '-m', memory, '-drive', 'format=raw,file=fat:rw:esp']
+                 '-m', memory, '-smp', smp, '-drive', 'format=raw,file=fat:rw:esp']
 
     if debug:
         qemu_args += ['-s', '-S']
@@ -120,6 +122,45 @@ def copy_to_esp(src: str, dst: str):
         raise Exception(f'{src} is not a file')
 
 
+def cpio_entry(path: str, mode: int, data: bytes, ino: int) -> bytes:
+    name = path.encode() + b'\0'
+    fields = [ino, mode, 0, 0, 1, 0, len(data), 0, 0, 0, 0, len(name), 0]
+    header = b'070701' + b''.join(f'{f:08x}'.encode() for f in fields)
+    # the header and name, then the data, are padded to 4 bytes
+    entry = header + name
+    entry += b'\0' * (-len(entry) % 4)
+    return entry + data + b'\0' * (-len(data) % 4)
+
+
+# 将文件打包成cpio(newc)格式的initramfs
+def make_initramfs(files: dict, dst: str):
+    dst = os.path.join(os.getcwd(), args.boot, dst)
+    debug('Packing', f'{len(files)} files -> {dst}')
+    if args.dry_run:
+        return
+
+    # directories are listed before the files in them
+    dirs = set()
+    for path in files:
+        parts = path.split('/')[:-1]
+        dirs.update('/'.join(parts[:i + 1]) for i in range(len(parts)))
+
+    archive = b''
+    ino = 1
+    for path in sorted(dirs):
+        archive += cpio_entry(path, 0o040755, b'', ino)
+        ino += 1
+    for path, src in sorted(files.items()):
+        with open(src, 'rb') as f:
+            archive += cpio_entry(path, 0o100755, f.read(), ino)
+        ino += 1
+    archive += cpio_entry('TRAILER!!!', 0, b'', 0)
+
+    os.makedirs(os.path.dirname(dst), exist_ok=True)
+    with open(dst, 'wb') as f:
+        f.write(archive)
+
+
 def build():
     cargo_exe = shutil.which('cargo')
 
@@ -154,6 +195,7 @@ def build():
 
     # build apps
     apps = get_apps()
+    initramfs = {}
     for app in apps:
         app_path = os.path.join(os.getcwd(), 'pkg', 'app', app)
 
@@ -168,6 +210,17 @@ def build():
         execute_command([cargo_exe, 'build', profile], app_path)
         compile_output = os.path.join(os.getcwd(), 'target', 'x86_64-unknown-ysos', profile_dir, app_name)
         copy_to_esp(compile_output, os.path.join('APP', app))
+        initramfs[f'bin/{app}'] = compile_output
+
+    # pack apps and pkg/kernel/rootfs into initramfs
+    rootfs = os.path.join(os.getcwd(), 'pkg', 'kernel', 'rootfs')
+    for root, _, names in os.walk(rootfs):
+        for name in names:
+            src = os.path.join(root, name)
+            initramfs[os.path.relpath(src, rootfs).replace(os.sep, '/')] = src
+
+    info('Packing', 'initramfs...')
+    make_initramfs(initramfs, 'INITRAMFS.CPIO')
 
 
 def clean():
@@ -182,16 +235,40 @@ def clean():
     execute_command([cargo_exe, 'clean'])
 
 
+def test():
+    cargo_exe = shutil.which('cargo')
+
+    if cargo_exe is None:
+        raise Exception('cargo not found in PATH')
+
+    # unit tests run on the host, not on the targets of the packages
+    rustc = subprocess.run(['rustc', '-vV'], capture_output=True, text=True, check=True)
+    host = next(line.split(': ')[1] for line in rustc.stdout.splitlines() if line.startswith('host: '))
+
+    packages = {
+        'elf': [],
+        # the UEFI services only build for the UEFI target
+        'boot': ['--no-default-features', '--lib'],
+        'kernel': ['--lib'],
+    }
+    for package, flags in packages.items():
+        info('Testing', f'{package}...')
+        execute_command([cargo_exe, 'test', '--target', host] + flags,
+                        os.path.join(os.getcwd(), 'pkg', package))
+
+
 def main():
     if args.task == 'build':
         build()
     elif args.task == 'clean':
         clean()
     elif args.task == 'launch':
-        qemu(args.output, args.memory, args.debug, args.intdbg)
+        qemu(args.output, args.memory, args.smp, args.debug, args.intdbg)
+    elif args.task == 'test':
+        test()
     elif args.task == 'run':
         build()
-        qemu(args.output, args.memory, args.debug, args.intdbg)
+        qemu(args.output, args.memory, args.smp, args.debug, args.intdbg)
 
 
 if __name__ == "__main__":